@@ -0,0 +1,225 @@
+// Generates the lookup textures SMAA needs into OUT_DIR, src/anti_aliasing.rs embeds
+// them with include_bytes!. Both follow the layout of the reference AreaTex.py and
+// SearchTex.py, without the diagonal and subpixel parts SMAA 1x doesn't use.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// distances to the ends of an edge are stored as sqrt(distance), so 16 texels reach 225
+const AREA_MAX_DISTANCE: usize = 16;
+// crossing edges at an end are read back as round(4 * e), which is 0, 1, 3 or 4
+const AREA_TILES: usize = 5;
+const AREA_SIZE: usize = AREA_MAX_DISTANCE * AREA_TILES;
+// U shaped edges shorter than this are rounded off instead of getting a sharp corner
+const SMOOTH_MAX_DISTANCE: f64 = 32.0;
+
+const SEARCH_WIDTH: usize = 66;
+const SEARCH_HEIGHT: usize = 33;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("smaa_area.bin"), area_texture()).unwrap();
+    fs::write(
+        Path::new(&out_dir).join("smaa_search.bin"),
+        search_texture(),
+    )
+    .unwrap();
+}
+
+// Rg8 texture of the coverage area for every pair of edge ends. Tile (v, w) holds the
+// ends v on the left and w on the right, texel (x, y) inside it distances of x² and y².
+fn area_texture() -> Vec<u8> {
+    let mut texels = vec![0u8; AREA_SIZE * AREA_SIZE * 2];
+    for left_end in [0, 1, 3, 4] {
+        for right_end in [0, 1, 3, 4] {
+            for y in 0..AREA_MAX_DISTANCE {
+                for x in 0..AREA_MAX_DISTANCE {
+                    let area = end_area(left_end, right_end, x * x, y * y);
+                    let column = left_end * AREA_MAX_DISTANCE + x;
+                    let row = right_end * AREA_MAX_DISTANCE + y;
+                    let index = (row * AREA_SIZE + column) * 2;
+                    texels[index] = to_unorm(area.0);
+                    texels[index + 1] = to_unorm(area.1);
+                }
+            }
+        }
+    }
+    texels
+}
+
+fn to_unorm(value: f64) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// where the line through an edge end starts, 1 is a crossing edge above the edge and
+// 3 one below it, 4 has both and is averaged over the two
+fn end_heights(end: usize) -> &'static [f64] {
+    match end {
+        1 => &[0.5],
+        3 => &[-0.5],
+        4 => &[0.5, -0.5],
+        _ => &[],
+    }
+}
+
+fn end_area(left_end: usize, right_end: usize, left: usize, right: usize) -> (f64, f64) {
+    let left_heights: Vec<Option<f64>> = match end_heights(left_end) {
+        [] => vec![None],
+        heights => heights.iter().copied().map(Some).collect(),
+    };
+    let right_heights: Vec<Option<f64>> = match end_heights(right_end) {
+        [] => vec![None],
+        heights => heights.iter().copied().map(Some).collect(),
+    };
+
+    let mut total = (0.0, 0.0);
+    for &left_height in &left_heights {
+        for &right_height in &right_heights {
+            let area = shape_area(left_height, right_height, left, right);
+            total.0 += area.0;
+            total.1 += area.1;
+        }
+    }
+    let count = (left_heights.len() * right_heights.len()) as f64;
+    (total.0 / count, total.1 / count)
+}
+
+// Coverage of the pixel `left` pixels from the left end of an edge `left + right + 1`
+// pixels long, for the revectorized line through its ends.
+fn shape_area(
+    left_height: Option<f64>,
+    right_height: Option<f64>,
+    left: usize,
+    right: usize,
+) -> (f64, f64) {
+    let d = (left + right + 1) as f64;
+    match (left_height, right_height) {
+        (None, None) => (0.0, 0.0),
+        // L shapes only cover the half of the edge closer to their end
+        (Some(height), None) if left <= right => area((0.0, height), (d / 2.0, 0.0), left),
+        (None, Some(height)) if left >= right => area((d / 2.0, 0.0), (d, height), left),
+        (Some(_), None) | (None, Some(_)) => (0.0, 0.0),
+        // U shape
+        (Some(left_height), Some(right_height)) if left_height == right_height => smooth_area(
+            d,
+            area((0.0, left_height), (d / 2.0, 0.0), left),
+            area((d / 2.0, 0.0), (d, right_height), left),
+        ),
+        // Z shape
+        (Some(left_height), Some(right_height)) => {
+            area((0.0, left_height), (d, right_height), left)
+        }
+    }
+}
+
+// Area between the line p1 -> p2 and the edge over pixel x. Area above the edge goes to
+// the second channel, below it to the first.
+fn area(p1: (f64, f64), p2: (f64, f64), x: usize) -> (f64, f64) {
+    let d = (p2.0 - p1.0, p2.1 - p1.1);
+    let x1 = x as f64;
+    let x2 = x1 + 1.0;
+    let y1 = p1.1 + d.1 * (x1 - p1.0) / d.0;
+    let y2 = p1.1 + d.1 * (x2 - p1.0) / d.0;
+
+    let inside = (x1 >= p1.0 && x1 < p2.0) || (x2 > p1.0 && x2 <= p2.0);
+    if !inside {
+        return (0.0, 0.0);
+    }
+
+    let trapezoid = y1.signum() == y2.signum() || y1.abs() < 1e-4 || y2.abs() < 1e-4;
+    if trapezoid {
+        let a = (y1 + y2) / 2.0;
+        return if a < 0.0 {
+            (a.abs(), 0.0)
+        } else {
+            (0.0, a.abs())
+        };
+    }
+
+    // the line crosses the edge inside the pixel, leaving a triangle on either side
+    let crossing = -p1.1 * d.0 / d.1 + p1.0;
+    let fraction = crossing.fract();
+    let a1 = if crossing > p1.0 {
+        y1 * fraction / 2.0
+    } else {
+        0.0
+    };
+    let a2 = if crossing < p2.0 {
+        y2 * (1.0 - fraction) / 2.0
+    } else {
+        0.0
+    };
+    let a = if a1.abs() > a2.abs() { a1 } else { -a2 };
+    if a < 0.0 {
+        (a1.abs(), a2.abs())
+    } else {
+        (a2.abs(), a1.abs())
+    }
+}
+
+fn smooth_area(d: f64, a1: (f64, f64), a2: (f64, f64)) -> (f64, f64) {
+    let b1 = ((a1.0 * 2.0).sqrt() * 0.5, (a1.1 * 2.0).sqrt() * 0.5);
+    let b2 = ((a2.0 * 2.0).sqrt() * 0.5, (a2.1 * 2.0).sqrt() * 0.5);
+    let p = (d / SMOOTH_MAX_DISTANCE).clamp(0.0, 1.0);
+    let lerp = |a: f64, b: f64| a + (b - a) * p;
+    (
+        lerp(b1.0, a1.0) + lerp(b2.0, a2.0),
+        lerp(b1.1, a1.1) + lerp(b2.1, a2.1),
+    )
+}
+
+// R8 texture of how far the edge search overshot, indexed by the bilinear fetch of the
+// edges it stopped on. The left half is for searches to the left or up, the right half
+// for searches to the right or down. Values are 127 per pixel.
+fn search_texture() -> Vec<u8> {
+    // the fetch sits between four pixels, weighting them 1, 3, 7 and 21 / 32:
+    //   e[0] e[1]
+    //   e[2] e[3] <- the pixel the search stopped at
+    let mut edges = [None; SEARCH_HEIGHT];
+    for combination in 0..16 {
+        let e = [
+            combination & 1,
+            (combination >> 1) & 1,
+            (combination >> 2) & 1,
+            (combination >> 3) & 1,
+        ];
+        edges[e[0] + 3 * e[1] + 7 * e[2] + 21 * e[3]] = Some(e);
+    }
+
+    let mut texels = vec![0u8; SEARCH_WIDTH * SEARCH_HEIGHT];
+    for (y, top) in edges.iter().enumerate() {
+        for (x, left) in edges.iter().enumerate() {
+            let (Some(left), Some(top)) = (left, top) else {
+                continue;
+            };
+            texels[y * SEARCH_WIDTH + x] = 127 * delta_left(left, top);
+            texels[y * SEARCH_WIDTH + x + SEARCH_WIDTH / 2] = 127 * delta_right(left, top);
+        }
+    }
+    texels
+}
+
+fn delta_left(left: &[usize; 4], top: &[usize; 4]) -> u8 {
+    let mut d = 0;
+    // the edge continues into the current pixel
+    if top[3] == 1 {
+        d += 1;
+    }
+    // and into the one before it without a crossing edge in between
+    if d == 1 && top[2] == 1 && left[1] != 1 && left[3] != 1 {
+        d += 1;
+    }
+    d
+}
+
+fn delta_right(left: &[usize; 4], top: &[usize; 4]) -> u8 {
+    let mut d = 0;
+    if top[3] == 1 && left[1] != 1 && left[3] != 1 {
+        d += 1;
+    }
+    if d == 1 && top[2] == 1 && left[0] != 1 && left[2] != 1 {
+        d += 1;
+    }
+    d
+}
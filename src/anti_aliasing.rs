@@ -0,0 +1,329 @@
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+
+use crate::post_process::{self, draw_fullscreen};
+use crate::sampler::{SamplerCache, SamplerDesc};
+use crate::texture::Texture;
+
+// SMAA's lookup textures, generated by build.rs
+const AREA_TEXTURE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/smaa_area.bin"));
+const AREA_TEXTURE_SIZE: u32 = 80;
+const SEARCH_TEXTURE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/smaa_search.bin"));
+const SEARCH_TEXTURE_WIDTH: u32 = 66;
+const SEARCH_TEXTURE_HEIGHT: u32 = 33;
+
+const EDGES_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg8Unorm;
+const WEIGHTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    Off,
+    Fxaa,
+    Smaa,
+}
+
+impl AntiAliasing {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Fxaa,
+            Self::Fxaa => Self::Smaa,
+            Self::Smaa => Self::Off,
+        }
+    }
+}
+
+// Anti-aliasing on the tonemapped image, which also catches the shader aliasing msaa
+// leaves alone. While a mode is on the tonemapper writes into target() and encode
+// resolves it to the surface, the mode can be changed at any time.
+pub struct AntiAliasingPass {
+    pub mode: AntiAliasing,
+    programs: Programs,
+    targets: Targets,
+}
+
+// what the passes draw with, independent of the screen size
+struct Programs {
+    sampler: Arc<wgpu::Sampler>,
+    fxaa_pipeline: wgpu::RenderPipeline,
+    edges_pipeline: wgpu::RenderPipeline,
+    weights_pipeline: wgpu::RenderPipeline,
+    blend_pipeline: wgpu::RenderPipeline,
+    area_texture: Texture,
+    search_texture: Texture,
+}
+
+// the screen sized textures and the bind groups reading them, recreated on resize
+struct Targets {
+    ldr_texture: Texture,
+    edges_texture: Texture,
+    weights_texture: Texture,
+    fxaa_bind_group: wgpu::BindGroup,
+    edges_bind_group: wgpu::BindGroup,
+    weights_bind_group: wgpu::BindGroup,
+    blend_bind_group: wgpu::BindGroup,
+}
+
+impl AntiAliasingPass {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        surface_config: &wgpu::SurfaceConfiguration,
+        mode: AntiAliasing,
+        samplers: &mut SamplerCache,
+    ) -> Self {
+        let format = surface_config.format;
+        let fxaa_shader =
+            post_process::fullscreen_shader(device, "Fxaa shader", include_str!("fxaa.wgsl"));
+        let fxaa_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &fxaa_shader,
+            "fs_main",
+            format.into(),
+            "Fxaa pipeline",
+        );
+        let smaa_shader =
+            post_process::fullscreen_shader(device, "Smaa shader", include_str!("smaa.wgsl"));
+        let edges_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &smaa_shader,
+            "fs_edges",
+            EDGES_FORMAT.into(),
+            "Smaa edge detection pipeline",
+        );
+        let weights_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &smaa_shader,
+            "fs_weights",
+            WEIGHTS_FORMAT.into(),
+            "Smaa blend weights pipeline",
+        );
+        let blend_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &smaa_shader,
+            "fs_blend",
+            format.into(),
+            "Smaa neighborhood blending pipeline",
+        );
+
+        let sampler = samplers.get(device, SamplerDesc::LINEAR_CLAMP);
+        let area_texture = create_lookup_texture(
+            device,
+            queue,
+            AREA_TEXTURE,
+            AREA_TEXTURE_SIZE,
+            AREA_TEXTURE_SIZE,
+            wgpu::TextureFormat::Rg8Unorm,
+            sampler.clone(),
+            "smaa_area_texture",
+        );
+        // the search lengths are exact per texel, filtering between them gives wrong ones
+        let search_texture = create_lookup_texture(
+            device,
+            queue,
+            SEARCH_TEXTURE,
+            SEARCH_TEXTURE_WIDTH,
+            SEARCH_TEXTURE_HEIGHT,
+            wgpu::TextureFormat::R8Unorm,
+            samplers.get(device, SamplerDesc::NEAREST_CLAMP),
+            "smaa_search_texture",
+        );
+
+        let programs = Programs {
+            sampler,
+            fxaa_pipeline,
+            edges_pipeline,
+            weights_pipeline,
+            blend_pipeline,
+            area_texture,
+            search_texture,
+        };
+        let targets = Targets::new(device, surface_config, &programs);
+        Self {
+            mode,
+            programs,
+            targets,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) {
+        self.targets = Targets::new(device, surface_config, &self.programs);
+    }
+
+    // where the final image has to go before encode, None when it can go straight to
+    // the surface
+    pub fn target(&self) -> Option<&Texture> {
+        match self.mode {
+            AntiAliasing::Off => None,
+            AntiAliasing::Fxaa | AntiAliasing::Smaa => Some(&self.targets.ldr_texture),
+        }
+    }
+
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let programs = &self.programs;
+        let targets = &self.targets;
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT);
+
+        match self.mode {
+            AntiAliasing::Off => {}
+            AntiAliasing::Fxaa => {
+                draw_fullscreen(
+                    encoder,
+                    &programs.fxaa_pipeline,
+                    &targets.fxaa_bind_group,
+                    output,
+                    clear,
+                    "Fxaa pass",
+                );
+            }
+            AntiAliasing::Smaa => {
+                // edge detection discards pixels without edges, the clear covers them
+                draw_fullscreen(
+                    encoder,
+                    &programs.edges_pipeline,
+                    &targets.edges_bind_group,
+                    &targets.edges_texture.view,
+                    clear,
+                    "Smaa edge detection pass",
+                );
+                draw_fullscreen(
+                    encoder,
+                    &programs.weights_pipeline,
+                    &targets.weights_bind_group,
+                    &targets.weights_texture.view,
+                    clear,
+                    "Smaa blend weights pass",
+                );
+                draw_fullscreen(
+                    encoder,
+                    &programs.blend_pipeline,
+                    &targets.blend_bind_group,
+                    output,
+                    clear,
+                    "Smaa neighborhood blending pass",
+                );
+            }
+        }
+    }
+}
+
+impl Targets {
+    fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        programs: &Programs,
+    ) -> Self {
+        let ldr_texture = Texture::create_render_target(
+            device,
+            surface_config,
+            surface_config.format,
+            "ldr_texture",
+        );
+        let edges_texture = Texture::create_render_target(
+            device,
+            surface_config,
+            EDGES_FORMAT,
+            "smaa_edges_texture",
+        );
+        let weights_texture = Texture::create_render_target(
+            device,
+            surface_config,
+            WEIGHTS_FORMAT,
+            "smaa_weights_texture",
+        );
+
+        let bind_group = |pipeline: &wgpu::RenderPipeline, entries: &[wgpu::BindGroupEntry]| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &pipeline.get_bind_group_layout(0),
+                entries,
+                label: Some("anti_aliasing_bind_group"),
+            })
+        };
+        let sampler_entry = || wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&programs.sampler),
+        };
+        let fxaa_bind_group = bind_group(
+            &programs.fxaa_pipeline,
+            &[texture_entry(0, &ldr_texture), sampler_entry()],
+        );
+        let edges_bind_group =
+            bind_group(&programs.edges_pipeline, &[texture_entry(0, &ldr_texture)]);
+        let weights_bind_group = bind_group(
+            &programs.weights_pipeline,
+            &[
+                sampler_entry(),
+                texture_entry(2, &edges_texture),
+                texture_entry(3, &programs.area_texture),
+                texture_entry(4, &programs.search_texture),
+            ],
+        );
+        let blend_bind_group = bind_group(
+            &programs.blend_pipeline,
+            &[
+                texture_entry(0, &ldr_texture),
+                sampler_entry(),
+                texture_entry(5, &weights_texture),
+            ],
+        );
+
+        Self {
+            ldr_texture,
+            edges_texture,
+            weights_texture,
+            fxaa_bind_group,
+            edges_bind_group,
+            weights_bind_group,
+            blend_bind_group,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_lookup_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    sampler: Arc<wgpu::Sampler>,
+    label: &str,
+) -> Texture {
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        data,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn texture_entry(binding: u32, texture: &Texture) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(&texture.view),
+    }
+}
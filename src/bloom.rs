@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use crate::post_process::{self, draw_fullscreen, EffectContext, EffectParam, PostEffect};
+use crate::texture::Texture;
+
+// levels below half resolution, the last one covers a 1/64th of the screen
+const MAX_MIP_COUNT: u32 = 6;
+
+#[derive(Copy, Clone, Debug)]
+pub struct BloomSettings {
+    // colors brighter than this glow, 0 lets the whole scene bloom a little
+    pub threshold: f32,
+    pub knee: f32,
+    pub intensity: f32,
+    pub filter_radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.2,
+            filter_radius: 0.005,
+        }
+    }
+}
+
+impl BloomSettings {
+    // in the order of BloomParams in bloom.wgsl
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("threshold", self.threshold),
+            EffectParam::new("knee", self.knee),
+            EffectParam::new("intensity", self.intensity),
+            EffectParam::new("filter_radius", self.filter_radius),
+        ]
+    }
+}
+
+// HDR bloom that downsamples the bright parts of the scene into a mip chain, blurs
+// them back up and adds the result on top of the scene.
+pub struct Bloom {
+    format: wgpu::TextureFormat,
+    params: Vec<EffectParam>,
+    params_buffer: wgpu::Buffer,
+    sampler: Arc<wgpu::Sampler>,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    mip_views: Vec<wgpu::TextureView>,
+    // downsample_bind_groups[i] reads mip i, upsample_bind_groups[i] reads mip i + 1
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    upsample_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Bloom {
+    pub const NAME: &'static str = "bloom";
+
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        settings: BloomSettings,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let shader =
+            post_process::fullscreen_shader(device, "Bloom shader", include_str!("bloom.wgsl"));
+        let pipeline = |entry_point, blend, label| {
+            let target = wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            };
+            post_process::fullscreen_pipeline(device, None, &shader, entry_point, target, label)
+        };
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let prefilter_pipeline = pipeline("fs_prefilter", None, "Bloom prefilter pipeline");
+        let downsample_pipeline = pipeline("fs_downsample", None, "Bloom downsample pipeline");
+        let upsample_pipeline = pipeline(
+            "fs_upsample",
+            Some(wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+            "Bloom upsample pipeline",
+        );
+        let composite_pipeline = pipeline("fs_composite", None, "Bloom composite pipeline");
+
+        let params = settings.params();
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom params buffer"),
+            size: (params.len() * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut bloom = Self {
+            format,
+            params,
+            params_buffer,
+            sampler,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+            mip_views: Vec::new(),
+            downsample_bind_groups: Vec::new(),
+            upsample_bind_groups: Vec::new(),
+        };
+        bloom.resize(device, surface_config.width, surface_config.height);
+        bloom
+    }
+
+    fn source_bind_group(
+        &self,
+        device: &wgpu::Device,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::TextureView,
+        with_params: bool,
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(source),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ];
+        if with_params {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2,
+                resource: self.params_buffer.as_entire_binding(),
+            });
+        }
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+            label: Some("bloom_bind_group"),
+        })
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn params(&self) -> &[EffectParam] {
+        &self.params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match self.params.iter_mut().find(|p| p.name == name) {
+            Some(param) => {
+                param.value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let size = wgpu::Extent3d {
+            width: (width / 2).max(1),
+            height: (height / 2).max(1),
+            depth_or_array_layers: 1,
+        };
+        let mip_count = size.max_mips(wgpu::TextureDimension::D2).min(MAX_MIP_COUNT);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Bloom mip chain"),
+            size,
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        self.mip_views = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom mip view"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let last = self.mip_views.len() - 1;
+        self.downsample_bind_groups = self.mip_views[..last]
+            .iter()
+            .map(|view| self.source_bind_group(device, &self.downsample_pipeline, view, false))
+            .collect();
+        self.upsample_bind_groups = self.mip_views[1..]
+            .iter()
+            .map(|view| self.source_bind_group(device, &self.upsample_pipeline, view, true))
+            .collect();
+    }
+
+    fn run(&mut self, context: &mut EffectContext, input: &Texture, output: &Texture) {
+        let mut values = self.params.iter().map(|p| p.value).collect::<Vec<_>>();
+        values.resize(4, 0.0);
+        context
+            .queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&values));
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let prefilter_bind_group =
+            self.source_bind_group(context.device, &self.prefilter_pipeline, &input.view, true);
+        draw_fullscreen(
+            context.encoder,
+            &self.prefilter_pipeline,
+            &prefilter_bind_group,
+            &self.mip_views[0],
+            clear,
+            "Bloom prefilter pass",
+        );
+        for (bind_group, target) in self.downsample_bind_groups.iter().zip(&self.mip_views[1..]) {
+            draw_fullscreen(
+                context.encoder,
+                &self.downsample_pipeline,
+                bind_group,
+                target,
+                clear,
+                "Bloom downsample pass",
+            );
+        }
+        // smallest first, each level adds the blurred level below onto itself
+        for (bind_group, target) in self.upsample_bind_groups.iter().zip(&self.mip_views).rev() {
+            draw_fullscreen(
+                context.encoder,
+                &self.upsample_pipeline,
+                bind_group,
+                target,
+                wgpu::LoadOp::Load,
+                "Bloom upsample pass",
+            );
+        }
+
+        let composite_bind_group = context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.composite_pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&input.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&self.mip_views[0]),
+                    },
+                ],
+                label: Some("bloom_composite_bind_group"),
+            });
+        draw_fullscreen(
+            context.encoder,
+            &self.composite_pipeline,
+            &composite_bind_group,
+            &output.view,
+            clear,
+            "Bloom composite pass",
+        );
+    }
+}
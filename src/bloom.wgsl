@@ -0,0 +1,126 @@
+// Downsample and upsample filters from Jimenez's "Next Generation Post Processing in
+// Call of Duty: Advanced Warfare". Appended to fullscreen.wgsl for vs_main.
+
+struct BloomParams {
+    threshold: f32,
+    // width of the soft transition below the threshold
+    knee: f32,
+    intensity: f32,
+    // upsample tent size in uv units of the target
+    filter_radius: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: BloomParams;
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// weights bright samples down so single pixel highlights don't flicker
+fn karis_weight(color: vec3<f32>) -> f32 {
+    return 1.0 / (1.0 + luminance(color));
+}
+
+fn sample_source(uv: vec2<f32>, offset: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    return textureSampleLevel(t_source, s_source, uv + offset * texel, 0.0).rgb;
+}
+
+// the 13 taps, overlapping 2x2 boxes around the center
+struct Taps {
+    a: vec3<f32>, b: vec3<f32>, c: vec3<f32>,
+    d: vec3<f32>, e: vec3<f32>, f: vec3<f32>,
+    g: vec3<f32>, h: vec3<f32>, i: vec3<f32>,
+    j: vec3<f32>, k: vec3<f32>, l: vec3<f32>, m: vec3<f32>,
+};
+
+fn gather_taps(uv: vec2<f32>) -> Taps {
+    var taps: Taps;
+    taps.a = sample_source(uv, vec2<f32>(-2.0, -2.0));
+    taps.b = sample_source(uv, vec2<f32>(0.0, -2.0));
+    taps.c = sample_source(uv, vec2<f32>(2.0, -2.0));
+    taps.d = sample_source(uv, vec2<f32>(-2.0, 0.0));
+    taps.e = sample_source(uv, vec2<f32>(0.0, 0.0));
+    taps.f = sample_source(uv, vec2<f32>(2.0, 0.0));
+    taps.g = sample_source(uv, vec2<f32>(-2.0, 2.0));
+    taps.h = sample_source(uv, vec2<f32>(0.0, 2.0));
+    taps.i = sample_source(uv, vec2<f32>(2.0, 2.0));
+    taps.j = sample_source(uv, vec2<f32>(-1.0, -1.0));
+    taps.k = sample_source(uv, vec2<f32>(1.0, -1.0));
+    taps.l = sample_source(uv, vec2<f32>(-1.0, 1.0));
+    taps.m = sample_source(uv, vec2<f32>(1.0, 1.0));
+    return taps;
+}
+
+// soft threshold, fully passes colors a knee above the threshold
+fn prefilter(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 0.0001);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.0001);
+    return color * contribution;
+}
+
+// first downsample from the scene, thresholded and karis averaged per box
+@fragment
+fn fs_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let t = gather_taps(in.tex_coords);
+    var boxes = array<vec3<f32>, 5>(
+        (t.j + t.k + t.l + t.m) * 0.25,
+        (t.a + t.b + t.d + t.e) * 0.25,
+        (t.b + t.c + t.e + t.f) * 0.25,
+        (t.d + t.e + t.g + t.h) * 0.25,
+        (t.e + t.f + t.h + t.i) * 0.25,
+    );
+    var box_weights = array<f32, 5>(0.5, 0.125, 0.125, 0.125, 0.125);
+
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var n = 0; n < 5; n++) {
+        let weight = box_weights[n] * karis_weight(boxes[n]);
+        color += boxes[n] * weight;
+        total_weight += weight;
+    }
+    return vec4<f32>(prefilter(color / total_weight), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let t = gather_taps(in.tex_coords);
+    var color = t.e * 0.125;
+    color += (t.a + t.c + t.g + t.i) * 0.03125;
+    color += (t.b + t.d + t.f + t.h) * 0.0625;
+    color += (t.j + t.k + t.l + t.m) * 0.125;
+    return vec4<f32>(color, 1.0);
+}
+
+// 3x3 tent, blended additively onto the level above
+@fragment
+fn fs_upsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let r = bloom.filter_radius;
+    let uv = in.tex_coords;
+    var color = textureSampleLevel(t_source, s_source, uv, 0.0).rgb * 4.0;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(-r, 0.0), 0.0).rgb * 2.0;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(r, 0.0), 0.0).rgb * 2.0;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(0.0, -r), 0.0).rgb * 2.0;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(0.0, r), 0.0).rgb * 2.0;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(-r, -r), 0.0).rgb;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(r, -r), 0.0).rgb;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(-r, r), 0.0).rgb;
+    color += textureSampleLevel(t_source, s_source, uv + vec2<f32>(r, r), 0.0).rgb;
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let scene = textureSampleLevel(t_source, s_source, in.tex_coords, 0.0);
+    let glow = textureSampleLevel(t_bloom, s_source, in.tex_coords, 0.0).rgb;
+    return vec4<f32>(scene.rgb + glow * bloom.intensity, scene.a);
+}
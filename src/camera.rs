@@ -1,4 +1,4 @@
-use nalgebra::{Matrix4, Point3, Vector3};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
@@ -21,6 +21,8 @@ pub struct Camera {
     pub fovy: f32,
     pub znear: f32,
     pub zfar: f32,
+    // sub-pixel offset of the projection in ndc, changed every frame for taa
+    pub jitter: Vector2<f32>,
 }
 
 impl Camera {
@@ -34,6 +36,7 @@ impl Camera {
             fovy: 45.0,
             znear: 0.01,
             zfar: 100.0,
+            jitter: Vector2::zeros(),
         }
     }
     pub fn get_camera_forward(&self) -> Vector3<f32> {
@@ -44,18 +47,44 @@ impl Camera {
             -1.0 * self.pitch.cos() * self.yaw.cos(),
         )
     }
-    pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
-        let view = nalgebra::Matrix4::look_at_rh(
+    pub fn build_view_matrix(&self) -> Matrix4<f32> {
+        nalgebra::Matrix4::look_at_rh(
             &self.position,
             &(self.position + self.get_camera_forward()),
             &self.up,
-        );
+        )
+    }
+    // maps view space depth to wgpu's 0 to 1 range
+    pub fn build_projection_matrix(&self) -> Matrix4<f32> {
         let proj = nalgebra::Perspective3::new(self.aspect, self.fovy, self.znear, self.zfar);
-        return OPENGL_TO_WGPU_MATRIX * proj.as_matrix() * view;
+        OPENGL_TO_WGPU_MATRIX * proj.as_matrix()
+    }
+    // shifts clip space by jitter * w so every depth moves by the same amount in ndc
+    pub fn build_jittered_projection_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&Vector3::new(self.jitter.x, self.jitter.y, 0.0))
+            * self.build_projection_matrix()
+    }
+    pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
+        self.build_projection_matrix() * self.build_view_matrix()
     }
     pub fn get_uniform(&self) -> CameraUniform {
+        let view = self.build_view_matrix();
+        let proj = self.build_jittered_projection_matrix();
+        let view_proj = proj * view;
+        let unjittered_view_proj = self.build_projection_matrix() * view;
         CameraUniform {
-            view_proj: self.build_view_projection_matrix().into(),
+            view_proj: view_proj.into(),
+            inv_view_proj: view_proj
+                .try_inverse()
+                .unwrap_or_else(Matrix4::identity)
+                .into(),
+            position: self.position.to_homogeneous().into(),
+            view: view.into(),
+            proj: proj.into(),
+            inv_proj: proj.try_inverse().unwrap_or_else(Matrix4::identity).into(),
+            unjittered_view_proj: unjittered_view_proj.into(),
+            // no motion until the renderer fills in last frame's
+            prev_view_proj: unjittered_view_proj.into(),
         }
     }
 }
@@ -64,6 +93,17 @@ impl Camera {
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     pub view_proj: [[f32; 4]; 4],
+    // takes clip space back to world space, for passes that rebuild view rays
+    pub inv_view_proj: [[f32; 4]; 4],
+    // w is 1, vec4 keeps the uniform layout free of padding rules
+    pub position: [f32; 4],
+    // view space matrices for screen space passes
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+    pub inv_proj: [[f32; 4]; 4],
+    // motion vectors compare these two, without jitter so it doesn't show up as motion
+    pub unjittered_view_proj: [[f32; 4]; 4],
+    pub prev_view_proj: [[f32; 4]; 4],
 }
 
 #[derive(Debug)]
@@ -0,0 +1,41 @@
+// Clustered lighting definitions shared by the light assignment compute pass, the
+// scene shaders and the heatmap. The view frustum is cut into grid_size tiles on
+// screen and exponentially spaced slices in depth, each cluster lists the lights whose
+// range reaches into it.
+
+// must match MAX_LIGHTS_PER_CLUSTER in lights.rs
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+
+// A point light, or a spot light when spot_scale isn't 0. The cone falls off as
+// dot(-l, direction) * spot_scale + spot_offset goes from 1 to 0.
+struct Light {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    spot_scale: f32,
+    direction: vec3<f32>,
+    spot_offset: f32,
+};
+
+struct ClusterParams {
+    grid_size: vec3<u32>,
+    light_count: u32,
+    screen_size: vec2<f32>,
+    // view space distances the slices are spread between
+    near: f32,
+    far: f32,
+};
+
+// view distance where slice starts, slices get longer further away so clusters stay
+// roughly cube shaped
+fn slice_depth(params: ClusterParams, slice: f32) -> f32 {
+    return params.near * pow(params.far / params.near, slice / f32(params.grid_size.z));
+}
+
+// frag_xy is in pixels, view_z the positive distance along the view axis
+fn cluster_index(params: ClusterParams, frag_xy: vec2<f32>, view_z: f32) -> u32 {
+    let tile = vec2<u32>(max(frag_xy / params.screen_size * vec2<f32>(params.grid_size.xy), vec2<f32>(0.0)));
+    let slice = u32(max(log(view_z / params.near) * f32(params.grid_size.z) / log(params.far / params.near), 0.0));
+    let cluster = min(vec3<u32>(tile, slice), params.grid_size - 1u);
+    return cluster.x + cluster.y * params.grid_size.x + cluster.z * params.grid_size.x * params.grid_size.y;
+}
@@ -0,0 +1,71 @@
+// Light assignment, appended to cluster.wgsl. One invocation per cluster builds the
+// cluster's view space bounds and lists every light whose range sphere touches them.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> params: ClusterParams;
+@group(0) @binding(2)
+var<storage, read> lights: array<Light>;
+@group(0) @binding(3)
+var<storage, read_write> cluster_light_counts: array<u32>;
+@group(0) @binding(4)
+var<storage, read_write> cluster_light_indices: array<u32>;
+
+// the view space point where the ray through uv meets the plane view_z away
+fn view_point(uv: vec2<f32>, view_z: f32) -> vec3<f32> {
+    let clip = camera.inv_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    let ray = clip.xyz / clip.w;
+    return ray * (view_z / -ray.z);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn assign_lights(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id >= params.grid_size) {
+        return;
+    }
+
+    let uv_min = vec2<f32>(id.xy) / vec2<f32>(params.grid_size.xy);
+    let uv_max = vec2<f32>(id.xy + 1u) / vec2<f32>(params.grid_size.xy);
+    var corners = array<vec2<f32>, 4>(
+        uv_min,
+        vec2<f32>(uv_max.x, uv_min.y),
+        vec2<f32>(uv_min.x, uv_max.y),
+        uv_max,
+    );
+    let near = slice_depth(params, f32(id.z));
+    let far = slice_depth(params, f32(id.z + 1u));
+    var bounds_min = vec3<f32>(1e30);
+    var bounds_max = vec3<f32>(-1e30);
+    for (var i = 0; i < 4; i++) {
+        for (var j = 0; j < 2; j++) {
+            let point = view_point(corners[i], select(near, far, j == 1));
+            bounds_min = min(bounds_min, point);
+            bounds_max = max(bounds_max, point);
+        }
+    }
+
+    let cluster = id.x + id.y * params.grid_size.x + id.z * params.grid_size.x * params.grid_size.y;
+    var count = 0u;
+    for (var i = 0u; i < params.light_count && count < MAX_LIGHTS_PER_CLUSTER; i++) {
+        let light = lights[i];
+        let center = (camera.view * vec4<f32>(light.position, 1.0)).xyz;
+        let offset = clamp(center, bounds_min, bounds_max) - center;
+        if dot(offset, offset) <= light.range * light.range {
+            cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + count] = i;
+            count++;
+        }
+    }
+    cluster_light_counts[cluster] = count;
+}
@@ -0,0 +1,44 @@
+// Light count per cluster drawn over the final image. Appended to fullscreen.wgsl and
+// cluster.wgsl.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(0)
+var<uniform> params: ClusterParams;
+@group(0) @binding(1)
+var<storage, read> cluster_light_counts: array<u32>;
+@group(0) @binding(2)
+var t_depth: texture_depth_2d;
+
+// light count at which the heatmap turns fully red
+const HEATMAP_MAX_LIGHTS: f32 = 32.0;
+
+// blue through green and yellow to red
+fn heat(t: f32) -> vec3<f32> {
+    let x = saturate(t) * 3.0;
+    return saturate(vec3<f32>(x - 1.0, min(x, 3.0 - x), 1.0 - x));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let depth = textureLoad(t_depth, vec2<i32>(in.clip_position.xy), 0);
+    if depth >= 1.0 {
+        discard;
+    }
+    let ndc = vec2<f32>(in.tex_coords.x * 2.0 - 1.0, 1.0 - in.tex_coords.y * 2.0);
+    let view = camera.inv_proj * vec4<f32>(ndc, depth, 1.0);
+    let cluster = cluster_index(params, in.clip_position.xy, -view.z / view.w);
+    let count = f32(cluster_light_counts[cluster]);
+    return vec4<f32>(heat(count / HEATMAP_MAX_LIGHTS), 0.6);
+}
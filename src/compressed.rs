@@ -0,0 +1,865 @@
+use anyhow::{anyhow, bail};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BcFormat {
+    Bc1,
+    Bc3,
+    Bc5,
+    Bc7,
+}
+
+impl BcFormat {
+    pub fn block_size(self) -> usize {
+        match self {
+            BcFormat::Bc1 => 8,
+            BcFormat::Bc3 | BcFormat::Bc5 | BcFormat::Bc7 => 16,
+        }
+    }
+
+    // bc5 only stores two linear channels so there is no srgb variant
+    pub fn wgpu_format(self, srgb: bool) -> wgpu::TextureFormat {
+        match (self, srgb) {
+            (BcFormat::Bc1, false) => wgpu::TextureFormat::Bc1RgbaUnorm,
+            (BcFormat::Bc1, true) => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+            (BcFormat::Bc3, false) => wgpu::TextureFormat::Bc3RgbaUnorm,
+            (BcFormat::Bc3, true) => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+            (BcFormat::Bc5, _) => wgpu::TextureFormat::Bc5RgUnorm,
+            (BcFormat::Bc7, false) => wgpu::TextureFormat::Bc7RgbaUnorm,
+            (BcFormat::Bc7, true) => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+        }
+    }
+
+    fn level_byte_len(self, width: u32, height: u32) -> usize {
+        width.div_ceil(4) as usize * height.div_ceil(4) as usize * self.block_size()
+    }
+}
+
+// Block compressed 2d image with the mip chain baked into the file, level 0 first.
+pub struct CompressedImage {
+    pub format: BcFormat,
+    pub srgb: bool,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    pub fn from_ktx2(bytes: &[u8]) -> anyhow::Result<Self> {
+        use ktx2::Format;
+
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("invalid ktx2 file: {e:?}"))?;
+        let header = reader.header();
+        if let Some(scheme) = header.supercompression_scheme {
+            bail!("supercompressed ktx2 files are not supported ({scheme:?})");
+        }
+        if header.face_count != 1 || header.layer_count > 1 || header.pixel_depth > 1 {
+            bail!("only single 2d ktx2 images are supported");
+        }
+        let (format, srgb) = match header.format {
+            Some(Format::BC1_RGB_UNORM_BLOCK) | Some(Format::BC1_RGBA_UNORM_BLOCK) => {
+                (BcFormat::Bc1, false)
+            }
+            Some(Format::BC1_RGB_SRGB_BLOCK) | Some(Format::BC1_RGBA_SRGB_BLOCK) => {
+                (BcFormat::Bc1, true)
+            }
+            Some(Format::BC3_UNORM_BLOCK) => (BcFormat::Bc3, false),
+            Some(Format::BC3_SRGB_BLOCK) => (BcFormat::Bc3, true),
+            Some(Format::BC5_UNORM_BLOCK) => (BcFormat::Bc5, false),
+            Some(Format::BC7_UNORM_BLOCK) => (BcFormat::Bc7, false),
+            Some(Format::BC7_SRGB_BLOCK) => (BcFormat::Bc7, true),
+            other => bail!("unsupported ktx2 format {other:?}"),
+        };
+        let levels = reader.levels().map(|level| level.data.to_vec()).collect();
+        Self::new(
+            format,
+            srgb,
+            header.pixel_width,
+            header.pixel_height,
+            levels,
+        )
+    }
+
+    // legacy dds headers don't say whether the data is srgb, srgb_hint decides for them
+    pub fn from_dds(bytes: &[u8], srgb_hint: bool) -> anyhow::Result<Self> {
+        use ddsfile::{D3DFormat, DxgiFormat};
+
+        let dds = ddsfile::Dds::read(bytes)?;
+        if dds.get_depth() > 1 || dds.get_num_array_layers() > 1 {
+            bail!("only single 2d dds images are supported");
+        }
+        let (format, srgb) = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+            (Some(DxgiFormat::BC1_UNorm), _) => (BcFormat::Bc1, false),
+            (Some(DxgiFormat::BC1_UNorm_sRGB), _) => (BcFormat::Bc1, true),
+            (Some(DxgiFormat::BC3_UNorm), _) => (BcFormat::Bc3, false),
+            (Some(DxgiFormat::BC3_UNorm_sRGB), _) => (BcFormat::Bc3, true),
+            (Some(DxgiFormat::BC5_UNorm), _) => (BcFormat::Bc5, false),
+            (Some(DxgiFormat::BC7_UNorm), _) => (BcFormat::Bc7, false),
+            (Some(DxgiFormat::BC7_UNorm_sRGB), _) => (BcFormat::Bc7, true),
+            (None, Some(D3DFormat::DXT1)) => (BcFormat::Bc1, srgb_hint),
+            (None, Some(D3DFormat::DXT5)) => (BcFormat::Bc3, srgb_hint),
+            (dxgi, d3d) => bail!("unsupported dds format {dxgi:?} {d3d:?}"),
+        };
+
+        // every level is stored back to back after the header
+        let data = dds.get_data(0)?;
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut levels = Vec::new();
+        let mut offset = 0;
+        for level in 0..dds.get_num_mipmap_levels() {
+            let len = format.level_byte_len((width >> level).max(1), (height >> level).max(1));
+            let Some(level_data) = data.get(offset..offset + len) else {
+                break;
+            };
+            levels.push(level_data.to_vec());
+            offset += len;
+        }
+        Self::new(format, srgb, width, height, levels)
+    }
+
+    fn new(
+        format: BcFormat,
+        srgb: bool,
+        width: u32,
+        height: u32,
+        levels: Vec<Vec<u8>>,
+    ) -> anyhow::Result<Self> {
+        let image = Self {
+            format,
+            srgb,
+            width,
+            height,
+            levels,
+        };
+        // the smaller mips can end in partial blocks but the base has to be whole ones
+        if !width.is_multiple_of(4) || !height.is_multiple_of(4) {
+            bail!("compressed image is {width}x{height}, which isn't a multiple of the 4x4 blocks");
+        }
+        if image.levels.is_empty() {
+            bail!("compressed image has no levels");
+        }
+        for (level, data) in image.levels.iter().enumerate() {
+            let (w, h) = image.level_size(level as u32);
+            if data.len() < format.level_byte_len(w, h) {
+                bail!("compressed image level {level} is truncated");
+            }
+        }
+        Ok(image)
+    }
+
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    // bytes per row of blocks, for uploading the level as is
+    pub fn level_bytes_per_row(&self, level: u32) -> u32 {
+        let (width, _) = self.level_size(level);
+        width.div_ceil(4) * self.format.block_size() as u32
+    }
+
+    // Software decode for adapters without TEXTURE_COMPRESSION_BC. Bc5 fills red and
+    // green only, like sampling the compressed texture would.
+    pub fn decode_level_rgba8(&self, level: u32) -> Vec<u8> {
+        let (width, height) = self.level_size(level);
+        let data = &self.levels[level as usize];
+        let blocks_wide = width.div_ceil(4) as usize;
+        let mut rgba = vec![0u8; width as usize * height as usize * 4];
+
+        for (block_index, block) in data
+            .chunks_exact(self.format.block_size())
+            .take(blocks_wide * height.div_ceil(4) as usize)
+            .enumerate()
+        {
+            let texels = match self.format {
+                BcFormat::Bc1 => decode_bc1(block, true),
+                BcFormat::Bc3 => {
+                    let alpha = decode_bc4(&block[..8]);
+                    let mut texels = decode_bc1(&block[8..], false);
+                    for (texel, a) in texels.iter_mut().zip(alpha) {
+                        texel[3] = a;
+                    }
+                    texels
+                }
+                BcFormat::Bc5 => {
+                    let red = decode_bc4(&block[..8]);
+                    let green = decode_bc4(&block[8..]);
+                    std::array::from_fn(|i| [red[i], green[i], 0, 255])
+                }
+                BcFormat::Bc7 => decode_bc7(block),
+            };
+
+            let block_x = (block_index % blocks_wide) * 4;
+            let block_y = (block_index / blocks_wide) * 4;
+            for (i, texel) in texels.iter().enumerate() {
+                let x = block_x + i % 4;
+                let y = block_y + i / 4;
+                if x < width as usize && y < height as usize {
+                    let offset = (y * width as usize + x) * 4;
+                    rgba[offset..offset + 4].copy_from_slice(texel);
+                }
+            }
+        }
+        rgba
+    }
+}
+
+fn expand_565(color: u16) -> [u8; 4] {
+    let r = ((color >> 11) & 0x1f) as u8;
+    let g = ((color >> 5) & 0x3f) as u8;
+    let b = (color & 0x1f) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+        255,
+    ]
+}
+
+// bc3 color blocks always use the four color mode
+fn decode_bc1(block: &[u8], allow_transparent: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let e0 = expand_565(c0);
+    let e1 = expand_565(c1);
+    let mut palette = [e0, e1, [0; 4], [0; 4]];
+    if c0 > c1 || !allow_transparent {
+        for c in 0..3 {
+            palette[2][c] = ((2 * e0[c] as u16 + e1[c] as u16) / 3) as u8;
+            palette[3][c] = ((e0[c] as u16 + 2 * e1[c] as u16) / 3) as u8;
+        }
+        palette[2][3] = 255;
+        palette[3][3] = 255;
+    } else {
+        for c in 0..3 {
+            palette[2][c] = ((e0[c] as u16 + e1[c] as u16) / 2) as u8;
+        }
+        palette[2][3] = 255;
+    }
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|i| palette[((indices >> (2 * i)) & 0b11) as usize])
+}
+
+// single channel block, also the alpha of bc3 and each channel of bc5
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let a0 = block[0] as u32;
+    let a1 = block[1] as u32;
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 0];
+    if a0 > a1 {
+        for k in 1..7 {
+            palette[k + 1] = ((7 - k as u32) * a0 + k as u32 * a1) / 7;
+        }
+    } else {
+        for k in 1..5 {
+            palette[k + 1] = ((5 - k as u32) * a0 + k as u32 * a1) / 5;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| palette[((indices >> (3 * i)) & 0b111) as usize] as u8)
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+// field meanings follow the mode table of the bc7 format spec
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 4,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 4,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 3,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 6,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: true,
+        index_bits: 3,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 5,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 1,
+        color_bits: 5,
+        alpha_bits: 6,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        secondary_index_bits: 3,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 8,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        secondary_index_bits: 2,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 7,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 4,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 5,
+        alpha_bits: 5,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        secondary_index_bits: 0,
+    },
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+// one bit per texel, set for the second subset
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+// two bits per texel holding its subset
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+// texel whose index drops its top bit, for the second subset of two subset partitions
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+// anchors of the second and third subset of three subset partitions
+const BC7_ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6,
+        8, 5, 15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8,
+        5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3,
+        15, 6, 10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15,
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let value = (self.bits >> self.position) as u32 & ((1u32 << count) - 1);
+        self.position += count;
+        value
+    }
+}
+
+fn bc7_interpolate(e0: u8, e1: u8, weight: u32) -> u8 {
+    (((64 - weight) * e0 as u32 + weight * e1 as u32 + 32) >> 6) as u8
+}
+
+fn bc7_weight(index_bits: u32, index: u32) -> u32 {
+    match index_bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    }
+}
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut reader = BitReader {
+        bits: u128::from_le_bytes(block.try_into().expect("bc7 blocks are 16 bytes")),
+        position: 0,
+    };
+    // the mode is the number of zero bits before the first set bit, 8 is reserved
+    let mode_index = block[0].trailing_zeros();
+    if mode_index >= 8 {
+        return [[0; 4]; 16];
+    }
+    reader.position = mode_index + 1;
+    let mode = &BC7_MODES[mode_index as usize];
+
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = reader.read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = reader.read(mode.alpha_bits);
+    }
+
+    let mut pbits = [0u32; 6];
+    if mode.endpoint_pbits {
+        for pbit in pbits.iter_mut().take(endpoint_count) {
+            *pbit = reader.read(1);
+        }
+    } else if mode.shared_pbits {
+        for subset in 0..mode.subsets {
+            let pbit = reader.read(1);
+            pbits[subset * 2] = pbit;
+            pbits[subset * 2 + 1] = pbit;
+        }
+    }
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+
+    // expand every endpoint channel to 8 bits, pbits become the new lowest bit
+    let mut colors = [[255u8; 4]; 6];
+    for e in 0..endpoint_count {
+        for channel in 0..4 {
+            let mut bits = if channel < 3 {
+                mode.color_bits
+            } else {
+                mode.alpha_bits
+            };
+            if bits == 0 {
+                continue;
+            }
+            let mut value = endpoints[e][channel];
+            if has_pbits {
+                value = (value << 1) | pbits[e];
+                bits += 1;
+            }
+            value <<= 8 - bits;
+            value |= value >> bits;
+            colors[e][channel] = value as u8;
+        }
+    }
+
+    let subset_of = |texel: usize| -> usize {
+        match mode.subsets {
+            2 => ((BC7_PARTITIONS_2[partition] >> texel) & 1) as usize,
+            3 => ((BC7_PARTITIONS_3[partition] >> (texel * 2)) & 0b11) as usize,
+            _ => 0,
+        }
+    };
+    let is_anchor = |texel: usize| -> bool {
+        texel == 0
+            || match mode.subsets {
+                2 => texel == BC7_ANCHORS_2[partition] as usize,
+                3 => {
+                    texel == BC7_ANCHORS_3[0][partition] as usize
+                        || texel == BC7_ANCHORS_3[1][partition] as usize
+                }
+                _ => false,
+            }
+    };
+
+    let mut primary = [0u32; 16];
+    for (texel, index) in primary.iter_mut().enumerate() {
+        let bits = mode.index_bits - is_anchor(texel) as u32;
+        *index = reader.read(bits);
+    }
+    let mut secondary = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (texel, index) in secondary.iter_mut().enumerate() {
+            let bits = mode.secondary_index_bits - (texel == 0) as u32;
+            *index = reader.read(bits);
+        }
+    }
+
+    std::array::from_fn(|texel| {
+        let subset = subset_of(texel);
+        let e0 = colors[subset * 2];
+        let e1 = colors[subset * 2 + 1];
+
+        // modes 4 and 5 index color and alpha separately, index_selection swaps which is which
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = bc7_weight(mode.index_bits, primary[texel]);
+            (weight, weight)
+        } else if index_selection == 0 {
+            (
+                bc7_weight(mode.index_bits, primary[texel]),
+                bc7_weight(mode.secondary_index_bits, secondary[texel]),
+            )
+        } else {
+            (
+                bc7_weight(mode.secondary_index_bits, secondary[texel]),
+                bc7_weight(mode.index_bits, primary[texel]),
+            )
+        };
+
+        let mut texel = [
+            bc7_interpolate(e0[0], e1[0], color_weight),
+            bc7_interpolate(e0[1], e1[1], color_weight),
+            bc7_interpolate(e0[2], e1[2], color_weight),
+            bc7_interpolate(e0[3], e1[3], alpha_weight),
+        ];
+        match rotation {
+            1 => texel.swap(0, 3),
+            2 => texel.swap(1, 3),
+            3 => texel.swap(2, 3),
+            _ => {}
+        }
+        texel
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED_565: u16 = 0xf800;
+    const BLUE_565: u16 = 0x001f;
+
+    fn bc1_block(c0: u16, c1: u16, indices: u32) -> Vec<u8> {
+        [c0.to_le_bytes(), c1.to_le_bytes()]
+            .concat()
+            .into_iter()
+            .chain(indices.to_le_bytes())
+            .collect()
+    }
+
+    // a0 and a1 followed by sixteen 3 bit indices
+    fn bc4_block(a0: u8, a1: u8, indices: [u8; 16]) -> Vec<u8> {
+        let bits = indices
+            .iter()
+            .enumerate()
+            .fold(0u64, |bits, (i, &index)| bits | (index as u64) << (3 * i));
+        [a0, a1]
+            .into_iter()
+            .chain(bits.to_le_bytes().into_iter().take(6))
+            .collect()
+    }
+
+    // packs fields from the lowest bit up, the way bc7 blocks are read
+    #[derive(Default)]
+    struct BitWriter {
+        bits: u128,
+        position: u32,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, count: u32) -> &mut Self {
+            self.bits |= (value as u128) << self.position;
+            self.position += count;
+            self
+        }
+
+        // mode m is m zero bits and a one
+        fn mode(&mut self, mode: u32) -> &mut Self {
+            self.write(1 << mode, mode + 1)
+        }
+
+        fn finish(&self) -> [u8; 16] {
+            assert_eq!(self.position, 128, "bc7 blocks are 128 bits");
+            self.bits.to_le_bytes()
+        }
+    }
+
+    #[test]
+    fn bc1_opaque_block_interpolates_two_thirds() {
+        // texels 0 to 3 use palette entries 0 to 3, the rest entry 0
+        let texels = decode_bc1(&bc1_block(RED_565, BLUE_565, 0b11_10_01_00), true);
+        assert_eq!(texels[0], [255, 0, 0, 255]);
+        assert_eq!(texels[1], [0, 0, 255, 255]);
+        assert_eq!(texels[2], [170, 0, 85, 255]);
+        assert_eq!(texels[3], [85, 0, 170, 255]);
+        assert!(texels[4..].iter().all(|&t| t == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn bc1_punch_through_block_has_a_transparent_entry() {
+        // c0 <= c1 switches to three colors and transparent black
+        let block = bc1_block(BLUE_565, RED_565, 0b11_10);
+        let texels = decode_bc1(&block, true);
+        assert_eq!(texels[0], [127, 0, 127, 255]);
+        assert_eq!(texels[1], [0, 0, 0, 0]);
+        // bc3's color half ignores the endpoint order
+        let texels = decode_bc1(&block, false);
+        assert_eq!(texels[0], [85, 0, 170, 255]);
+        assert_eq!(texels[1], [170, 0, 85, 255]);
+    }
+
+    #[test]
+    fn bc4_eight_value_block() {
+        let mut indices = [0; 16];
+        indices[1] = 1;
+        indices[2] = 2;
+        indices[3] = 7;
+        let values = decode_bc4(&bc4_block(255, 0, indices));
+        assert_eq!(values[..4], [255, 0, 218, 36]);
+    }
+
+    #[test]
+    fn bc4_six_value_block_has_zero_and_one() {
+        let mut indices = [0; 16];
+        indices[0] = 6;
+        indices[1] = 7;
+        indices[2] = 2;
+        indices[3] = 5;
+        let values = decode_bc4(&bc4_block(0, 255, indices));
+        assert_eq!(values[..4], [0, 255, 51, 204]);
+    }
+
+    #[test]
+    fn bc5_decodes_into_red_and_green() {
+        let mut indices = [0; 16];
+        indices[15] = 1;
+        let block = [bc4_block(200, 10, indices), bc4_block(100, 20, [0; 16])].concat();
+        let image = CompressedImage::new(BcFormat::Bc5, false, 4, 4, vec![block]).unwrap();
+        let rgba = image.decode_level_rgba8(0);
+        assert_eq!(rgba[..4], [200, 100, 0, 255]);
+        assert_eq!(rgba[60..], [10, 100, 0, 255]);
+    }
+
+    #[test]
+    fn bc7_mode_6_block() {
+        let mut writer = BitWriter::default();
+        writer.mode(6);
+        // r0 r1 g0 g1 b0 b1 a0 a1 at 7 bits, then a pbit per endpoint
+        for value in [127, 0, 0, 127, 0, 0, 127, 127] {
+            writer.write(value, 7);
+        }
+        writer.write(1, 1).write(0, 1);
+        // texel 0 is the anchor with one bit less
+        writer.write(0, 3).write(15, 4).write(8, 4);
+        for _ in 3..16 {
+            writer.write(0, 4);
+        }
+        let texels = decode_bc7(&writer.finish());
+        assert_eq!(texels[0], [255, 1, 1, 255]);
+        assert_eq!(texels[1], [0, 254, 0, 254]);
+        // weight 34 of 64 towards the second endpoint
+        assert_eq!(texels[2], [120, 135, 0, 254]);
+        assert_eq!(texels[15], texels[0]);
+    }
+
+    fn bc7_mode_5_block(rotation: u32) -> [u8; 16] {
+        let mut writer = BitWriter::default();
+        writer.mode(5).write(rotation, 2);
+        for value in [127, 0, 0, 127, 64, 64] {
+            writer.write(value, 7);
+        }
+        writer.write(0, 8).write(255, 8);
+        // color indices, then alpha indices, both with a one bit anchor
+        writer.write(0, 1).write(3, 2).write(1, 2);
+        for _ in 3..16 {
+            writer.write(0, 2);
+        }
+        writer.write(1, 1).write(0, 2).write(2, 2);
+        for _ in 3..16 {
+            writer.write(0, 2);
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn bc7_mode_5_indexes_color_and_alpha_separately() {
+        let texels = decode_bc7(&bc7_mode_5_block(0));
+        assert_eq!(texels[0], [255, 0, 129, 84]);
+        assert_eq!(texels[1], [0, 255, 129, 0]);
+        assert_eq!(texels[2], [171, 84, 129, 171]);
+    }
+
+    #[test]
+    fn bc7_rotation_swaps_alpha_into_a_color_channel() {
+        let texels = decode_bc7(&bc7_mode_5_block(1));
+        assert_eq!(texels[0], [84, 0, 129, 255]);
+        let texels = decode_bc7(&bc7_mode_5_block(3));
+        assert_eq!(texels[0], [255, 0, 84, 129]);
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_transparent_black() {
+        assert_eq!(decode_bc7(&[0; 16]), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn levels_are_checked_against_the_size() {
+        // 8x8 bc1 is 2x2 blocks, then one block for 4x4
+        let levels = vec![vec![0; 32], vec![0; 8]];
+        let image = CompressedImage::new(BcFormat::Bc1, false, 8, 8, levels).unwrap();
+        assert_eq!(image.level_bytes_per_row(0), 16);
+        assert_eq!(image.level_size(3), (1, 1));
+
+        let truncated = vec![vec![0; 32], vec![0; 7]];
+        assert!(CompressedImage::new(BcFormat::Bc1, false, 8, 8, truncated).is_err());
+        assert!(CompressedImage::new(BcFormat::Bc1, false, 8, 8, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn sizes_that_arent_whole_blocks_are_rejected() {
+        let levels = vec![vec![0; 8 * 8 * 8]];
+        let error = CompressedImage::new(BcFormat::Bc1, false, 30, 30, levels)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("30x30"), "{error}");
+    }
+
+    // A ktx2 file with an empty data format descriptor, enough for the reader.
+    fn ktx2(vk_format: u32, width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        const HEADER_LEN: usize = 80;
+        const DFD_LEN: usize = 4;
+        let dfd_offset = HEADER_LEN + levels.len() * 24;
+        let mut file = b"\xabKTX 20\xbb\r\n\x1a\n".to_vec();
+        let header = [vk_format, 1, width, height, 0, 0, 1, levels.len() as u32, 0];
+        file.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+        file.extend((dfd_offset as u32).to_le_bytes());
+        file.extend((DFD_LEN as u32).to_le_bytes());
+        // no key/value or supercompression data
+        file.extend([0u8; 24]);
+        let mut offset = dfd_offset + DFD_LEN;
+        for level in levels {
+            let len = level.len() as u64;
+            file.extend((offset as u64).to_le_bytes());
+            file.extend(len.to_le_bytes());
+            file.extend(len.to_le_bytes());
+            offset += level.len();
+        }
+        file.extend((DFD_LEN as u32).to_le_bytes());
+        file.extend(levels.concat());
+        file
+    }
+
+    const VK_FORMAT_BC1_RGBA_SRGB_BLOCK: u32 = 134;
+    const VK_FORMAT_BC7_UNORM_BLOCK: u32 = 145;
+
+    #[test]
+    fn ktx2_reads_format_and_levels() {
+        let levels = [vec![1; 32], vec![2; 8]];
+        let file = ktx2(VK_FORMAT_BC1_RGBA_SRGB_BLOCK, 8, 8, &levels);
+        let image = CompressedImage::from_ktx2(&file).unwrap();
+        assert_eq!(image.format, BcFormat::Bc1);
+        assert!(image.srgb);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.levels, levels);
+    }
+
+    #[test]
+    fn ktx2_with_truncated_level_is_rejected() {
+        let file = ktx2(VK_FORMAT_BC7_UNORM_BLOCK, 8, 8, &[vec![0; 63]]);
+        assert!(CompressedImage::from_ktx2(&file).is_err());
+        let file = ktx2(VK_FORMAT_BC7_UNORM_BLOCK, 8, 8, &[vec![0; 64]]);
+        assert!(CompressedImage::from_ktx2(&file).is_ok());
+    }
+
+    fn dds(format: ddsfile::DxgiFormat, size: u32, mipmap_levels: u32) -> ddsfile::Dds {
+        ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: size,
+            width: size,
+            depth: None,
+            format,
+            mipmap_levels: Some(mipmap_levels),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })
+        .unwrap()
+    }
+
+    fn write_dds(dds: &ddsfile::Dds) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn dds_splits_the_mip_chain() {
+        // 16x16, 8x8 and 4x4 bc7 are 16, 4 and 1 blocks
+        let file = write_dds(&dds(ddsfile::DxgiFormat::BC7_UNorm_sRGB, 16, 3));
+        let image = CompressedImage::from_dds(&file, false).unwrap();
+        assert_eq!(image.format, BcFormat::Bc7);
+        assert!(image.srgb);
+        let lengths = image.levels.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(lengths, [256, 64, 16]);
+    }
+
+    #[test]
+    fn dds_with_truncated_data_is_rejected() {
+        let mut dds = dds(ddsfile::DxgiFormat::BC1_UNorm, 16, 1);
+        dds.data.truncate(100);
+        assert!(CompressedImage::from_dds(&write_dds(&dds), false).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use std::f32::consts::TAU;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+use crate::vertex::{Vertex, VertexAttributeDesc, VertexSemantic};
+
+// segments in each of a sphere's circles
+const SPHERE_SEGMENTS: u32 = 32;
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [1.0, 0.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0, 1.0],
+    [0.0, 0.0, 1.0, 1.0],
+];
+
+// How long a shape stays and whether the scene can hide it.
+#[derive(Copy, Clone, Debug)]
+pub struct DebugStyle {
+    // zero draws it in the next frame only
+    pub duration: Duration,
+    pub depth_test: bool,
+}
+
+impl Default for DebugStyle {
+    fn default() -> Self {
+        Self {
+            duration: Duration::ZERO,
+            depth_test: true,
+        }
+    }
+}
+
+impl DebugStyle {
+    pub fn with_duration(self, duration: Duration) -> Self {
+        Self { duration, ..self }
+    }
+
+    pub fn with_depth_test(self, depth_test: bool) -> Self {
+        Self { depth_test, ..self }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl Vertex for LineVertex {
+    const ATTRIBUTES: &'static [VertexAttributeDesc] = &[
+        VertexAttributeDesc::new(
+            VertexSemantic::Position,
+            wgpu::VertexFormat::Float32x3,
+            mem::offset_of!(LineVertex, position),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::Color,
+            wgpu::VertexFormat::Float32x4,
+            mem::offset_of!(LineVertex, color),
+        ),
+    ];
+}
+
+// one call's lines, as pairs of vertices
+struct Shape {
+    vertices: Vec<LineVertex>,
+    depth_test: bool,
+    expires: Instant,
+}
+
+// Immediate mode lines and shapes in world space, for seeing what code is doing. Shapes
+// added during a frame are drawn over the final image by encode, and kept until their
+// duration has passed.
+pub struct DebugDraw {
+    shapes: Vec<Shape>,
+    // grows to fit the most lines drawn in a frame so far
+    vertex_buffer: wgpu::Buffer,
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug draw shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug_draw.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug draw pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug draw vertex buffer"),
+            size: 0,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            shapes: Vec::new(),
+            vertex_buffer,
+            depth_tested_pipeline: create_pipeline(device, &layout, &shader, format, true),
+            overlay_pipeline: create_pipeline(device, &layout, &shader, format, false),
+        }
+    }
+
+    pub fn line(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        color: [f32; 4],
+        style: DebugStyle,
+    ) {
+        self.add(vec![(start, end)], color, style);
+    }
+
+    // the box between two opposite corners, aligned with the world axes
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 4], style: DebugStyle) {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        self.add(box_edges(corner), color, style);
+    }
+
+    // a circle around each axis
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 4], style: DebugStyle) {
+        let mut segments = Vec::new();
+        for axis in 0..3 {
+            let point = |segment: u32| {
+                let angle = segment as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                let mut offset = Vector3::zeros();
+                offset[(axis + 1) % 3] = angle.cos() * radius;
+                offset[(axis + 2) % 3] = angle.sin() * radius;
+                center + offset
+            };
+            segments.extend((0..SPHERE_SEGMENTS).map(|i| (point(i), point(i + 1))));
+        }
+        self.add(segments, color, style);
+    }
+
+    // The volume a camera with this view projection sees, from Camera's
+    // build_view_projection_matrix for example.
+    pub fn frustum(&mut self, view_proj: &Matrix4<f32>, color: [f32; 4], style: DebugStyle) {
+        let Some(inverse) = view_proj.try_inverse() else {
+            return;
+        };
+        // wgpu's ndc goes from 0 to 1 in depth
+        let corner = |i: usize| {
+            let ndc = Point3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+            );
+            inverse.transform_point(&ndc)
+        };
+        self.add(box_edges(corner), color, style);
+    }
+
+    // x, y and z of the rotated frame at origin in red, green and blue
+    pub fn axis(
+        &mut self,
+        origin: Point3<f32>,
+        rotation: UnitQuaternion<f32>,
+        length: f32,
+        style: DebugStyle,
+    ) {
+        for (axis, color) in AXIS_COLORS.into_iter().enumerate() {
+            let direction = rotation * Vector3::ith(axis, length);
+            self.add(vec![(origin, origin + direction)], color, style);
+        }
+    }
+
+    // A square grid on the xz plane, size wide with divisions cells along each side.
+    pub fn grid(
+        &mut self,
+        center: Point3<f32>,
+        size: f32,
+        divisions: u32,
+        color: [f32; 4],
+        style: DebugStyle,
+    ) {
+        let divisions = divisions.max(1);
+        let half = size / 2.0;
+        let mut segments = Vec::new();
+        for i in 0..=divisions {
+            let offset = i as f32 / divisions as f32 * size - half;
+            segments.push((
+                center + Vector3::new(offset, 0.0, -half),
+                center + Vector3::new(offset, 0.0, half),
+            ));
+            segments.push((
+                center + Vector3::new(-half, 0.0, offset),
+                center + Vector3::new(half, 0.0, offset),
+            ));
+        }
+        self.add(segments, color, style);
+    }
+
+    fn add(
+        &mut self,
+        segments: Vec<(Point3<f32>, Point3<f32>)>,
+        color: [f32; 4],
+        style: DebugStyle,
+    ) {
+        let vertices = segments
+            .into_iter()
+            .flat_map(|(start, end)| [start, end])
+            .map(|position| LineVertex {
+                position: position.into(),
+                color,
+            })
+            .collect();
+        self.shapes.push(Shape {
+            vertices,
+            depth_test: style.depth_test,
+            expires: Instant::now() + style.duration,
+        });
+    }
+
+    // Draws every shape over output, the depth tested ones against depth, then drops
+    // the shapes whose time is up. camera_bind_group is the scene's, the lines are
+    // projected with its unjittered_view_proj and not view_proj, so they hold still
+    // while taa jitters the scene under them.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+        depth: &Texture,
+    ) {
+        if self.shapes.is_empty() {
+            return;
+        }
+        // depth tested lines first, then the ones drawn over everything
+        let (tested, overlay): (Vec<&Shape>, Vec<&Shape>) =
+            self.shapes.iter().partition(|shape| shape.depth_test);
+        let tested_count = tested.iter().map(|s| s.vertices.len()).sum::<usize>() as u32;
+        let vertices = tested
+            .iter()
+            .chain(&overlay)
+            .flat_map(|shape| shape.vertices.iter().copied())
+            .collect::<Vec<_>>();
+        let vertex_count = vertices.len() as u32;
+        let contents: &[u8] = bytemuck::cast_slice(&vertices);
+        if (contents.len() as wgpu::BufferAddress) > self.vertex_buffer.size() {
+            self.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Debug draw vertex buffer"),
+                contents,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+        } else {
+            queue.write_buffer(&self.vertex_buffer, 0, contents);
+        }
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug draw pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            if tested_count > 0 {
+                render_pass.set_pipeline(&self.depth_tested_pipeline);
+                render_pass.draw(0..tested_count, 0..1);
+            }
+            if vertex_count > tested_count {
+                render_pass.set_pipeline(&self.overlay_pipeline);
+                render_pass.draw(tested_count..vertex_count, 0..1);
+            }
+        }
+
+        let now = Instant::now();
+        self.shapes.retain(|shape| shape.expires > now);
+    }
+}
+
+// the twelve edges of a box from its corners, bit 0 of the index picks x, bit 1 y and
+// bit 2 z
+fn box_edges(corner: impl Fn(usize) -> Point3<f32>) -> Vec<(Point3<f32>, Point3<f32>)> {
+    let mut edges = Vec::new();
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                edges.push((corner(i), corner(i | bit)));
+            }
+        }
+    }
+    edges
+}
+
+// lines are never written to depth, depth_test decides whether the scene hides them
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    depth_test: bool,
+) -> wgpu::RenderPipeline {
+    let vertex_layout = LineVertex::layout();
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(if depth_test {
+            "Debug draw pipeline"
+        } else {
+            "Debug draw overlay pipeline"
+        }),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            buffers: &[vertex_layout.buffer_layout()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: if depth_test {
+                wgpu::CompareFunction::LessEqual
+            } else {
+                wgpu::CompareFunction::Always
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::model::{self, Mesh};
+use crate::texture::Texture;
+use crate::vertex::{ReflectedShader, VertexLayout, VertexSemantic};
+
+// Views for inspecting models, drawn over the final image instead of the lit scene.
+// The numbers are what shader.wgsl's DEBUG_VIEW is set to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    Off = 0,
+    Wireframe = 1,
+    // vertex normals in world space
+    Normals = 2,
+    Uvs = 3,
+    // the level the diffuse texture is sampled from
+    MipLevel = 4,
+    // linear from the eye to the far plane
+    Depth = 5,
+}
+
+impl DebugView {
+    // the views that draw something
+    const SHOWN: [DebugView; 5] = [
+        Self::Wireframe,
+        Self::Normals,
+        Self::Uvs,
+        Self::MipLevel,
+        Self::Depth,
+    ];
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Wireframe,
+            Self::Wireframe => Self::Normals,
+            Self::Normals => Self::Uvs,
+            Self::Uvs => Self::MipLevel,
+            Self::MipLevel => Self::Depth,
+            Self::Depth => Self::Off,
+        }
+    }
+}
+
+// how the wireframe view is drawn, which depends on the adapter rasterizing lines
+enum Wireframe {
+    // the mesh pipelines with PolygonMode::Line
+    Lines,
+    // wireframe.wgsl, with a bind group per mesh for reading its buffers
+    Barycentric {
+        pipeline: wgpu::RenderPipeline,
+        bind_groups: Vec<wgpu::BindGroup>,
+    },
+}
+
+// Draws the meshes with fs_debug_view in shader.wgsl, or wireframe.wgsl for wireframes
+// without POLYGON_MODE_LINE. The renderer begins the pass and sets the scene bind groups,
+// then calls draw_mesh for every mesh while view isn't Off.
+pub struct DebugViewPass {
+    pub view: DebugView,
+    // every view but the barycentric wireframe for every vertex layout
+    pipelines: HashMap<(VertexLayout, DebugView), wgpu::RenderPipeline>,
+    wireframe: Wireframe,
+}
+
+impl DebugViewPass {
+    // layout and shader are the ones the scene is drawn with, format is the surface's
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &ReflectedShader,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        meshes: &[Mesh],
+        instance_layout: &VertexLayout,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let wireframe = if device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+        {
+            Wireframe::Lines
+        } else {
+            create_barycentric_wireframe(
+                device,
+                camera_bind_group_layout,
+                meshes,
+                instance_layout,
+                format,
+            )
+        };
+
+        let mut pipelines = HashMap::new();
+        for mesh in meshes {
+            for view in DebugView::SHOWN {
+                let polygon_mode = match (view, &wireframe) {
+                    (DebugView::Wireframe, Wireframe::Lines) => wgpu::PolygonMode::Line,
+                    (DebugView::Wireframe, Wireframe::Barycentric { .. }) => continue,
+                    _ => wgpu::PolygonMode::Fill,
+                };
+                let key = (mesh.vertex_layout.clone(), view);
+                if pipelines.contains_key(&key) {
+                    continue;
+                }
+                let pipeline = create_pipeline(
+                    device,
+                    layout,
+                    shader,
+                    &[mesh.vertex_layout.clone(), instance_layout.clone()],
+                    format,
+                    view,
+                    polygon_mode,
+                )
+                .unwrap();
+                pipelines.insert(key, pipeline);
+            }
+        }
+
+        Self {
+            view: DebugView::Off,
+            pipelines,
+            wireframe,
+        }
+    }
+
+    // Draws instances of the mesh at mesh_index in the model, in the current view.
+    pub fn draw_mesh<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        mesh_index: usize,
+        mesh: &'a Mesh,
+        material_bind_group: &'a wgpu::BindGroup,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+    ) {
+        if self.view == DebugView::Wireframe {
+            if let Wireframe::Barycentric {
+                pipeline,
+                bind_groups,
+            } = &self.wireframe
+            {
+                // the positions come out of the bind group, the only vertex buffer is the
+                // instances
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(0, &bind_groups[mesh_index], &[]);
+                render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+                render_pass.draw(0..mesh.num_elements, instances);
+                return;
+            }
+        }
+        render_pass.set_pipeline(&self.pipelines[&(mesh.vertex_layout.clone(), self.view)]);
+        render_pass.set_bind_group(0, material_bind_group, &[]);
+        model::draw_mesh_instanced(render_pass, mesh, instances);
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &ReflectedShader,
+    vertex_layouts: &[VertexLayout],
+    format: wgpu::TextureFormat,
+    view: DebugView,
+    polygon_mode: wgpu::PolygonMode,
+) -> anyhow::Result<wgpu::RenderPipeline> {
+    let (vertex_entry_point, matched_layouts) = shader.best_vertex_entry(vertex_layouts)?;
+    let buffers = matched_layouts
+        .iter()
+        .map(VertexLayout::buffer_layout)
+        .collect::<Vec<_>>();
+    let constants = HashMap::from([("DEBUG_VIEW".to_string(), view as u32 as f64)]);
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Debug view pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader.module,
+            entry_point: vertex_entry_point,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            buffers: &buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader.module,
+            entry_point: "fs_debug_view",
+            targets: &[Some(format.into())],
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            },
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode,
+            ..Default::default()
+        },
+        depth_stencil: Some(depth_stencil_state()),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+    Ok(pipeline)
+}
+
+fn depth_stencil_state() -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format: Texture::DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
+}
+
+fn create_barycentric_wireframe(
+    device: &wgpu::Device,
+    camera_bind_group_layout: &wgpu::BindGroupLayout,
+    meshes: &[Mesh],
+    instance_layout: &VertexLayout,
+    format: wgpu::TextureFormat,
+) -> Wireframe {
+    let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            storage_entry(0),
+            storage_entry(1),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        label: Some("wireframe_bind_group_layout"),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Wireframe pipeline layout"),
+        bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Wireframe shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("wireframe.wgsl").into()),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Wireframe pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            buffers: &[instance_layout.buffer_layout()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(format.into())],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(depth_stencil_state()),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+
+    let bind_groups = meshes
+        .iter()
+        .map(|mesh| {
+            let layout = &mesh.vertex_layout;
+            // every vertex type keeps its position as three floats
+            let position_offset = layout
+                .attributes
+                .iter()
+                .find(|a| a.semantic == VertexSemantic::Position)
+                .map_or(0, |a| a.offset);
+            let float_size = std::mem::size_of::<f32>() as wgpu::BufferAddress;
+            // uniform buffers are sized in multiples of 16 bytes
+            let params = [
+                (layout.array_stride / float_size) as u32,
+                (position_offset / float_size) as u32,
+                0,
+                0,
+            ];
+            let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Wireframe params buffer"),
+                contents: bytemuck::cast_slice(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: mesh.index_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: mesh.vertex_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("wireframe_bind_group"),
+            })
+        })
+        .collect();
+
+    Wireframe::Barycentric {
+        pipeline,
+        bind_groups,
+    }
+}
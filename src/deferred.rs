@@ -0,0 +1,366 @@
+use crate::lights;
+use crate::post_process;
+use crate::renderer::{NORMAL_FORMAT, VELOCITY_FORMAT};
+use crate::texture::Texture;
+use crate::tonemap::HDR_FORMAT;
+
+pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+pub const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+// g-buffer pass color attachments: albedo, world space normals, metallic and roughness,
+// emissive, and motion since the last frame into the renderer's velocity texture
+pub const GBUFFER_FORMATS: [wgpu::TextureFormat; 5] = [
+    ALBEDO_FORMAT,
+    NORMAL_FORMAT,
+    MATERIAL_FORMAT,
+    HDR_FORMAT,
+    VELOCITY_FORMAT,
+];
+// what the lighting pass writes, the main pass targets before velocity
+const LIGHTING_FORMATS: [wgpu::TextureFormat; 3] = [HDR_FORMAT, NORMAL_FORMAT, HDR_FORMAT];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    // every mesh is lit as it is drawn
+    Forward,
+    // meshes fill the g-buffer, which is lit once per pixel afterwards
+    Deferred,
+}
+
+impl RenderPath {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Forward => Self::Deferred,
+            Self::Deferred => Self::Forward,
+        }
+    }
+}
+
+// What the deferred path shows, the lit scene or one g-buffer channel. The numbers are
+// what deferred.wgsl switches on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GBufferView {
+    Lit = 0,
+    Albedo = 1,
+    Normal = 2,
+    MetallicRoughness = 3,
+    Emissive = 4,
+    Depth = 5,
+}
+
+impl GBufferView {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Lit => Self::Albedo,
+            Self::Albedo => Self::Normal,
+            Self::Normal => Self::MetallicRoughness,
+            Self::MetallicRoughness => Self::Emissive,
+            Self::Emissive => Self::Depth,
+            Self::Depth => Self::Lit,
+        }
+    }
+}
+
+// The g-buffer and the fullscreen passes that read it. The renderer draws the meshes
+// into targets() with fs_gbuffer, light fills the main pass targets from them, and
+// draw_debug_view replaces the final image with a channel when view isn't Lit.
+pub struct DeferredPass {
+    pub view: GBufferView,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    lighting_pipeline: wgpu::RenderPipeline,
+    debug_pipeline: wgpu::RenderPipeline,
+    albedo_texture: Texture,
+    normal_texture: Texture,
+    material_texture: Texture,
+    emissive_texture: Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl DeferredPass {
+    // depth is the single sampled depth texture the g-buffer pass draws with
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth: &Texture,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        environment_bind_group_layout: &wgpu::BindGroupLayout,
+        lights_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let float = wgpu::TextureSampleType::Float { filterable: false };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, float),
+                texture_entry(1, float),
+                texture_entry(2, float),
+                texture_entry(3, float),
+                texture_entry(4, wgpu::TextureSampleType::Depth),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("gbuffer_bind_group_layout"),
+        });
+        // uniform buffers are sized in multiples of 16 bytes
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Deferred params buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let shader = post_process::fullscreen_shader(
+            device,
+            "Deferred shader",
+            &lights::scene_shader_source(include_str!("deferred.wgsl")),
+        );
+        let lighting_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred lighting pipeline layout"),
+            bind_group_layouts: &[
+                &bind_group_layout,
+                camera_bind_group_layout,
+                environment_bind_group_layout,
+                lights_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let targets = LIGHTING_FORMATS
+            .iter()
+            .map(|&format| Some(format.into()))
+            .collect::<Vec<_>>();
+        let lighting_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Deferred lighting pipeline"),
+            layout: Some(&lighting_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_lighting",
+                targets: &targets,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let debug_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gbuffer debug pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let debug_pipeline = post_process::fullscreen_pipeline(
+            device,
+            Some(&debug_layout),
+            &shader,
+            "fs_debug",
+            surface_config.format.into(),
+            "Gbuffer debug pipeline",
+        );
+
+        let [albedo_texture, normal_texture, material_texture, emissive_texture] =
+            create_gbuffer(device, surface_config);
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            [
+                &albedo_texture,
+                &normal_texture,
+                &material_texture,
+                &emissive_texture,
+                depth,
+            ],
+            &params_buffer,
+        );
+
+        Self {
+            view: GBufferView::Lit,
+            bind_group_layout,
+            params_buffer,
+            lighting_pipeline,
+            debug_pipeline,
+            albedo_texture,
+            normal_texture,
+            material_texture,
+            emissive_texture,
+            bind_group,
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth: &Texture,
+    ) {
+        [
+            self.albedo_texture,
+            self.normal_texture,
+            self.material_texture,
+            self.emissive_texture,
+        ] = create_gbuffer(device, surface_config);
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            [
+                &self.albedo_texture,
+                &self.normal_texture,
+                &self.material_texture,
+                &self.emissive_texture,
+                depth,
+            ],
+            &self.params_buffer,
+        );
+    }
+
+    // the g-buffer pass draws into these in the order of GBUFFER_FORMATS, followed by
+    // the velocity texture
+    pub fn targets(&self) -> [&Texture; 4] {
+        [
+            &self.albedo_texture,
+            &self.normal_texture,
+            &self.material_texture,
+            &self.emissive_texture,
+        ]
+    }
+
+    // Lights every pixel the g-buffer pass covered into the main pass targets. The rest
+    // is left at background in color for the skybox to draw over.
+    pub fn light(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        environment_bind_group: &wgpu::BindGroup,
+        lights_bind_group: &wgpu::BindGroup,
+        outputs: [&Texture; 3],
+        background: wgpu::Color,
+    ) {
+        let clear_colors = [
+            background,
+            wgpu::Color::TRANSPARENT,
+            wgpu::Color::TRANSPARENT,
+        ];
+        let color_attachments = outputs
+            .iter()
+            .zip(clear_colors)
+            .map(|(output, clear)| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &output.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Deferred lighting pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.lighting_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, environment_bind_group, &[]);
+        render_pass.set_bind_group(3, lights_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Draws the selected g-buffer channel over output, does nothing while view is Lit.
+    pub fn draw_debug_view(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) {
+        if self.view == GBufferView::Lit {
+            return;
+        }
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[self.view as u32, 0, 0, 0]),
+        );
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Gbuffer debug pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.debug_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_gbuffer(
+    device: &wgpu::Device,
+    surface_config: &wgpu::SurfaceConfiguration,
+) -> [Texture; 4] {
+    [
+        (ALBEDO_FORMAT, "gbuffer_albedo_texture"),
+        (NORMAL_FORMAT, "gbuffer_normal_texture"),
+        (MATERIAL_FORMAT, "gbuffer_material_texture"),
+        (HDR_FORMAT, "gbuffer_emissive_texture"),
+    ]
+    .map(|(format, label)| Texture::create_render_target(device, surface_config, format, label))
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    textures: [&Texture; 5],
+    params_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    let mut entries = textures
+        .iter()
+        .enumerate()
+        .map(|(binding, texture)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        })
+        .collect::<Vec<_>>();
+    entries.push(wgpu::BindGroupEntry {
+        binding: 5,
+        resource: params_buffer.as_entire_binding(),
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &entries,
+        label: Some("gbuffer_bind_group"),
+    })
+}
@@ -0,0 +1,115 @@
+// Deferred lighting and g-buffer debug views. Appended to fullscreen.wgsl, pbr.wgsl,
+// cluster.wgsl and lights.wgsl. fs_gbuffer in shader.wgsl fills the g-buffer,
+// fs_lighting turns it into what the forward path's fs_main would have drawn.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct DeferredParams {
+    // GBufferView in deferred.rs, 0 is the lit scene
+    view: u32,
+};
+
+@group(0) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(0) @binding(1)
+var t_world_normal: texture_2d<f32>;
+@group(0) @binding(2)
+var t_material: texture_2d<f32>;
+@group(0) @binding(3)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(4)
+var t_depth: texture_depth_2d;
+@group(0) @binding(5)
+var<uniform> params: DeferredParams;
+
+// the main pass targets besides velocity, which the g-buffer pass already wrote
+struct LightingOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) view_normal: vec4<f32>,
+    @location(2) ambient: vec4<f32>,
+};
+
+fn ndc_from_uv(uv: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+@fragment
+fn fs_lighting(in: FullscreenOutput) -> LightingOutput {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    var out: LightingOutput;
+    // nothing was drawn here, the skybox fills it in afterwards
+    if depth >= 1.0 {
+        return out;
+    }
+
+    let world = camera.inv_view_proj * vec4<f32>(ndc_from_uv(in.tex_coords), depth, 1.0);
+    let world_position = world.xyz / world.w;
+    let albedo = textureLoad(t_albedo, pixel, 0);
+    let normal = normalize(textureLoad(t_world_normal, pixel, 0).xyz);
+    let material = textureLoad(t_material, pixel, 0);
+    let emissive = textureLoad(t_emissive, pixel, 0).rgb;
+
+    let v = normalize(camera.position.xyz - world_position);
+    var light = shade_surface(albedo.rgb, normal, material.r, material.g, v);
+    light.ambient *= material.b;
+    let view_z = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+    let local_light = shade_lights(
+        albedo.rgb,
+        normal,
+        material.r,
+        material.g,
+        v,
+        world_position,
+        in.clip_position.xy,
+        view_z,
+    );
+    out.color = vec4<f32>(light.direct + local_light + light.ambient + emissive, albedo.a);
+    out.view_normal = vec4<f32>((camera.view * vec4<f32>(normal, 0.0)).xyz, 0.0);
+    out.ambient = vec4<f32>(light.ambient, 0.0);
+    return out;
+}
+
+// distance from the eye along the view axis
+fn view_depth(uv: vec2<f32>, depth: f32) -> f32 {
+    let view = camera.inv_proj * vec4<f32>(ndc_from_uv(uv), depth, 1.0);
+    return -view.z / view.w;
+}
+
+@fragment
+fn fs_debug(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    var color = vec3<f32>(0.0);
+    switch params.view {
+        case 1u: {
+            color = textureLoad(t_albedo, pixel, 0).rgb;
+        }
+        case 2u: {
+            color = textureLoad(t_world_normal, pixel, 0).xyz * 0.5 + 0.5;
+        }
+        case 3u: {
+            color = vec3<f32>(textureLoad(t_material, pixel, 0).rg, 0.0);
+        }
+        case 4u: {
+            color = textureLoad(t_emissive, pixel, 0).rgb;
+        }
+        case 5u: {
+            // linear from the eye to the far plane, the raw value is nearly all white
+            let far = view_depth(in.tex_coords, 1.0);
+            color = vec3<f32>(view_depth(in.tex_coords, textureLoad(t_depth, pixel, 0)) / far);
+        }
+        default: {}
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::texture::Texture;
+use crate::vertex::{ReflectedShader, VertexLayout};
+
+// Position only pipelines that fill the depth buffer with the opaque meshes before the
+// main pass. The renderer draws with them when the prepass is on, and draws opaque
+// meshes in the main pass with an equal depth test and no depth writes.
+pub struct DepthPrepass {
+    shader: ReflectedShader,
+    layout: wgpu::PipelineLayout,
+    // one per mesh vertex layout, built for the sample count of the main pass
+    pipelines: HashMap<VertexLayout, wgpu::RenderPipeline>,
+}
+
+impl DepthPrepass {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[VertexLayout],
+        instance_layout: &VertexLayout,
+        multisample: wgpu::MultisampleState,
+    ) -> Self {
+        let shader = ReflectedShader::new(
+            device,
+            include_str!("depth_prepass.wgsl"),
+            "Depth prepass shader",
+        )
+        .expect("error parsing depth prepass shader");
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth prepass pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let mut prepass = Self {
+            shader,
+            layout,
+            pipelines: HashMap::new(),
+        };
+        prepass.rebuild_pipelines(device, vertex_layouts, instance_layout, multisample);
+        prepass
+    }
+
+    // for when the meshes or the msaa sample count change
+    pub fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        vertex_layouts: &[VertexLayout],
+        instance_layout: &VertexLayout,
+        multisample: wgpu::MultisampleState,
+    ) {
+        self.pipelines.clear();
+        for vertex_layout in vertex_layouts {
+            if self.pipelines.contains_key(vertex_layout) {
+                continue;
+            }
+            let pipeline = self
+                .create_pipeline(device, vertex_layout, instance_layout, multisample)
+                .unwrap();
+            self.pipelines.insert(vertex_layout.clone(), pipeline);
+        }
+    }
+
+    pub fn pipeline(&self, vertex_layout: &VertexLayout) -> &wgpu::RenderPipeline {
+        &self.pipelines[vertex_layout]
+    }
+
+    // only the position of the vertex and the model matrix of the instance are fetched
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        vertex_layout: &VertexLayout,
+        instance_layout: &VertexLayout,
+        multisample: wgpu::MultisampleState,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        let (entry_point, matched_layouts) = self
+            .shader
+            .best_vertex_entry(&[vertex_layout.clone(), instance_layout.clone()])?;
+        let buffers = matched_layouts
+            .iter()
+            .map(VertexLayout::buffer_layout)
+            .collect::<Vec<_>>();
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth prepass pipeline"),
+            layout: Some(&self.layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader.module,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &buffers,
+            },
+            fragment: None,
+            // the same rasterization as the opaque mesh pipelines
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample,
+            multiview: None,
+            cache: None,
+        });
+        Ok(pipeline)
+    }
+}
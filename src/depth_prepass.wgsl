@@ -0,0 +1,39 @@
+// Depth only drawing of opaque meshes ahead of the main pass, which then only shades
+// the fragments that end up visible. Positions have to come out bit for bit the same
+// as transform_vertex in shader.wgsl for its equal depth test to pass, so the math
+// matches it and the position is invariant in both.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    instance: InstanceInput,
+) -> @builtin(position) @invariant vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+    return camera.view_proj * world_position;
+}
@@ -0,0 +1,10 @@
+// Copies the first sample of the multisampled depth buffer into the single sampled one
+// screen space passes read. Appended to fullscreen.wgsl for vs_main.
+
+@group(0) @binding(0)
+var t_depth: texture_depth_multisampled_2d;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @builtin(frag_depth) f32 {
+    return textureLoad(t_depth, vec2<i32>(in.clip_position.xy), 0);
+}
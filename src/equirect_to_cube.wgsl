@@ -0,0 +1,50 @@
+const PI: f32 = 3.14159265359;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) @interpolate(flat) face: u32,
+};
+
+// one fullscreen triangle per face, the instance index says which face is drawn
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) face: u32,
+) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.tex_coords = uv;
+    out.face = face;
+    return out;
+}
+
+// direction through a texel of a cube face, faces in +x -x +y -y +z -z layer order
+fn face_direction(face: u32, tex_coords: vec2<f32>) -> vec3<f32> {
+    let s = tex_coords.x * 2.0 - 1.0;
+    let t = tex_coords.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return vec3<f32>(1.0, -t, -s); }
+        case 1u: { return vec3<f32>(-1.0, -t, s); }
+        case 2u: { return vec3<f32>(s, 1.0, t); }
+        case 3u: { return vec3<f32>(s, -1.0, -t); }
+        case 4u: { return vec3<f32>(s, -t, 1.0); }
+        default: { return vec3<f32>(-s, -t, -1.0); }
+    }
+}
+
+@group(0) @binding(0)
+var t_equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var s_equirect: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(face_direction(in.face, in.tex_coords));
+    let equirect_coords = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    return vec4<f32>(textureSampleLevel(t_equirect, s_equirect, equirect_coords, 0.0).rgb, 1.0);
+}
@@ -0,0 +1,81 @@
+const BIN_COUNT: u32 = 256u;
+// pixels darker than this land in bin 0 and are left out of the average
+const MIN_LUMINANCE: f32 = 0.005;
+
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // how far the adapted luminance moves toward this frame's average, from 0 to 1
+    adaptation: f32,
+    pixel_count: f32,
+};
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<uniform> params: ExposureParams;
+@group(0) @binding(3)
+var<storage, read_write> adapted_luminance: f32;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+var<workgroup> weighted_bins: array<f32, 256>;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2125, 0.7154, 0.0721));
+}
+
+fn bin_index(lum: f32) -> u32 {
+    if lum < MIN_LUMINANCE {
+        return 0u;
+    }
+    let log_lum = clamp((log2(lum) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return u32(log_lum * 254.0 + 1.0);
+}
+
+// log luminance histogram of the frame, counted per workgroup first to keep the
+// global atomics down
+@compute @workgroup_size(16, 16, 1)
+fn histogram_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    atomicStore(&local_bins[local_index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(t_hdr);
+    if id.x < size.x && id.y < size.y {
+        let color = textureLoad(t_hdr, id.xy, 0).rgb;
+        atomicAdd(&local_bins[bin_index(luminance(color))], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[local_index], atomicLoad(&local_bins[local_index]));
+}
+
+// Averages the histogram, eases the adapted luminance toward it and clears the bins
+// for the next frame. Dispatched as a single workgroup.
+@compute @workgroup_size(256, 1, 1)
+fn average_main(@builtin(local_invocation_index) local_index: u32) {
+    let count = atomicLoad(&histogram[local_index]);
+    weighted_bins[local_index] = f32(count) * f32(local_index);
+    atomicStore(&histogram[local_index], 0u);
+    workgroupBarrier();
+
+    for (var stride = BIN_COUNT / 2u; stride > 0u; stride >>= 1u) {
+        if local_index < stride {
+            weighted_bins[local_index] += weighted_bins[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if local_index == 0u {
+        // thread 0 holds the count of the dark bin
+        let counted_pixels = max(params.pixel_count - f32(count), 1.0);
+        let average_bin = weighted_bins[0] / counted_pixels - 1.0;
+        let average_log = average_bin / 254.0 * params.log_luminance_range + params.min_log_luminance;
+        let average = exp2(average_log);
+        adapted_luminance += (average - adapted_luminance) * params.adaptation;
+    }
+}
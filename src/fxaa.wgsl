@@ -0,0 +1,147 @@
+// FXAA on the tonemapped image. Appended to fullscreen.wgsl for vs_main. Finds the
+// direction of the edge through each pixel, walks along it to both ends and resamples
+// across it by how far the pixel is from the closer end.
+
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const EDGE_THRESHOLD_MAX: f32 = 0.125;
+const SUBPIXEL_QUALITY: f32 = 0.75;
+const ITERATIONS: i32 = 12;
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_linear: sampler;
+
+// the target decodes srgb on load, the square root brings luma close to perceptual again
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.2126, 0.7152, 0.0722)));
+}
+
+fn sample_luma(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(t_color, s_linear, uv, 0.0).rgb);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // step multipliers for each iteration of the search along the edge
+    var quality = array<f32, 12>(1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+    let uv = in.tex_coords;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_color));
+    let color = textureSampleLevel(t_color, s_linear, uv, 0.0);
+    let luma_center = luma(color.rgb);
+    let luma_up = sample_luma(uv + vec2<f32>(0.0, -texel.y));
+    let luma_down = sample_luma(uv + vec2<f32>(0.0, texel.y));
+    let luma_left = sample_luma(uv + vec2<f32>(-texel.x, 0.0));
+    let luma_right = sample_luma(uv + vec2<f32>(texel.x, 0.0));
+
+    let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    let luma_range = luma_max - luma_min;
+    // no edge, or too little contrast to notice
+    if luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX) {
+        return color;
+    }
+
+    let luma_up_left = sample_luma(uv - texel);
+    let luma_down_right = sample_luma(uv + texel);
+    let luma_up_right = sample_luma(uv + vec2<f32>(texel.x, -texel.y));
+    let luma_down_left = sample_luma(uv + vec2<f32>(-texel.x, texel.y));
+
+    let luma_up_down = luma_up + luma_down;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_up_left + luma_down_left;
+    let luma_right_corners = luma_up_right + luma_down_right;
+    let luma_up_corners = luma_up_left + luma_up_right;
+    let luma_down_corners = luma_down_left + luma_down_right;
+
+    let edge_horizontal = abs(luma_left_corners - 2.0 * luma_left)
+        + abs(luma_up_down - 2.0 * luma_center) * 2.0
+        + abs(luma_right_corners - 2.0 * luma_right);
+    let edge_vertical = abs(luma_up_corners - 2.0 * luma_up)
+        + abs(luma_left_right - 2.0 * luma_center) * 2.0
+        + abs(luma_down_corners - 2.0 * luma_down);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // the neighbours on either side of the edge, 1 is towards negative uv
+    let luma1 = select(luma_left, luma_up, is_horizontal);
+    let luma2 = select(luma_right, luma_down, is_horizontal);
+    let gradient1 = luma1 - luma_center;
+    let gradient2 = luma2 - luma_center;
+    let is_1_steepest = abs(gradient1) >= abs(gradient2);
+    let gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average = 0.5 * (luma2 + luma_center);
+    if is_1_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma1 + luma_center);
+    }
+
+    // start half a pixel over, on the edge itself
+    var edge_uv = uv;
+    if is_horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+
+    // walk both ways until the luma along the edge changes as much as across it
+    var uv1 = edge_uv - offset;
+    var uv2 = edge_uv + offset;
+    var luma_end1 = sample_luma(uv1) - luma_local_average;
+    var luma_end2 = sample_luma(uv2) - luma_local_average;
+    var reached1 = abs(luma_end1) >= gradient_scaled;
+    var reached2 = abs(luma_end2) >= gradient_scaled;
+    if !reached1 {
+        uv1 -= offset;
+    }
+    if !reached2 {
+        uv2 += offset;
+    }
+    for (var i = 2; i < ITERATIONS && !(reached1 && reached2); i++) {
+        if !reached1 {
+            luma_end1 = sample_luma(uv1) - luma_local_average;
+        }
+        if !reached2 {
+            luma_end2 = sample_luma(uv2) - luma_local_average;
+        }
+        reached1 = abs(luma_end1) >= gradient_scaled;
+        reached2 = abs(luma_end2) >= gradient_scaled;
+        if !reached1 {
+            uv1 -= offset * quality[i];
+        }
+        if !reached2 {
+            uv2 += offset * quality[i];
+        }
+    }
+
+    let distance1 = select(uv.y - uv1.y, uv.x - uv1.x, is_horizontal);
+    let distance2 = select(uv2.y - uv.y, uv2.x - uv.x, is_horizontal);
+    let is_direction1 = distance1 < distance2;
+    let distance_final = min(distance1, distance2);
+    let edge_length = distance1 + distance2;
+    let pixel_offset = 0.5 - distance_final / edge_length;
+
+    // only blend when the closer end changes luma the way the center does, otherwise the
+    // pixel is past the end of the edge
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let end_is_smaller = select(luma_end2 < 0.0, luma_end1 < 0.0, is_direction1);
+    var final_offset = select(0.0, pixel_offset, end_is_smaller != is_luma_center_smaller);
+
+    // single pixel features get blurred by how much they stand out from the 3x3 average
+    let luma_average = (2.0 * (luma_up_down + luma_left_right) + luma_left_corners
+        + luma_right_corners) / 12.0;
+    let subpixel1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    let subpixel2 = (-2.0 * subpixel1 + 3.0) * subpixel1 * subpixel1;
+    final_offset = max(final_offset, subpixel2 * subpixel2 * SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    return textureSampleLevel(t_color, s_linear, final_uv, 0.0);
+}
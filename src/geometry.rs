@@ -0,0 +1,740 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use nalgebra::{Vector2, Vector3};
+
+// CPU side mesh with one vec per vertex channel. Optional channels are either
+// empty or the same length as positions.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub normals: Vec<[f32; 3]>,
+    // xyz is the tangent, w is the bitangent sign: bitangent = cross(normal, tangent) * w
+    pub tangents: Vec<[f32; 4]>,
+    pub colors: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+}
+
+// One vertex with every channel filled in, missing channels get neutral defaults
+#[derive(Copy, Clone, Debug)]
+pub struct VertexData {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    pub color: [f32; 4],
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum UvProjection {
+    // projects along the given axis, uvs span the mesh bounds
+    Planar { axis: Axis },
+    // planar projection along whichever axis the vertex normal faces most
+    Box,
+    Spherical,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl MeshData {
+    // Missing or short channels in the obj are dropped instead of indexed out of bounds.
+    pub fn from_obj(mesh: &tobj::Mesh) -> Self {
+        let vertex_count = mesh.positions.len() / 3;
+        let positions = mesh
+            .positions
+            .chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2]])
+            .collect();
+        let tex_coords = if mesh.texcoords.len() >= vertex_count * 2 {
+            mesh.texcoords
+                .chunks_exact(2)
+                .take(vertex_count)
+                .map(|t| [t[0], 1.0 - t[1]])
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let normals = if mesh.normals.len() >= vertex_count * 3 {
+            mesh.normals
+                .chunks_exact(3)
+                .take(vertex_count)
+                .map(|n| [n[0], n[1], n[2]])
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let colors = if mesh.vertex_color.len() >= vertex_count * 3 {
+            mesh.vertex_color
+                .chunks_exact(3)
+                .take(vertex_count)
+                .map(|c| [c[0], c[1], c[2], 1.0])
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            positions,
+            tex_coords,
+            normals,
+            tangents: Vec::new(),
+            colors,
+            indices: mesh.indices.clone(),
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn vertex(&self, i: usize) -> VertexData {
+        VertexData {
+            position: self.positions[i],
+            tex_coords: self.tex_coords.get(i).copied().unwrap_or([0.0, 0.0]),
+            normal: self.normals.get(i).copied().unwrap_or([0.0, 1.0, 0.0]),
+            tangent: self
+                .tangents
+                .get(i)
+                .copied()
+                .unwrap_or([1.0, 0.0, 0.0, 1.0]),
+            color: self.colors.get(i).copied().unwrap_or([1.0, 1.0, 1.0, 1.0]),
+        }
+    }
+
+    pub fn build_vertices<V: From<VertexData>>(&self) -> Vec<V> {
+        (0..self.vertex_count())
+            .map(|i| V::from(self.vertex(i)))
+            .collect()
+    }
+
+    fn triangles(&self) -> impl Iterator<Item = [usize; 3]> + '_ {
+        self.indices
+            .chunks_exact(3)
+            .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+    }
+
+    fn position(&self, i: usize) -> Vector3<f32> {
+        Vector3::from(self.positions[i])
+    }
+
+    // copies every channel this mesh has of vertex i onto the end of out
+    fn push_vertex(&self, i: usize, out: &mut MeshData) -> u32 {
+        out.positions.push(self.positions[i]);
+        if !self.tex_coords.is_empty() {
+            out.tex_coords.push(self.tex_coords[i]);
+        }
+        if !self.normals.is_empty() {
+            out.normals.push(self.normals[i]);
+        }
+        if !self.tangents.is_empty() {
+            out.tangents.push(self.tangents[i]);
+        }
+        if !self.colors.is_empty() {
+            out.colors.push(self.colors[i]);
+        }
+        (out.positions.len() - 1) as u32
+    }
+
+    // unnormalized, so the length is twice the triangle area
+    fn face_normal(&self, tri: [usize; 3]) -> Vector3<f32> {
+        let a = self.position(tri[0]);
+        let b = self.position(tri[1]);
+        let c = self.position(tri[2]);
+        (b - a).cross(&(c - a))
+    }
+
+    fn corner_angle(&self, tri: [usize; 3], corner: usize) -> f32 {
+        let p = self.position(tri[corner]);
+        let e1 = self.position(tri[(corner + 1) % 3]) - p;
+        let e2 = self.position(tri[(corner + 2) % 3]) - p;
+        let len = e1.norm() * e2.norm();
+        if len <= f32::EPSILON {
+            return 0.0;
+        }
+        (e1.dot(&e2) / len).clamp(-1.0, 1.0).acos()
+    }
+
+    // Duplicates every corner into its own vertex so each triangle gets its face normal.
+    pub fn generate_flat_normals(&mut self) {
+        let mut out = MeshData::default();
+        for tri in self.triangles() {
+            let normal = self
+                .face_normal(tri)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(Vector3::y);
+            for i in tri {
+                let index = self.push_vertex(i, &mut out);
+                out.indices.push(index);
+                if self.normals.is_empty() {
+                    out.normals.push(normal.into());
+                } else {
+                    out.normals[index as usize] = normal.into();
+                }
+            }
+        }
+        out.tangents.clear();
+        *self = out;
+    }
+
+    // Area and angle weighted vertex normals. Faces meeting at more than crease_angle
+    // (radians) keep a hard edge, which splits the shared vertex.
+    pub fn generate_smooth_normals(&mut self, crease_angle: f32) {
+        let triangles = self.triangles().collect::<Vec<_>>();
+        let face_normals = triangles
+            .iter()
+            .map(|&tri| self.face_normal(tri))
+            .collect::<Vec<_>>();
+        let face_units = face_normals
+            .iter()
+            .map(|n| n.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros))
+            .collect::<Vec<_>>();
+
+        // vertices split by uv seams still share a position and should be smoothed together
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let position_group = self
+            .positions
+            .iter()
+            .map(|p| {
+                let key = p.map(f32::to_bits);
+                let next = welded.len();
+                *welded.entry(key).or_insert(next)
+            })
+            .collect::<Vec<_>>();
+
+        // (triangle, corner) pairs touching each welded position
+        let mut group_corners = vec![Vec::new(); welded.len()];
+        for (t, tri) in triangles.iter().enumerate() {
+            for (corner, &i) in tri.iter().enumerate() {
+                group_corners[position_group[i]].push((t, corner));
+            }
+        }
+
+        let cos_crease = crease_angle.cos();
+        let mut corner_normals = vec![[0.0; 3]; triangles.len() * 3];
+        for corners in &group_corners {
+            for &(t, corner) in corners {
+                let mut sum = Vector3::zeros();
+                for &(other_t, other_corner) in corners {
+                    if face_units[t].dot(&face_units[other_t]) < cos_crease && t != other_t {
+                        continue;
+                    }
+                    let angle = self.corner_angle(triangles[other_t], other_corner);
+                    sum += face_normals[other_t] * angle;
+                }
+                let normal = sum
+                    .try_normalize(f32::EPSILON)
+                    .or_else(|| face_units[t].try_normalize(f32::EPSILON))
+                    .unwrap_or_else(Vector3::y);
+                corner_normals[t * 3 + corner] = normal.into();
+            }
+        }
+
+        // corners of the same vertex that ended up with the same normal share it again
+        let mut out = MeshData::default();
+        let mut remap: HashMap<(usize, [u32; 3]), u32> = HashMap::new();
+        for (t, tri) in triangles.iter().enumerate() {
+            for (corner, &i) in tri.iter().enumerate() {
+                let normal = corner_normals[t * 3 + corner];
+                let index = *remap
+                    .entry((i, normal.map(f32::to_bits)))
+                    .or_insert_with(|| {
+                        let index = self.push_vertex(i, &mut out);
+                        if self.normals.is_empty() {
+                            out.normals.push(normal);
+                        } else {
+                            out.normals[index as usize] = normal;
+                        }
+                        index
+                    });
+                out.indices.push(index);
+            }
+        }
+        out.tangents.clear();
+        *self = out;
+    }
+
+    // MikkTSpace tangents, the ones Blender and most bakers use, so normal maps baked
+    // against the same normals and uvs come out without seams. They are generated per
+    // corner, corners of one vertex that disagree (mirrored uvs) split the vertex. Needs
+    // normals, call after normal generation.
+    pub fn generate_tangents(&mut self) {
+        let triangles = self.triangles().collect::<Vec<_>>();
+        let mut geometry = TangentGeometry {
+            mesh: self,
+            corner_tangents: vec![[1.0, 0.0, 0.0, 1.0]; triangles.len() * 3],
+            triangles,
+        };
+        // fails only without triangles, which leaves nothing to split
+        mikktspace::generate_tangents(&mut geometry);
+        let TangentGeometry {
+            triangles,
+            corner_tangents,
+            ..
+        } = geometry;
+
+        let mut out = MeshData::default();
+        let mut remap: HashMap<(usize, [u32; 4]), u32> = HashMap::new();
+        for (t, tri) in triangles.iter().enumerate() {
+            for (corner, &i) in tri.iter().enumerate() {
+                let tangent = corner_tangents[t * 3 + corner];
+                let index = *remap
+                    .entry((i, tangent.map(f32::to_bits)))
+                    .or_insert_with(|| {
+                        let index = self.push_vertex(i, &mut out);
+                        if self.tangents.is_empty() {
+                            out.tangents.push(tangent);
+                        } else {
+                            out.tangents[index as usize] = tangent;
+                        }
+                        index
+                    });
+                out.indices.push(index);
+            }
+        }
+        *self = out;
+    }
+
+    // Replaces tex_coords with a projection of the positions. Box projection uses the
+    // normals when there are any, otherwise it falls back to the position's major axis.
+    pub fn generate_tex_coords(&mut self, projection: UvProjection) {
+        let (min, max) = self.bounds();
+        let extent = (max - min).map(|e| if e > f32::EPSILON { e } else { 1.0 });
+        let center = (min + max) * 0.5;
+
+        self.tex_coords = (0..self.vertex_count())
+            .map(|i| {
+                let p = self.position(i);
+                let local = (p - min).component_div(&extent);
+                let uv = match projection {
+                    UvProjection::Planar { axis } => planar_uv(&local, axis),
+                    UvProjection::Box => {
+                        let facing = match self.normals.get(i) {
+                            Some(n) => Vector3::from(*n),
+                            None => p - center,
+                        };
+                        planar_uv(&local, major_axis(&facing))
+                    }
+                    UvProjection::Spherical => {
+                        let d = (p - center)
+                            .try_normalize(f32::EPSILON)
+                            .unwrap_or_else(Vector3::y);
+                        Vector2::new(
+                            0.5 + d.z.atan2(d.x) / (2.0 * PI),
+                            d.y.clamp(-1.0, 1.0).acos() / PI,
+                        )
+                    }
+                };
+                [uv.x, uv.y]
+            })
+            .collect();
+        self.tangents.clear();
+    }
+
+    fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        if self.positions.is_empty() {
+            return (Vector3::zeros(), Vector3::zeros());
+        }
+        let mut min = Vector3::repeat(f32::MAX);
+        let mut max = Vector3::repeat(f32::MIN);
+        for p in &self.positions {
+            let p = Vector3::from(*p);
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        (min, max)
+    }
+}
+
+// what mikktspace reads the mesh through, one face per triangle
+struct TangentGeometry<'a> {
+    mesh: &'a MeshData,
+    triangles: Vec<[usize; 3]>,
+    corner_tangents: Vec<[f32; 4]>,
+}
+
+impl mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.mesh.positions[self.triangles[face][vert]]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.mesh.normals[self.triangles[face][vert]]
+    }
+
+    // bakers see v pointing up like the obj stores it, from_obj flipped it for wgpu
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let uv = self
+            .mesh
+            .tex_coords
+            .get(self.triangles[face][vert])
+            .copied()
+            .unwrap_or([0.0, 0.0]);
+        [uv[0], 1.0 - uv[1]]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corner_tangents[face * 3 + vert] = tangent;
+    }
+}
+
+// local is the position normalized to the mesh bounds
+fn planar_uv(local: &Vector3<f32>, axis: Axis) -> Vector2<f32> {
+    match axis {
+        Axis::X => Vector2::new(local.z, 1.0 - local.y),
+        Axis::Y => Vector2::new(local.x, local.z),
+        Axis::Z => Vector2::new(local.x, 1.0 - local.y),
+    }
+}
+
+fn major_axis(v: &Vector3<f32>) -> Axis {
+    let a = v.abs();
+    if a.x >= a.y && a.x >= a.z {
+        Axis::X
+    } else if a.y >= a.z {
+        Axis::Y
+    } else {
+        Axis::Z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    // a 2x2x2 cube around the origin with a quad of four vertices per face, the way an
+    // obj with uv seams comes in, uvs are in the flipped convention from_obj produces
+    fn split_cube() -> MeshData {
+        let x = Vector3::x();
+        let y = Vector3::y();
+        let z = Vector3::z();
+        // (normal, u, v) with u x v = normal so the quads wind counter clockwise
+        let faces = [
+            (x, y, z),
+            (-x, z, y),
+            (y, z, x),
+            (-y, x, z),
+            (z, x, y),
+            (-z, y, x),
+        ];
+        let mut mesh = MeshData::default();
+        for (normal, u, v) in faces {
+            let base = mesh.positions.len() as u32;
+            let corners = [
+                (normal - u - v, [0.0, 1.0]),
+                (normal + u - v, [1.0, 1.0]),
+                (normal + u + v, [1.0, 0.0]),
+                (normal - u + v, [0.0, 0.0]),
+            ];
+            for (position, uv) in corners {
+                mesh.positions.push(position.into());
+                mesh.tex_coords.push(uv);
+            }
+            mesh.indices
+                .extend([0, 1, 2, 0, 2, 3].iter().map(|i| base + i));
+        }
+        mesh
+    }
+
+    // the same cube with one vertex per corner position and no other channels
+    fn shared_cube() -> MeshData {
+        let split = split_cube();
+        let mut mesh = MeshData::default();
+        let mut shared: HashMap<[u32; 3], u32> = HashMap::new();
+        for &i in &split.indices {
+            let position = split.positions[i as usize];
+            let index = *shared.entry(position.map(f32::to_bits)).or_insert_with(|| {
+                mesh.positions.push(position);
+                (mesh.positions.len() - 1) as u32
+            });
+            mesh.indices.push(index);
+        }
+        mesh
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).norm() < EPSILON, "{a:?} != {b:?}");
+    }
+
+    fn triangle_center(mesh: &MeshData, tri: [usize; 3]) -> Vector3<f32> {
+        tri.iter().map(|&i| mesh.position(i)).sum::<Vector3<f32>>() / 3.0
+    }
+
+    // the face normal of a cube face is the axis its center lies furthest along
+    fn cube_face_normal(center: Vector3<f32>) -> Vector3<f32> {
+        let mut normal = Vector3::zeros();
+        let axis = center.iamax();
+        normal[axis] = center[axis].signum();
+        normal
+    }
+
+    #[test]
+    fn flat_normals_give_every_corner_its_face_normal() {
+        let mut mesh = shared_cube();
+        assert_eq!(mesh.vertex_count(), 8);
+        mesh.generate_flat_normals();
+
+        assert_eq!(mesh.vertex_count(), 36);
+        assert_eq!(mesh.normals.len(), 36);
+        for tri in mesh.triangles() {
+            let expected = cube_face_normal(triangle_center(&mesh, tri));
+            for i in tri {
+                assert_near(Vector3::from(mesh.normals[i]), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_normals_keep_other_channels() {
+        let mut mesh = split_cube();
+        mesh.colors = vec![[1.0, 0.0, 0.0, 1.0]; mesh.vertex_count()];
+        mesh.generate_flat_normals();
+
+        assert_eq!(mesh.tex_coords.len(), 36);
+        assert_eq!(mesh.colors.len(), 36);
+        assert!(mesh.tangents.is_empty());
+    }
+
+    #[test]
+    fn smooth_normals_keep_edges_sharper_than_the_crease_angle() {
+        let mut mesh = split_cube();
+        mesh.generate_smooth_normals(30f32.to_radians());
+
+        assert_eq!(mesh.vertex_count(), 24);
+        for tri in mesh.triangles() {
+            let expected = cube_face_normal(triangle_center(&mesh, tri));
+            for i in tri {
+                assert_near(Vector3::from(mesh.normals[i]), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_normals_split_shared_vertices_at_hard_edges() {
+        let mut mesh = shared_cube();
+        mesh.generate_smooth_normals(30f32.to_radians());
+
+        // each of the 8 corners gets one vertex per face meeting there
+        assert_eq!(mesh.vertex_count(), 24);
+    }
+
+    #[test]
+    fn smooth_normals_weld_vertices_split_by_uv_seams() {
+        let mut mesh = split_cube();
+        mesh.generate_smooth_normals(100f32.to_radians());
+
+        // the uv seams stay, but every vertex at a corner points out of that corner
+        assert_eq!(mesh.vertex_count(), 24);
+        for i in 0..mesh.vertex_count() {
+            let expected = mesh.position(i).normalize();
+            assert_near(Vector3::from(mesh.normals[i]), expected);
+        }
+    }
+
+    #[test]
+    fn smooth_normals_share_vertices_that_agree() {
+        let mut mesh = shared_cube();
+        mesh.generate_smooth_normals(100f32.to_radians());
+
+        assert_eq!(mesh.vertex_count(), 8);
+        assert_eq!(mesh.indices.len(), 36);
+        for i in 0..mesh.vertex_count() {
+            assert_near(Vector3::from(mesh.normals[i]), mesh.position(i).normalize());
+        }
+    }
+
+    // two quads facing +z that share their middle edge, with the uvs of the right one
+    // mirrored so u runs back towards the edge
+    fn mirrored_quad() -> MeshData {
+        MeshData {
+            positions: vec![
+                [-1.0, 0.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [-1.0, 1.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+            ],
+            tex_coords: vec![
+                [0.0, 1.0],
+                [1.0, 1.0],
+                [1.0, 0.0],
+                [0.0, 0.0],
+                [0.0, 1.0],
+                [0.0, 0.0],
+            ],
+            normals: vec![[0.0, 0.0, 1.0]; 6],
+            indices: vec![0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u_in_the_normal_plane() {
+        let mut mesh = split_cube();
+        mesh.generate_flat_normals();
+        mesh.generate_tangents();
+
+        assert_eq!(mesh.tangents.len(), mesh.vertex_count());
+        for tri in mesh.triangles() {
+            let [a, b, c] = tri.map(|i| mesh.position(i));
+            let [uv_a, uv_b, uv_c] = tri.map(|i| mesh.tex_coords[i]);
+            for i in tri {
+                let normal = Vector3::from(mesh.normals[i]);
+                let [x, y, z, w] = mesh.tangents[i];
+                let tangent = Vector3::new(x, y, z);
+                assert!((tangent.norm() - 1.0).abs() < EPSILON);
+                assert!(tangent.dot(&normal).abs() < EPSILON);
+                assert_eq!(w, 1.0);
+                // u increases along the tangent on every face
+                let du = [(b - a, uv_b[0] - uv_a[0]), (c - a, uv_c[0] - uv_a[0])];
+                for (edge, du) in du {
+                    assert!((edge.dot(&tangent) - du * 2.0).abs() < EPSILON);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_split_vertices_on_mirrored_uvs() {
+        let mut mesh = mirrored_quad();
+        mesh.generate_tangents();
+
+        // the two vertices on the mirror edge get one copy per side
+        assert_eq!(mesh.vertex_count(), 8);
+        for tri in mesh.triangles() {
+            let mirrored = triangle_center(&mesh, tri).x > 0.0;
+            for i in tri {
+                let [x, y, z, w] = mesh.tangents[i];
+                let tangent = Vector3::new(x, y, z);
+                let normal = Vector3::from(mesh.normals[i]);
+                if mirrored {
+                    assert_near(tangent, -Vector3::x());
+                    assert_eq!(w, -1.0);
+                } else {
+                    assert_near(tangent, Vector3::x());
+                    assert_eq!(w, 1.0);
+                }
+                // the bitangent points up the texture on both sides
+                assert_near(normal.cross(&tangent) * w, Vector3::y());
+            }
+        }
+    }
+
+    fn assert_uv(uv: [f32; 2], expected: [f32; 2]) {
+        let d = Vector2::from(uv) - Vector2::from(expected);
+        assert!(d.norm() < EPSILON, "{uv:?} != {expected:?}");
+    }
+
+    #[test]
+    fn planar_uvs_span_the_bounds() {
+        let mut mesh = split_cube();
+        mesh.generate_tex_coords(UvProjection::Planar { axis: Axis::Z });
+
+        for i in 0..mesh.vertex_count() {
+            let [x, y, _] = mesh.positions[i];
+            assert_uv(mesh.tex_coords[i], [(x + 1.0) / 2.0, (1.0 - y) / 2.0]);
+        }
+    }
+
+    #[test]
+    fn box_uvs_project_each_face_along_its_normal() {
+        let mut mesh = split_cube();
+        mesh.generate_flat_normals();
+        mesh.generate_tangents();
+        mesh.generate_tex_coords(UvProjection::Box);
+
+        // projecting along the wrong axis would squash a face onto an edge of the square
+        for face in mesh.indices.chunks_exact(6) {
+            let mut corners = face
+                .iter()
+                .map(|&i| mesh.tex_coords[i as usize].map(|c| c.round() as u32))
+                .collect::<Vec<_>>();
+            corners.sort();
+            corners.dedup();
+            assert_eq!(corners, [[0, 0], [0, 1], [1, 0], [1, 1]]);
+        }
+        // they are stale once the uvs change
+        assert!(mesh.tangents.is_empty());
+    }
+
+    #[test]
+    fn spherical_uvs_wrap_around_the_center() {
+        let mut mesh = MeshData {
+            positions: vec![
+                [0.0, 1.0, 0.0],
+                [0.0, -1.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [-1.0, 0.0, 0.0],
+                [0.0, 0.0, -1.0],
+            ],
+            ..Default::default()
+        };
+        mesh.generate_tex_coords(UvProjection::Spherical);
+
+        assert_eq!(mesh.tex_coords[0][1], 0.0);
+        assert_eq!(mesh.tex_coords[1][1], 1.0);
+        assert_uv(mesh.tex_coords[2], [0.5, 0.5]);
+        assert_uv(mesh.tex_coords[3], [0.75, 0.5]);
+        assert_uv(mesh.tex_coords[4], [1.0, 0.5]);
+        assert_uv(mesh.tex_coords[5], [0.25, 0.5]);
+    }
+
+    #[test]
+    fn from_obj_drops_missing_and_short_channels() {
+        let obj = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            // one uv for three vertices
+            texcoords: vec![0.5, 0.5],
+            vertex_color: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let mesh = MeshData::from_obj(&obj);
+
+        assert_eq!(mesh.vertex_count(), 3);
+        assert!(mesh.tex_coords.is_empty());
+        assert!(mesh.normals.is_empty());
+        assert!(mesh.tangents.is_empty());
+        assert_eq!(mesh.colors[1], [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+
+        let vertex = mesh.vertex(2);
+        assert_eq!(vertex.tex_coords, [0.0, 0.0]);
+        assert_eq!(vertex.normal, [0.0, 1.0, 0.0]);
+        assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(vertex.color, [0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn from_obj_flips_v_and_keeps_full_channels() {
+        let obj = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            texcoords: vec![0.0, 0.0, 1.0, 0.0, 0.0, 0.25],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let mesh = MeshData::from_obj(&obj);
+
+        assert_eq!(mesh.tex_coords, [[0.0, 1.0], [1.0, 1.0], [0.0, 0.75]]);
+        assert_eq!(mesh.normals, [[0.0, 0.0, 1.0]; 3]);
+        assert!(mesh.colors.is_empty());
+        assert_eq!(mesh.vertex(0).color, [1.0; 4]);
+    }
+}
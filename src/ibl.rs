@@ -0,0 +1,634 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::bail;
+
+use crate::sampler::{SamplerCache, SamplerDesc};
+use crate::skybox::Cubemap;
+use crate::texture::{MipmapGenerator, Texture, TextureRole};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+// the last mip is 8 texels wide and holds the fully rough reflections
+const PREFILTERED_MIP_COUNT: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+const WORKGROUP_SIZE: u32 = 8;
+
+const CACHE_MAGIC: &[u8; 4] = b"IBL2";
+// stands for FORMAT in the cache headers, change it along with FORMAT
+const FORMAT_CODE: u32 = 1;
+// width, height, layers, mip levels and format of each map, in the order they're cached
+const CACHE_HEADERS: [[u32; 5]; 3] = [
+    [
+        IRRADIANCE_SIZE,
+        IRRADIANCE_SIZE,
+        Cubemap::FACE_COUNT,
+        1,
+        FORMAT_CODE,
+    ],
+    [
+        PREFILTERED_SIZE,
+        PREFILTERED_SIZE,
+        Cubemap::FACE_COUNT,
+        PREFILTERED_MIP_COUNT,
+        FORMAT_CODE,
+    ],
+    [BRDF_LUT_SIZE, BRDF_LUT_SIZE, 1, 1, FORMAT_CODE],
+];
+
+// Everything the main shader needs for image based lighting: diffuse irradiance, a
+// specular cubemap with roughness going up with the mip level, and the split sum
+// brdf lookup table.
+pub struct IblMaps {
+    pub irradiance: Cubemap,
+    pub prefiltered: Cubemap,
+    pub brdf_lut: Texture,
+}
+
+impl IblMaps {
+    // Runs the compute passes over an environment cubemap, usually the skybox's.
+    pub fn from_environment(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &Cubemap,
+        samplers: &mut SamplerCache,
+    ) -> Self {
+        let generator = IblGenerator::new(device);
+        let irradiance = create_cube_texture(device, IRRADIANCE_SIZE, 1, "irradiance");
+        let prefiltered = create_cube_texture(
+            device,
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_COUNT,
+            "prefiltered_environment",
+        );
+        let brdf_lut = create_brdf_lut_texture(device);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL encoder"),
+        });
+        generator.irradiance(device, &mut encoder, environment, &irradiance);
+        generator.prefilter(device, &mut encoder, environment, &prefiltered);
+        generator.brdf_lut(device, &mut encoder, &brdf_lut);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self::from_textures(device, irradiance, prefiltered, brdf_lut, samplers)
+    }
+
+    // Flat ambient light of one color, for scenes without an environment map.
+    pub fn uniform(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [f32; 3],
+        samplers: &mut SamplerCache,
+        mipmaps: &mut MipmapGenerator,
+    ) -> Self {
+        let face = image::DynamicImage::ImageRgba32F(image::Rgba32FImage::from_pixel(
+            1,
+            1,
+            image::Rgba([color[0], color[1], color[2], 1.0]),
+        ));
+        let faces = std::array::from_fn(|_| face.clone());
+        let sampler = samplers.get(device, SamplerDesc::TRILINEAR_CLAMP);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Uniform environment encoder"),
+        });
+        let [irradiance, prefiltered] = ["irradiance", "prefiltered_environment"].map(|label| {
+            Cubemap::from_faces(
+                device,
+                queue,
+                &mut encoder,
+                mipmaps,
+                &faces,
+                TextureRole::Hdr,
+                Some(label),
+                sampler.clone(),
+            )
+            .expect("uniform faces are all 1x1")
+            .texture
+        });
+
+        let brdf_lut = create_brdf_lut_texture(device);
+        IblGenerator::new(device).brdf_lut(device, &mut encoder, &brdf_lut);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self::from_textures(device, irradiance, prefiltered, brdf_lut, samplers)
+    }
+
+    // Loads the maps from cache_path when it was written for the same source, otherwise
+    // generates them and rewrites the cache. source is the file the environment was
+    // made from, only its hash is kept. A cache that can't be written is only a warning.
+    pub fn from_environment_cached(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &Cubemap,
+        source: &[u8],
+        cache_path: &Path,
+        samplers: &mut SamplerCache,
+    ) -> anyhow::Result<Self> {
+        let source_hash = source_hash(source);
+        if cache_path.exists() {
+            match Self::load(device, queue, cache_path, source_hash, samplers) {
+                Ok(maps) => return Ok(maps),
+                Err(e) => log::info!("regenerating {}: {e}", cache_path.display()),
+            }
+        }
+        let maps = Self::from_environment(device, queue, environment, samplers);
+        if let Err(e) = maps.save(device, queue, cache_path, source_hash) {
+            log::warn!("error writing ibl cache {}: {e}", cache_path.display());
+        }
+        Ok(maps)
+    }
+
+    pub fn save(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+        source_hash: u64,
+    ) -> anyhow::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        file.write_all(CACHE_MAGIC)?;
+        file.write_all(&source_hash.to_le_bytes())?;
+        for texture in [
+            &self.irradiance.texture,
+            &self.prefiltered.texture,
+            &self.brdf_lut.texture,
+        ] {
+            for value in [
+                texture.width(),
+                texture.height(),
+                texture.depth_or_array_layers(),
+                texture.mip_level_count(),
+                FORMAT_CODE,
+            ] {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            for level in read_texture(device, queue, texture)? {
+                file.write_all(&level)?;
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+        source_hash: u64,
+        samplers: &mut SamplerCache,
+    ) -> anyhow::Result<Self> {
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != CACHE_MAGIC {
+            bail!("{} is not an ibl cache", path.display());
+        }
+        let mut hash = [0u8; 8];
+        file.read_exact(&mut hash)?;
+        if u64::from_le_bytes(hash) != source_hash {
+            bail!("{} was made from a different environment", path.display());
+        }
+
+        let mut textures = Vec::new();
+        let labels = ["irradiance", "prefiltered_environment", "brdf_lut"];
+        for (label, expected) in labels.into_iter().zip(CACHE_HEADERS) {
+            let mut header = [0u32; 5];
+            for value in &mut header {
+                let mut bytes = [0u8; 4];
+                file.read_exact(&mut bytes)?;
+                *value = u32::from_le_bytes(bytes);
+            }
+            // sizes come from the file, a stale or corrupt one mustn't pick them
+            if header != expected {
+                bail!(
+                    "{label} in {} is {header:?}, expected {expected:?}",
+                    path.display()
+                );
+            }
+            let [width, height, layers, mip_level_count, _] = header;
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: usage(),
+                view_formats: &[],
+            });
+            for mip_level in 0..mip_level_count {
+                let size = texture
+                    .size()
+                    .mip_level_size(mip_level, wgpu::TextureDimension::D2);
+                let bytes_per_row = size.width * texel_size();
+                let mut data = vec![0u8; (bytes_per_row * size.height * layers) as usize];
+                file.read_exact(&mut data)?;
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d::ZERO,
+                    },
+                    &data,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(bytes_per_row),
+                        rows_per_image: Some(size.height),
+                    },
+                    size,
+                );
+            }
+            textures.push(texture);
+        }
+        let [irradiance, prefiltered, brdf_lut]: [wgpu::Texture; 3] =
+            textures.try_into().expect("three textures were read");
+        Ok(Self::from_textures(
+            device,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            samplers,
+        ))
+    }
+
+    fn from_textures(
+        device: &wgpu::Device,
+        irradiance: wgpu::Texture,
+        prefiltered: wgpu::Texture,
+        brdf_lut: wgpu::Texture,
+        samplers: &mut SamplerCache,
+    ) -> Self {
+        // all three maps are sampled with the same sampler in the main shader
+        let sampler = samplers.get(device, SamplerDesc::TRILINEAR_CLAMP);
+        Self {
+            irradiance: Cubemap::from_texture(irradiance, sampler.clone()),
+            prefiltered: Cubemap::from_texture(prefiltered, sampler.clone()),
+            brdf_lut: Texture {
+                view: brdf_lut.create_view(&wgpu::TextureViewDescriptor::default()),
+                texture: brdf_lut,
+                sampler,
+            },
+        }
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::Cube),
+                texture_entry(1, wgpu::TextureViewDimension::Cube),
+                texture_entry(2, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("environment_bind_group_layout"),
+        })
+    }
+
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.irradiance.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.prefiltered.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.brdf_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.prefiltered.sampler),
+                },
+            ],
+            label: Some("environment_bind_group"),
+        })
+    }
+}
+
+// 64 bit fnv-1a, stable between builds unlike std's hasher
+fn source_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn usage() -> wgpu::TextureUsages {
+    // copy src and dst so the maps can be written to and read back from the disk cache
+    wgpu::TextureUsages::TEXTURE_BINDING
+        | wgpu::TextureUsages::STORAGE_BINDING
+        | wgpu::TextureUsages::COPY_SRC
+        | wgpu::TextureUsages::COPY_DST
+}
+
+fn texel_size() -> u32 {
+    FORMAT
+        .block_copy_size(None)
+        .expect("ibl maps use a color format")
+}
+
+fn create_cube_texture(
+    device: &wgpu::Device,
+    size: u32,
+    mip_level_count: u32,
+    label: &str,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: Cubemap::FACE_COUNT,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: usage(),
+        view_formats: &[],
+    })
+}
+
+fn create_brdf_lut_texture(device: &wgpu::Device) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("brdf_lut"),
+        size: wgpu::Extent3d {
+            width: BRDF_LUT_SIZE,
+            height: BRDF_LUT_SIZE,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: usage(),
+        view_formats: &[],
+    })
+}
+
+// Copies every mip of the texture back to the cpu, each level with all of its layers
+// and rows tightly packed.
+fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let layers = texture.depth_or_array_layers();
+    let mut levels = Vec::new();
+    for mip_level in 0..texture.mip_level_count() {
+        let size = texture
+            .size()
+            .mip_level_size(mip_level, wgpu::TextureDimension::D2);
+        let unpadded_bytes_per_row = size.width * texel_size();
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_bytes_per_row * size.height * layers) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mapped = slice.get_mapped_range();
+        let level = mapped
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect();
+        drop(mapped);
+        buffer.unmap();
+        levels.push(level);
+    }
+    Ok(levels)
+}
+
+struct IblGenerator {
+    irradiance_pipeline: wgpu::ComputePipeline,
+    prefilter_pipeline: wgpu::ComputePipeline,
+    brdf_lut_pipeline: wgpu::ComputePipeline,
+    environment_sampler: wgpu::Sampler,
+}
+
+impl IblGenerator {
+    fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("IBL shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ibl.wgsl").into()),
+        });
+        let pipeline = |entry_point, label| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &shader,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let environment_sampler = device.create_sampler(&SamplerDesc::TRILINEAR_CLAMP.descriptor());
+        Self {
+            irradiance_pipeline: pipeline("irradiance_main", "Irradiance pipeline"),
+            prefilter_pipeline: pipeline("prefilter_main", "Prefilter pipeline"),
+            brdf_lut_pipeline: pipeline("brdf_lut_main", "BRDF LUT pipeline"),
+            environment_sampler,
+        }
+    }
+
+    fn irradiance(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        environment: &Cubemap,
+        target: &wgpu::Texture,
+    ) {
+        let target_view = storage_view(target, 0);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.irradiance_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.environment_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&target_view),
+                },
+            ],
+            label: None,
+        });
+        dispatch(
+            encoder,
+            &self.irradiance_pipeline,
+            &bind_group,
+            target.width(),
+            Cubemap::FACE_COUNT,
+        );
+    }
+
+    fn prefilter(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        environment: &Cubemap,
+        target: &wgpu::Texture,
+    ) {
+        use wgpu::util::DeviceExt;
+
+        let mip_level_count = target.mip_level_count();
+        for mip_level in 0..mip_level_count {
+            let roughness = mip_level as f32 / (mip_level_count - 1).max(1) as f32;
+            // padded to the 16 bytes uniform buffers are bound in
+            let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Prefilter params"),
+                contents: bytemuck::cast_slice(&[roughness, 0.0, 0.0, 0.0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let target_view = storage_view(target, mip_level);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.prefilter_pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&environment.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.environment_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&target_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+                label: None,
+            });
+            dispatch(
+                encoder,
+                &self.prefilter_pipeline,
+                &bind_group,
+                (target.width() >> mip_level).max(1),
+                Cubemap::FACE_COUNT,
+            );
+        }
+    }
+
+    fn brdf_lut(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Texture,
+    ) {
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.brdf_lut_pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&target_view),
+            }],
+            label: None,
+        });
+        dispatch(
+            encoder,
+            &self.brdf_lut_pipeline,
+            &bind_group,
+            target.width(),
+            1,
+        );
+    }
+}
+
+// every face of one mip, for writing from a compute shader
+fn storage_view(texture: &wgpu::Texture, mip_level: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("IBL storage view"),
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip_level,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
+
+fn dispatch(
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &wgpu::ComputePipeline,
+    bind_group: &wgpu::BindGroup,
+    size: u32,
+    layers: u32,
+) {
+    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+        label: Some("IBL pass"),
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    let workgroups = size.div_ceil(WORKGROUP_SIZE);
+    pass.dispatch_workgroups(workgroups, workgroups, layers);
+}
@@ -0,0 +1,178 @@
+const PI: f32 = 3.14159265359;
+const SPECULAR_SAMPLES: u32 = 1024u;
+const IRRADIANCE_PHI_STEPS: u32 = 120u;
+const IRRADIANCE_THETA_STEPS: u32 = 30u;
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+@group(0) @binding(2)
+var irradiance_out: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(3)
+var prefiltered_out: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(4)
+var<uniform> prefilter: PrefilterParams;
+@group(0) @binding(5)
+var brdf_lut_out: texture_storage_2d<rgba16float, write>;
+
+struct PrefilterParams {
+    roughness: f32,
+};
+
+// same face convention as equirect_to_cube.wgsl, +x -x +y -y +z -z layer order
+fn face_direction(face: u32, tex_coords: vec2<f32>) -> vec3<f32> {
+    let s = tex_coords.x * 2.0 - 1.0;
+    let t = tex_coords.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return vec3<f32>(1.0, -t, -s); }
+        case 1u: { return vec3<f32>(-1.0, -t, s); }
+        case 2u: { return vec3<f32>(s, 1.0, t); }
+        case 3u: { return vec3<f32>(s, -1.0, -t); }
+        case 4u: { return vec3<f32>(s, -t, 1.0); }
+        default: { return vec3<f32>(-s, -t, -1.0); }
+    }
+}
+
+fn texel_direction(id: vec3<u32>, size: vec2<u32>) -> vec3<f32> {
+    let tex_coords = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    return normalize(face_direction(id.z, tex_coords));
+}
+
+// orthonormal basis around n, stable for every direction
+fn tangent_to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var up = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(n.z) < 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return tangent * v.x + bitangent * v.y + n * v.z;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// half vector distributed like the ggx lobe around n
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return normalize(tangent_to_world(h, n));
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// cosine weighted hemisphere integral of the environment, pre-multiplied by pi so the
+// main shader only multiplies by albedo
+@compute @workgroup_size(8, 8, 1)
+fn irradiance_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(irradiance_out);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let n = texel_direction(id, size);
+
+    // the result is blurry anyway, a level around 64 texels wide keeps the sampling cheap
+    let environment_levels = f32(textureNumLevels(t_environment));
+    let level = max(environment_levels - 7.0, 0.0);
+
+    var irradiance = vec3<f32>(0.0);
+    for (var i = 0u; i < IRRADIANCE_PHI_STEPS; i++) {
+        let phi = (f32(i) + 0.5) / f32(IRRADIANCE_PHI_STEPS) * 2.0 * PI;
+        for (var j = 0u; j < IRRADIANCE_THETA_STEPS; j++) {
+            let theta = (f32(j) + 0.5) / f32(IRRADIANCE_THETA_STEPS) * 0.5 * PI;
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let direction = tangent_to_world(local, n);
+            let radiance = textureSampleLevel(t_environment, s_environment, direction, level).rgb;
+            irradiance += radiance * cos(theta) * sin(theta);
+        }
+    }
+    irradiance = PI * irradiance / f32(IRRADIANCE_PHI_STEPS * IRRADIANCE_THETA_STEPS);
+    textureStore(irradiance_out, id.xy, id.z, vec4<f32>(irradiance, 1.0));
+}
+
+// One mip of the specular map. Assumes the view direction equals the normal, and reads
+// blurrier source mips for samples that cover more solid angle to avoid fireflies.
+@compute @workgroup_size(8, 8, 1)
+fn prefilter_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(prefiltered_out);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let n = texel_direction(id, size);
+    let roughness = prefilter.roughness;
+
+    if roughness == 0.0 {
+        let radiance = textureSampleLevel(t_environment, s_environment, n, 0.0).rgb;
+        textureStore(prefiltered_out, id.xy, id.z, vec4<f32>(radiance, 1.0));
+        return;
+    }
+
+    let environment_size = f32(textureDimensions(t_environment).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * environment_size * environment_size);
+
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < SPECULAR_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, SPECULAR_SAMPLES), n, roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(dot(n, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, roughness) * 0.25 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(SPECULAR_SAMPLES) * pdf);
+            let level = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            color += textureSampleLevel(t_environment, s_environment, l, level).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+    textureStore(prefiltered_out, id.xy, id.z, vec4<f32>(color / max(total_weight, 0.0001), 1.0));
+}
+
+fn geometry_schlick_ggx_ibl(n_dot_x: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness * 0.5;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// Split sum brdf integral, x is n dot v and y is roughness. Red is the scale and green
+// the bias applied to F0.
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(brdf_lut_out);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let n_dot_v = (f32(id.x) + 0.5) / f32(size.x);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < SPECULAR_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, SPECULAR_SAMPLES), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(h.z, 0.0);
+            let v_dot_h = max(dot(v, h), 0.0);
+            let g = geometry_schlick_ggx_ibl(n_dot_v, roughness)
+                * geometry_schlick_ggx_ibl(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    let result = vec2<f32>(scale, bias) / f32(SPECULAR_SAMPLES);
+    textureStore(brdf_lut_out, id.xy, vec4<f32>(result, 0.0, 1.0));
+}
@@ -0,0 +1,450 @@
+use nalgebra::{Point3, Vector3};
+
+use crate::camera::Camera;
+use crate::post_process;
+use crate::texture::Texture;
+
+// lights past this are ignored
+pub const MAX_LIGHTS: usize = 1024;
+// must match MAX_LIGHTS_PER_CLUSTER in cluster.wgsl, a cluster drops lights past it
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+// tiles across and down the screen, and slices in depth
+const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+const CLUSTER_COUNT: u32 = CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2];
+// matches @workgroup_size in cluster_assign.wgsl
+const WORKGROUP_SIZE: u32 = 4;
+
+// Prepends the lighting every scene shader shares, the directional light, environment
+// and clustered lights, to source.
+pub fn scene_shader_source(source: &str) -> String {
+    format!(
+        "{}\n{}\n{}\n{}",
+        include_str!("pbr.wgsl"),
+        include_str!("cluster.wgsl"),
+        include_str!("lights.wgsl"),
+        source
+    )
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum LightKind {
+    Point,
+    // the cone is fully lit inside inner_angle and fades out to outer_angle, both in
+    // radians from direction
+    Spot {
+        direction: Vector3<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Point3<f32>,
+    // linear color times intensity
+    pub color: Vector3<f32>,
+    // nothing past this distance is lit, smaller ranges land in fewer clusters
+    pub range: f32,
+}
+
+impl Light {
+    pub fn point(position: Point3<f32>, color: Vector3<f32>, range: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            color,
+            range,
+        }
+    }
+
+    pub fn spot(
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        color: Vector3<f32>,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                direction,
+                inner_angle,
+                outer_angle,
+            },
+            position,
+            color,
+            range,
+        }
+    }
+
+    fn to_raw(self) -> LightRaw {
+        // the cone factor is dot * scale + offset, 1 at the inner angle and 0 at the outer
+        let (direction, spot_scale, spot_offset) = match self.kind {
+            LightKind::Point => (Vector3::zeros(), 0.0, 1.0),
+            LightKind::Spot {
+                direction,
+                inner_angle,
+                outer_angle,
+            } => {
+                let cos_outer = outer_angle.cos();
+                let scale = 1.0 / (inner_angle.cos() - cos_outer).max(0.0001);
+                (direction.normalize(), scale, -cos_outer * scale)
+            }
+        };
+        LightRaw {
+            position: self.position.into(),
+            range: self.range.max(0.0001),
+            color: self.color.into(),
+            spot_scale,
+            direction: direction.into(),
+            spot_offset,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    spot_scale: f32,
+    direction: [f32; 3],
+    spot_offset: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterParams {
+    grid_size: [u32; 3],
+    light_count: u32,
+    screen_size: [f32; 2],
+    near: f32,
+    far: f32,
+}
+
+// Point and spot lights for clustered forward shading. update assigns the lights to
+// clusters with a compute pass every frame, the scene shaders then only loop over the
+// lights of the cluster a pixel falls in. bind_group goes into group 3 of every
+// pipeline built on scene_shader_source.
+pub struct ClusteredLights {
+    pub lights: Vec<Light>,
+    // draws the number of lights per cluster over the final image
+    pub heatmap: bool,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    lights_buffer: wgpu::Buffer,
+    counts_buffer: wgpu::Buffer,
+    assign_pipeline: wgpu::ComputePipeline,
+    assign_bind_group: wgpu::BindGroup,
+    heatmap_pipeline: wgpu::RenderPipeline,
+    heatmap_bind_group_layout: wgpu::BindGroupLayout,
+    heatmap_bind_group: wgpu::BindGroup,
+}
+
+impl ClusteredLights {
+    // depth is the single sampled depth texture the heatmap reads
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        camera_buffer: &wgpu::Buffer,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        depth: &Texture,
+    ) -> Self {
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster params buffer"),
+            size: std::mem::size_of::<ClusterParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let lights_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lights buffer"),
+            size: (MAX_LIGHTS * std::mem::size_of::<LightRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let counts_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster light counts buffer"),
+            size: (CLUSTER_COUNT * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let indices_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster light indices buffer"),
+            size: (CLUSTER_COUNT * MAX_LIGHTS_PER_CLUSTER * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let assign_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cluster assign shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                format!(
+                    "{}\n{}",
+                    include_str!("cluster.wgsl"),
+                    include_str!("cluster_assign.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let assign_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cluster assign pipeline"),
+            layout: None,
+            module: &assign_shader,
+            entry_point: "assign_lights",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let assign_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &assign_pipeline.get_bind_group_layout(0),
+            entries: &[
+                camera_buffer.as_entire_binding(),
+                params_buffer.as_entire_binding(),
+                lights_buffer.as_entire_binding(),
+                counts_buffer.as_entire_binding(),
+                indices_buffer.as_entire_binding(),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect::<Vec<_>>(),
+            label: Some("cluster_assign_bind_group"),
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry,
+                storage_entry(1),
+                storage_entry(2),
+                storage_entry(3),
+            ],
+            label: Some("lights_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                params_buffer.as_entire_binding(),
+                lights_buffer.as_entire_binding(),
+                counts_buffer.as_entire_binding(),
+                indices_buffer.as_entire_binding(),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect::<Vec<_>>(),
+            label: Some("lights_bind_group"),
+        });
+
+        let heatmap_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_entry,
+                    storage_entry(1),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("cluster_heatmap_bind_group_layout"),
+            });
+        let heatmap_shader = post_process::fullscreen_shader(
+            device,
+            "Cluster heatmap shader",
+            &format!(
+                "{}\n{}",
+                include_str!("cluster.wgsl"),
+                include_str!("cluster_heatmap.wgsl")
+            ),
+        );
+        let heatmap_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cluster heatmap pipeline layout"),
+            bind_group_layouts: &[&heatmap_bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let heatmap_pipeline = post_process::fullscreen_pipeline(
+            device,
+            Some(&heatmap_layout),
+            &heatmap_shader,
+            "fs_main",
+            wgpu::ColorTargetState {
+                format: surface_config.format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+            "Cluster heatmap pipeline",
+        );
+        let heatmap_bind_group = create_heatmap_bind_group(
+            device,
+            &heatmap_bind_group_layout,
+            &params_buffer,
+            &counts_buffer,
+            depth,
+        );
+
+        Self {
+            lights: Vec::new(),
+            heatmap: false,
+            bind_group_layout,
+            bind_group,
+            params_buffer,
+            lights_buffer,
+            counts_buffer,
+            assign_pipeline,
+            assign_bind_group,
+            heatmap_pipeline,
+            heatmap_bind_group_layout,
+            heatmap_bind_group,
+        }
+    }
+
+    // false when MAX_LIGHTS are already in, the light is dropped then
+    pub fn add_light(&mut self, light: Light) -> bool {
+        if self.lights.len() >= MAX_LIGHTS {
+            return false;
+        }
+        self.lights.push(light);
+        true
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, depth: &Texture) {
+        self.heatmap_bind_group = create_heatmap_bind_group(
+            device,
+            &self.heatmap_bind_group_layout,
+            &self.params_buffer,
+            &self.counts_buffer,
+            depth,
+        );
+    }
+
+    // Uploads the lights and assigns them to the clusters of camera's view. Has to be
+    // encoded after the camera buffer is written and before the scene is drawn.
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+    ) {
+        let lights = self
+            .lights
+            .iter()
+            .take(MAX_LIGHTS)
+            .map(|light| light.to_raw())
+            .collect::<Vec<_>>();
+        if !lights.is_empty() {
+            queue.write_buffer(&self.lights_buffer, 0, bytemuck::cast_slice(&lights));
+        }
+        let params = ClusterParams {
+            grid_size: CLUSTER_GRID,
+            light_count: lights.len() as u32,
+            screen_size: [width.max(1) as f32, height.max(1) as f32],
+            near: camera.znear,
+            far: camera.zfar,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cluster assign pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.assign_pipeline);
+        compute_pass.set_bind_group(0, &self.assign_bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            CLUSTER_GRID[0].div_ceil(WORKGROUP_SIZE),
+            CLUSTER_GRID[1].div_ceil(WORKGROUP_SIZE),
+            CLUSTER_GRID[2].div_ceil(WORKGROUP_SIZE),
+        );
+    }
+
+    // Blends the light count of each pixel's cluster over output while heatmap is on.
+    pub fn draw_heatmap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) {
+        if !self.heatmap {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Cluster heatmap pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.heatmap_pipeline);
+        render_pass.set_bind_group(0, &self.heatmap_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_heatmap_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    params_buffer: &wgpu::Buffer,
+    counts_buffer: &wgpu::Buffer,
+    depth: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: counts_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            },
+        ],
+        label: Some("cluster_heatmap_bind_group"),
+    })
+}
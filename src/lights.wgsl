@@ -0,0 +1,41 @@
+// Point and spot lights for the scene shaders, looked up through the cluster of the
+// pixel. Needs pbr.wgsl and cluster.wgsl, the cluster data is in group 3.
+
+@group(3) @binding(0)
+var<uniform> cluster_params: ClusterParams;
+@group(3) @binding(1)
+var<storage, read> lights: array<Light>;
+@group(3) @binding(2)
+var<storage, read> cluster_light_counts: array<u32>;
+@group(3) @binding(3)
+var<storage, read> cluster_light_indices: array<u32>;
+
+// frag_xy is the fragment position in pixels, view_z the positive view distance of
+// world_position
+fn shade_lights(
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    v: vec3<f32>,
+    world_position: vec3<f32>,
+    frag_xy: vec2<f32>,
+    view_z: f32,
+) -> vec3<f32> {
+    let cluster = cluster_index(cluster_params, frag_xy, view_z);
+    let count = min(cluster_light_counts[cluster], MAX_LIGHTS_PER_CLUSTER);
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < count; i++) {
+        let light = lights[cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + i]];
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        let l = to_light / max(distance, 0.0001);
+        // inverse square, windowed to reach 0 at the range so the cluster bounds hold
+        let window = saturate(1.0 - pow(distance / light.range, 4.0));
+        let falloff = window * window / (distance * distance + 1.0);
+        let cone = saturate(dot(-l, light.direction) * light.spot_scale + light.spot_offset);
+        let radiance = light.color * falloff * cone * cone;
+        total += direct_light(albedo, normal, metallic, roughness, v, l, radiance);
+    }
+    return total;
+}
@@ -1,33 +1,95 @@
 use camera::{Camera, CameraController};
 use core::time;
+use debug_draw::DebugStyle;
+use lights::{Light, LightKind};
+use nalgebra::{Point3, UnitQuaternion, Vector3};
 use std::env;
+use std::path::Path;
 use winit::{
     application::ApplicationHandler,
     event::{DeviceEvent, WindowEvent},
     event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
 };
 
+mod anti_aliasing;
+mod bloom;
 mod camera;
+mod compressed;
+mod debug_draw;
+mod debug_view;
+mod deferred;
+mod depth_prepass;
+mod geometry;
+mod ibl;
+mod lights;
 
 mod model;
+mod msaa;
+mod oit;
+mod overdraw;
+mod post_process;
+mod sampler;
+mod shader_effects;
+mod skybox;
+mod ssao;
+mod taa;
 mod texture;
+mod tonemap;
 
 mod renderer;
+mod vertex;
 use renderer::Renderer;
 
+// setting the title every frame is slow on some platforms
+const TITLE_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
 struct App<'a> {
     renderer: Option<Renderer<'a>>,
     camera: Camera,
     camera_controller: CameraController,
     current_frame_start: std::time::Instant,
     last_frame_duration: std::time::Duration,
+    // the overdraw readout in the title is only refreshed every TITLE_INTERVAL
+    last_title_update: std::time::Instant,
+    // index into the post process chain of the effect X and Z act on
+    selected_effect: usize,
+    // the world grid, the axes at the origin, the mesh bounds and the light volumes,
+    // toggled with B
+    show_debug_grid: bool,
 }
 
 impl ApplicationHandler for App<'_> {
     //used for initialization as well as resume
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         if self.renderer.is_none() {
-            self.renderer = Some(Renderer::new(event_loop, &mut self.camera))
+            let mut renderer = Renderer::new(event_loop, &mut self.camera);
+            // without it the scene keeps the flat ambient light and background color
+            if let Err(e) = renderer.load_environment(
+                include_bytes!("../static/sky.hdr"),
+                &Path::new(env!("CARGO_MANIFEST_DIR")).join("static/sky.ibl"),
+            ) {
+                log::warn!("error loading environment: {e}");
+            }
+            // a warm and a cool point light on either side and a spot from above
+            for light in [
+                Light::point(Point3::new(3.0, 2.0, 3.0), Vector3::new(8.0, 5.0, 2.5), 8.0),
+                Light::point(
+                    Point3::new(-3.0, 2.0, -3.0),
+                    Vector3::new(2.0, 4.0, 8.0),
+                    8.0,
+                ),
+                Light::spot(
+                    Point3::new(0.0, 6.0, 0.0),
+                    -Vector3::y(),
+                    Vector3::new(12.0, 12.0, 12.0),
+                    12.0,
+                    20f32.to_radians(),
+                    30f32.to_radians(),
+                ),
+            ] {
+                renderer.lights.add_light(light);
+            }
+            self.renderer = Some(renderer);
         }
         self.renderer.as_mut().unwrap().window.request_redraw()
     }
@@ -78,6 +140,107 @@ impl ApplicationHandler for App<'_> {
                     PhysicalKey::Code(KeyCode::KeyW) | PhysicalKey::Code(KeyCode::ArrowUp) => {
                         self.camera_controller.forward_pressed = is_pressed;
                     }
+                    PhysicalKey::Code(KeyCode::KeyM) if is_pressed && !event.repeat => {
+                        let renderer = self.renderer.as_mut().unwrap();
+                        let sample_count = renderer.msaa.next_sample_count();
+                        renderer.set_sample_count(sample_count);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyF) if is_pressed && !event.repeat => {
+                        let anti_aliasing = &mut self.renderer.as_mut().unwrap().anti_aliasing;
+                        anti_aliasing.mode = anti_aliasing.mode.next();
+                    }
+                    PhysicalKey::Code(KeyCode::KeyR) if is_pressed && !event.repeat => {
+                        let renderer = self.renderer.as_mut().unwrap();
+                        renderer.set_render_path(renderer.render_path().next());
+                    }
+                    PhysicalKey::Code(KeyCode::KeyN) if is_pressed && !event.repeat => {
+                        let settings = &mut self.renderer.as_mut().unwrap().tonemap.settings;
+                        settings.tonemapper = settings.tonemapper.next();
+                    }
+                    PhysicalKey::Code(KeyCode::KeyE) if is_pressed && !event.repeat => {
+                        let settings = &mut self.renderer.as_mut().unwrap().tonemap.settings;
+                        settings.exposure = settings.exposure.toggle();
+                    }
+                    PhysicalKey::Code(KeyCode::KeyG) if is_pressed && !event.repeat => {
+                        let deferred = &mut self.renderer.as_mut().unwrap().deferred;
+                        deferred.view = deferred.view.next();
+                    }
+                    PhysicalKey::Code(KeyCode::KeyH) if is_pressed && !event.repeat => {
+                        let lights = &mut self.renderer.as_mut().unwrap().lights;
+                        lights.heatmap = !lights.heatmap;
+                    }
+                    PhysicalKey::Code(KeyCode::KeyT) if is_pressed && !event.repeat => {
+                        let renderer = self.renderer.as_mut().unwrap();
+                        renderer.transparency = renderer.transparency.next();
+                    }
+                    PhysicalKey::Code(KeyCode::KeyP) if is_pressed && !event.repeat => {
+                        let renderer = self.renderer.as_mut().unwrap();
+                        renderer.set_depth_prepass(!renderer.depth_prepass());
+                    }
+                    PhysicalKey::Code(KeyCode::KeyV) if is_pressed && !event.repeat => {
+                        let debug_view = &mut self.renderer.as_mut().unwrap().debug_view;
+                        debug_view.view = debug_view.view.next();
+                    }
+                    PhysicalKey::Code(KeyCode::KeyB) if is_pressed && !event.repeat => {
+                        self.show_debug_grid = !self.show_debug_grid;
+                    }
+                    // leaves the camera's current view volume in the scene for a while, to
+                    // look at from elsewhere
+                    PhysicalKey::Code(KeyCode::KeyK) if is_pressed && !event.repeat => {
+                        let debug_draw = &mut self.renderer.as_mut().unwrap().debug_draw;
+                        debug_draw.frustum(
+                            &self.camera.build_view_projection_matrix(),
+                            [1.0, 0.0, 1.0, 1.0],
+                            DebugStyle::default()
+                                .with_duration(time::Duration::from_secs(10))
+                                .with_depth_test(false),
+                        );
+                    }
+                    PhysicalKey::Code(KeyCode::KeyC) if is_pressed && !event.repeat => {
+                        let post_process = &self.renderer.as_ref().unwrap().post_process;
+                        self.selected_effect =
+                            (self.selected_effect + 1) % post_process.names().count().max(1);
+                        if let Some(name) = post_process.names().nth(self.selected_effect) {
+                            log::info!("selected post effect {name}");
+                        }
+                    }
+                    PhysicalKey::Code(KeyCode::KeyX) if is_pressed && !event.repeat => {
+                        let post_process = &mut self.renderer.as_mut().unwrap().post_process;
+                        let selected = post_process.names().nth(self.selected_effect);
+                        if let Some(name) = selected.map(str::to_string) {
+                            let enabled = !post_process.is_enabled(&name);
+                            post_process.set_enabled(&name, enabled);
+                            log::info!("{name} {}", if enabled { "on" } else { "off" });
+                        }
+                    }
+                    // moves the selected effect one place earlier, the first one to the end
+                    PhysicalKey::Code(KeyCode::KeyZ) if is_pressed && !event.repeat => {
+                        let post_process = &mut self.renderer.as_mut().unwrap().post_process;
+                        let count = post_process.names().count();
+                        let selected = post_process.names().nth(self.selected_effect);
+                        if let Some(name) = selected.map(str::to_string) {
+                            self.selected_effect = (self.selected_effect + count - 1) % count;
+                            post_process.move_to(&name, self.selected_effect);
+                            let order = post_process.names().collect::<Vec<_>>().join(", ");
+                            log::info!("post effects {order}");
+                        }
+                    }
+                    PhysicalKey::Code(code @ (KeyCode::BracketLeft | KeyCode::BracketRight))
+                        if is_pressed =>
+                    {
+                        let post_process = &mut self.renderer.as_mut().unwrap().post_process;
+                        let step = if code == KeyCode::BracketRight {
+                            0.1
+                        } else {
+                            -0.1
+                        };
+                        if let Some(strength) =
+                            post_process.param(shader_effects::VIGNETTE, "strength")
+                        {
+                            let strength = (strength + step).clamp(0.0, 1.0);
+                            post_process.set_param(shader_effects::VIGNETTE, "strength", strength);
+                        }
+                    }
                     _ => (),
                 }
             }
@@ -86,7 +249,66 @@ impl ApplicationHandler for App<'_> {
                 let dynamic_objects_to_render;
                 let instanced_static_objects_to_render;
 
-                self.renderer.as_mut().unwrap().render_pass(&self.camera);
+                let renderer = self.renderer.as_mut().unwrap();
+                if self.show_debug_grid {
+                    // the scene hides the grid, the mesh bounds and the light volumes, the
+                    // axes stay on top
+                    let bounds = renderer.instance_bounds();
+                    let debug_draw = &mut renderer.debug_draw;
+                    debug_draw.grid(
+                        Point3::origin(),
+                        20.0,
+                        20,
+                        [0.5, 0.5, 0.5, 0.5],
+                        DebugStyle::default(),
+                    );
+                    debug_draw.axis(
+                        Point3::origin(),
+                        UnitQuaternion::identity(),
+                        1.0,
+                        DebugStyle::default().with_depth_test(false),
+                    );
+                    for (min, max) in bounds {
+                        debug_draw.aabb(min, max, [0.0, 1.0, 1.0, 0.5], DebugStyle::default());
+                    }
+                    let light_color = [1.0, 1.0, 0.0, 0.5];
+                    for light in &renderer.lights.lights {
+                        debug_draw.sphere(
+                            light.position,
+                            light.range,
+                            light_color,
+                            DebugStyle::default(),
+                        );
+                        if let LightKind::Spot { direction, .. } = light.kind {
+                            debug_draw.line(
+                                light.position,
+                                light.position + direction.normalize() * light.range,
+                                light_color,
+                                DebugStyle::default(),
+                            );
+                        }
+                    }
+                }
+                renderer.render_pass(&mut self.camera);
+                // overdraw with the prepass on and off, toggled with P
+                let title_due = self.last_title_update.elapsed() >= TITLE_INTERVAL;
+                if let Some(overdraw) = renderer
+                    .overdraw
+                    .as_ref()
+                    .filter(|_| title_due)
+                    .and_then(|counter| counter.fragments_per_pixel())
+                {
+                    self.last_title_update = std::time::Instant::now();
+                    let prepass = if renderer.depth_prepass() {
+                        "on"
+                    } else {
+                        "off"
+                    };
+                    renderer.window.set_title(&format!(
+                        "overdraw {:.2}x, depth prepass {}",
+                        overdraw, prepass
+                    ));
+                }
                 self.last_frame_duration = self.current_frame_start.elapsed();
                 self.current_frame_start = std::time::Instant::now();
             }
@@ -105,6 +327,9 @@ fn main() {
         camera: Camera::new(1920.0 / 1080.0),
         current_frame_start: std::time::Instant::now(),
         last_frame_duration: time::Duration::default(),
+        last_title_update: std::time::Instant::now(),
+        selected_effect: 0,
+        show_debug_grid: false,
     };
     let event_loop = EventLoop::new().expect("error creating event loop");
     event_loop.set_control_flow(ControlFlow::Poll);
@@ -1,12 +1,11 @@
-use std::{io::{BufReader, Cursor}, ops::Range};
+use std::{fs::File, io::BufReader, mem::offset_of, ops::Range, path::Path};
 
 use wgpu::util::DeviceExt;
 
-use crate::texture;
-
-pub trait Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static>;
-}
+use crate::geometry::{Axis, MeshData, UvProjection, VertexData};
+use crate::sampler::{parse_mtl_texture, SamplerCache, SamplerDesc};
+use crate::texture::{self, MipmapGenerator};
+use crate::vertex::{Vertex, VertexAttributeDesc, VertexLayout, VertexSemantic};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -16,44 +15,275 @@ pub struct ModelVertex {
     pub normal: [f32; 3],
 }
 
+impl From<VertexData> for ModelVertex {
+    fn from(v: VertexData) -> Self {
+        Self {
+            position: v.position,
+            tex_coords: v.tex_coords,
+            normal: v.normal,
+        }
+    }
+}
+
 impl Vertex for ModelVertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
+    const ATTRIBUTES: &'static [VertexAttributeDesc] = &[
+        VertexAttributeDesc::new(
+            VertexSemantic::Position,
+            wgpu::VertexFormat::Float32x3,
+            offset_of!(ModelVertex, position),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::TexCoord,
+            wgpu::VertexFormat::Float32x2,
+            offset_of!(ModelVertex, tex_coords),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::Normal,
+            wgpu::VertexFormat::Float32x3,
+            offset_of!(ModelVertex, normal),
+        ),
+    ];
+}
+
+// ModelVertex with a MikkTSpace tangent, used for normal mapped meshes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TangentVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+}
+
+impl From<VertexData> for TangentVertex {
+    fn from(v: VertexData) -> Self {
+        Self {
+            position: v.position,
+            tex_coords: v.tex_coords,
+            normal: v.normal,
+            tangent: v.tangent,
         }
     }
 }
 
+impl Vertex for TangentVertex {
+    const ATTRIBUTES: &'static [VertexAttributeDesc] = &[
+        VertexAttributeDesc::new(
+            VertexSemantic::Position,
+            wgpu::VertexFormat::Float32x3,
+            offset_of!(TangentVertex, position),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::TexCoord,
+            wgpu::VertexFormat::Float32x2,
+            offset_of!(TangentVertex, tex_coords),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::Normal,
+            wgpu::VertexFormat::Float32x3,
+            offset_of!(TangentVertex, normal),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::Tangent,
+            wgpu::VertexFormat::Float32x4,
+            offset_of!(TangentVertex, tangent),
+        ),
+    ];
+}
+
+// TangentVertex extended with a linear rgba color, used for meshes with obj vertex colors
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColoredVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl From<VertexData> for ColoredVertex {
+    fn from(v: VertexData) -> Self {
+        Self {
+            position: v.position,
+            tex_coords: v.tex_coords,
+            normal: v.normal,
+            tangent: v.tangent,
+            color: v.color,
+        }
+    }
+}
+
+impl Vertex for ColoredVertex {
+    const ATTRIBUTES: &'static [VertexAttributeDesc] = &[
+        VertexAttributeDesc::new(
+            VertexSemantic::Position,
+            wgpu::VertexFormat::Float32x3,
+            offset_of!(ColoredVertex, position),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::TexCoord,
+            wgpu::VertexFormat::Float32x2,
+            offset_of!(ColoredVertex, tex_coords),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::Normal,
+            wgpu::VertexFormat::Float32x3,
+            offset_of!(ColoredVertex, normal),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::Tangent,
+            wgpu::VertexFormat::Float32x4,
+            offset_of!(ColoredVertex, tangent),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::Color,
+            wgpu::VertexFormat::Float32x4,
+            offset_of!(ColoredVertex, color),
+        ),
+    ];
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material_id: u32,
+    pub vertex_layout: VertexLayout,
+    // the bounding box in model space, all zero for meshes without positions
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    // middle of the bounding box, transparent meshes are sorted by it
+    pub center: [f32; 3],
+}
+
+// alpha below this is cut away in masked materials that don't set their own
+pub const DEFAULT_ALPHA_CUTOFF: f32 = 0.5;
+
+// How a material's alpha is used. Each mode is drawn with its own pipelines, opaque and
+// masked meshes first, then blended ones back to front.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AlphaMode {
+    Opaque,
+    // pixels with alpha below MaterialUniform::alpha_cutoff are dropped, with msaa the
+    // edge becomes alpha to coverage instead
+    Mask,
+    // blended over what is behind, without writing depth
+    Blend,
+}
+
+impl AlphaMode {
+    pub const ALL: [AlphaMode; 3] = [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend];
+
+    // an alpha map (map_d) cuts the material out, a dissolve (d) below 1 blends it
+    pub fn from_mtl(material: &tobj::Material) -> Self {
+        if material.dissolve_texture.is_some() {
+            Self::Mask
+        } else if material.dissolve.is_some_and(|d| d < 1.0) {
+            Self::Blend
+        } else {
+            Self::Opaque
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub metallic: f32,
+    pub roughness: f32,
+    // 0 cuts nothing, anything else should only be set for AlphaMode::Mask materials
+    pub alpha_cutoff: f32,
+    // multiplies the alpha of the diffuse texture
+    pub opacity: f32,
+    // light the surface gives off by itself, added after lighting
+    pub emissive: [f32; 3],
+    // uniform buffers are bound in 16 byte steps
+    _emissive_padding: f32,
+}
+
+impl MaterialUniform {
+    pub fn new(metallic: f32, roughness: f32) -> Self {
+        Self {
+            metallic,
+            roughness,
+            alpha_cutoff: 0.0,
+            opacity: 1.0,
+            emissive: [0.0; 3],
+            _emissive_padding: 0.0,
+        }
+    }
+
+    pub fn with_emissive(mut self, emissive: [f32; 3]) -> Self {
+        self.emissive = emissive;
+        self
+    }
+
+    pub fn with_alpha(mut self, alpha_cutoff: f32, opacity: f32) -> Self {
+        self.alpha_cutoff = alpha_cutoff;
+        self.opacity = opacity;
+        self
+    }
+
+    // Pm and Pr from the pbr extension to mtl. Without Pr the phong exponent Ns is
+    // converted, sqrt(2 / (Ns + 2)) is the roughness whose ggx lobe has a similar width.
+    // Ke is the emissive color, d the opacity.
+    pub fn from_mtl(material: &tobj::Material) -> Self {
+        let param = |name: &str| {
+            material
+                .unknown_param
+                .get(name)
+                .and_then(|v| v.trim().parse::<f32>().ok())
+        };
+        let metallic = param("Pm").unwrap_or(0.0);
+        let roughness = param("Pr")
+            .or_else(|| {
+                material
+                    .shininess
+                    .map(|ns| (2.0 / (ns.max(0.0) + 2.0)).sqrt())
+            })
+            .unwrap_or(0.5);
+        let emissive = material
+            .unknown_param
+            .get("Ke")
+            .map(|v| {
+                v.split_whitespace()
+                    .filter_map(|c| c.parse::<f32>().ok())
+                    .collect::<Vec<_>>()
+            })
+            .and_then(|c| <[f32; 3]>::try_from(c).ok())
+            .unwrap_or([0.0; 3]);
+        let alpha_cutoff = match AlphaMode::from_mtl(material) {
+            AlphaMode::Mask => DEFAULT_ALPHA_CUTOFF,
+            AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+        };
+        Self::new(metallic.clamp(0.0, 1.0), roughness.clamp(0.0, 1.0))
+            .with_emissive(emissive.map(|c| c.max(0.0)))
+            .with_alpha(
+                alpha_cutoff,
+                material.dissolve.unwrap_or(1.0).clamp(0.0, 1.0),
+            )
+    }
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self::new(0.0, 0.5)
+    }
 }
 
 pub struct Material {
     pub name: String,
+    pub alpha_mode: AlphaMode,
     pub diffuse_texture: texture::Texture,
+    // flat 1x1 normal map when the material doesn't have one
+    pub normal_texture: texture::Texture,
+    // linear single channel maps, 1x1 white when missing
+    pub roughness_texture: texture::Texture,
+    pub metallic_texture: texture::Texture,
+    pub occlusion_texture: texture::Texture,
+    pub material_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -62,14 +292,90 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+#[derive(Copy, Clone, Debug)]
+pub enum NormalGeneration {
+    // crease_angle is in radians, faces meeting at a sharper angle keep a hard edge
+    Smooth { crease_angle: f32 },
+    Flat,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImportOptions {
+    pub normal_generation: NormalGeneration,
+    // ignore normals from the file and always generate them
+    pub regenerate_normals: bool,
+    // projection used for meshes without uvs, they get (0, 0) everywhere when this is None
+    pub tex_coord_generation: Option<UvProjection>,
+    pub regenerate_tex_coords: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            normal_generation: NormalGeneration::Smooth {
+                crease_angle: 60f32.to_radians(),
+            },
+            regenerate_normals: false,
+            tex_coord_generation: Some(UvProjection::Box),
+            regenerate_tex_coords: false,
+        }
+    }
+}
+
+impl ImportOptions {
+    // Defaults changed by command line flags: --flat-normals, --crease-angle <degrees>,
+    // --regenerate-normals, --uv <box|planar-x|planar-y|planar-z|spherical|none> and
+    // --regenerate-uvs. Arguments it doesn't know are skipped.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--flat-normals" => options.normal_generation = NormalGeneration::Flat,
+                "--crease-angle" => {
+                    if let Some(degrees) = args.next().and_then(|a| a.parse::<f32>().ok()) {
+                        options.normal_generation = NormalGeneration::Smooth {
+                            crease_angle: degrees.to_radians(),
+                        };
+                    }
+                }
+                "--regenerate-normals" => options.regenerate_normals = true,
+                "--uv" => {
+                    if let Some(projection) = args.next() {
+                        options.tex_coord_generation = match projection.as_str() {
+                            "box" => Some(UvProjection::Box),
+                            "planar-x" => Some(UvProjection::Planar { axis: Axis::X }),
+                            "planar-y" => Some(UvProjection::Planar { axis: Axis::Y }),
+                            "planar-z" => Some(UvProjection::Planar { axis: Axis::Z }),
+                            "spherical" => Some(UvProjection::Spherical),
+                            "none" => None,
+                            _ => options.tex_coord_generation,
+                        };
+                    }
+                }
+                "--regenerate-uvs" => options.regenerate_tex_coords = true,
+                _ => {}
+            }
+        }
+        options
+    }
+}
+
+// Texture mips are generated into encoder, submit it before drawing the model.
+#[allow(clippy::too_many_arguments)]
 pub fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
     layout: &wgpu::BindGroupLayout,
+    samplers: &mut SamplerCache,
+    mipmaps: &mut MipmapGenerator,
+    options: &ImportOptions,
 ) -> anyhow::Result<Model> {
-    let cursor = Cursor::new(file_name);
-    let mut reader = BufReader::new(cursor);
+    // mtl files and the textures they name are relative to the obj
+    let directory = Path::new(file_name).parent().unwrap_or(Path::new(""));
+    let mut reader = BufReader::new(File::open(file_name)?);
 
     let (models, obj_materials) = tobj::load_obj_buf(
         &mut reader,
@@ -78,12 +384,97 @@ pub fn load_model(
             single_index: true,
             ..Default::default()
         },
-        |path| tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(path.to_str().unwrap()))),
+        |path| tobj::load_mtl(directory.join(path)),
     )?;
 
+    // without an mtl file every mesh gets one default material, meshes without a material
+    // use the first one
+    let mut obj_materials = obj_materials.unwrap_or_else(|e| {
+        log::warn!("no materials loaded for {file_name}: {e}");
+        Vec::new()
+    });
+    if obj_materials.is_empty() {
+        obj_materials.push(tobj::Material {
+            name: "default".to_string(),
+            ..Default::default()
+        });
+    }
+
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = texture::load_texture(&m.diffuse_texture.unwrap(), device, queue)?;
+    for m in obj_materials {
+        let material_uniform = MaterialUniform::from_mtl(&m);
+        let alpha_mode = AlphaMode::from_mtl(&m);
+        let diffuse_texture = match &m.diffuse_texture {
+            Some(statement) => {
+                let (path, sampler) = parse_mtl_texture(statement);
+                texture::load_texture(
+                    &directory.join(path).to_string_lossy(),
+                    texture::TextureRole::Color,
+                    samplers.get(device, sampler),
+                    device,
+                    queue,
+                    encoder,
+                    mipmaps,
+                )?
+            }
+            // the diffuse color Kd stands in for map_Kd, white when there's neither
+            None => texture::Texture::solid_color(
+                device,
+                queue,
+                m.diffuse.map_or([255; 4], diffuse_texel),
+                "diffuse_color",
+                texture::TextureRole::Color,
+                samplers.get(device, SamplerDesc::ANISOTROPIC_REPEAT),
+            ),
+        };
+        let normal_texture = match &m.normal_texture {
+            Some(statement) => {
+                let (path, sampler) = parse_mtl_texture(statement);
+                texture::load_texture(
+                    &directory.join(path).to_string_lossy(),
+                    texture::TextureRole::NormalMap,
+                    samplers.get(device, sampler),
+                    device,
+                    queue,
+                    encoder,
+                    mipmaps,
+                )?
+            }
+            None => texture::Texture::flat_normal(
+                device,
+                queue,
+                samplers.get(device, SamplerDesc::ANISOTROPIC_REPEAT),
+            ),
+        };
+        // the pbr extension's roughness and metallic maps scale Pr and Pm, an occlusion map
+        // darkens the ambient light
+        let mut data_texture = |key: &str| match m.unknown_param.get(key) {
+            Some(statement) => {
+                let (path, sampler) = parse_mtl_texture(statement);
+                texture::load_texture(
+                    &directory.join(path).to_string_lossy(),
+                    texture::TextureRole::LinearData,
+                    samplers.get(device, sampler),
+                    device,
+                    queue,
+                    encoder,
+                    mipmaps,
+                )
+            }
+            None => Ok(texture::Texture::white_data(
+                device,
+                queue,
+                samplers.get(device, SamplerDesc::ANISOTROPIC_REPEAT),
+            )),
+        };
+        let roughness_texture = data_texture("map_Pr")?;
+        let metallic_texture = data_texture("map_Pm")?;
+        let occlusion_texture = data_texture("map_ao")?;
+        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} material buffer", m.name)),
+            contents: bytemuck::cast_slice(&[material_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -95,12 +486,54 @@ pub fn load_model(
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&roughness_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&roughness_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&metallic_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&metallic_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&occlusion_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&occlusion_texture.sampler),
+                },
             ],
             label: None,
         });
         materials.push(Material {
             name: m.name,
+            alpha_mode,
             diffuse_texture,
+            normal_texture,
+            roughness_texture,
+            metallic_texture,
+            occlusion_texture,
+            material_buffer,
             bind_group,
         });
     }
@@ -108,59 +541,70 @@ pub fn load_model(
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| {
-                    if m.mesh.normals.is_empty() {
-                        ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [0.0, 0.0, 0.0],
-                        }
-                    } else {
-                        ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [
-                                m.mesh.normals[i * 3],
-                                m.mesh.normals[i * 3 + 1],
-                                m.mesh.normals[i * 3 + 2],
-                            ],
-                        }
+            let mut mesh_data = MeshData::from_obj(&m.mesh);
+            if mesh_data.normals.is_empty() || options.regenerate_normals {
+                match options.normal_generation {
+                    NormalGeneration::Smooth { crease_angle } => {
+                        mesh_data.generate_smooth_normals(crease_angle)
                     }
-                })
-                .collect::<Vec<_>>();
+                    NormalGeneration::Flat => mesh_data.generate_flat_normals(),
+                }
+            }
+            if let Some(projection) = options.tex_coord_generation {
+                if mesh_data.tex_coords.is_empty() || options.regenerate_tex_coords {
+                    mesh_data.generate_tex_coords(projection);
+                }
+            }
+            mesh_data.generate_tangents();
+
+            let (vertex_layout, contents) = if mesh_data.colors.is_empty() {
+                let vertices = mesh_data.build_vertices::<TangentVertex>();
+                let contents = bytemuck::cast_slice(&vertices).to_vec();
+                (TangentVertex::layout(), contents)
+            } else {
+                let vertices = mesh_data.build_vertices::<ColoredVertex>();
+                let contents = bytemuck::cast_slice(&vertices).to_vec();
+                (ColoredVertex::layout(), contents)
+            };
 
+            // also read as storage by the barycentric wireframe, which fetches positions
+            // through the indices itself
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                contents: &contents,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                contents: bytemuck::cast_slice(&mesh_data.indices),
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::STORAGE,
             });
 
+            let (min, max) = if mesh_data.positions.is_empty() {
+                ([0.0; 3], [0.0; 3])
+            } else {
+                mesh_data
+                    .positions
+                    .iter()
+                    .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| {
+                        (
+                            [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])],
+                            [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])],
+                        )
+                    })
+            };
+            let center = [0, 1, 2].map(|i| (min[i] + max[i]) / 2.0);
+
             Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
+                num_elements: mesh_data.indices.len() as u32,
                 material_id: m.mesh.material_id.unwrap_or(0) as u32,
+                vertex_layout,
+                min,
+                max,
+                center,
             }
         })
         .collect::<Vec<_>>();
@@ -168,6 +612,20 @@ pub fn load_model(
     Ok(Model { meshes, materials })
 }
 
+// Kd is linear, color textures are srgb encoded and decoded again when sampled
+fn diffuse_texel(kd: [f32; 3]) -> [u8; 4] {
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let srgb = if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (srgb * 255.0).round() as u8
+    };
+    [encode(kd[0]), encode(kd[1]), encode(kd[2]), 255]
+}
+
 pub fn draw_mesh(render_pass: &mut wgpu::RenderPass, mesh: &Mesh) {
     draw_mesh_instanced(render_pass, mesh, 0..1);
 }
@@ -177,3 +635,59 @@ pub fn draw_mesh_instanced(render_pass: &mut wgpu::RenderPass, mesh: &Mesh, inst
     render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
     render_pass.draw_indexed(0..mesh.num_elements, 0, instances);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn no_args_give_the_defaults() {
+        let options = ImportOptions::from_args(Vec::new());
+        assert!(matches!(
+            options.normal_generation,
+            NormalGeneration::Smooth { .. }
+        ));
+        assert!(!options.regenerate_normals);
+    }
+
+    #[test]
+    fn normal_flags_select_the_generation() {
+        let options = ImportOptions::from_args(args(&["--flat-normals", "--regenerate-normals"]));
+        assert!(matches!(options.normal_generation, NormalGeneration::Flat));
+        assert!(options.regenerate_normals);
+
+        let options = ImportOptions::from_args(args(&["--crease-angle", "30", "--unknown"]));
+        let NormalGeneration::Smooth { crease_angle } = options.normal_generation else {
+            panic!("expected smooth normals");
+        };
+        assert!((crease_angle - 30f32.to_radians()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn uv_flags_select_the_projection() {
+        let options = ImportOptions::from_args(args(&["--uv", "planar-y", "--regenerate-uvs"]));
+        assert!(matches!(
+            options.tex_coord_generation,
+            Some(UvProjection::Planar { axis: Axis::Y })
+        ));
+        assert!(options.regenerate_tex_coords);
+
+        let options = ImportOptions::from_args(args(&["--uv", "spherical"]));
+        assert!(matches!(
+            options.tex_coord_generation,
+            Some(UvProjection::Spherical)
+        ));
+        let options = ImportOptions::from_args(args(&["--uv", "none"]));
+        assert!(options.tex_coord_generation.is_none());
+        // an unknown projection keeps the default
+        let options = ImportOptions::from_args(args(&["--uv", "cylinder"]));
+        assert!(matches!(
+            options.tex_coord_generation,
+            Some(UvProjection::Box)
+        ));
+    }
+}
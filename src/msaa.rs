@@ -0,0 +1,219 @@
+use crate::post_process;
+use crate::texture::Texture;
+
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+// Sample counts every format can render with. Without adapter specific format features
+// only what WebGPU guarantees is usable, which is 1 and 4.
+pub fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device_features: wgpu::Features,
+    formats: &[wgpu::TextureFormat],
+) -> Vec<u32> {
+    let adapter_specific =
+        device_features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    SAMPLE_COUNTS
+        .into_iter()
+        .filter(|&count| {
+            formats.iter().all(|&format| {
+                let features = if adapter_specific {
+                    adapter.get_texture_format_features(format)
+                } else {
+                    format.guaranteed_format_features(device_features)
+                };
+                features.flags.sample_count_supported(count)
+            })
+        })
+        .collect()
+}
+
+// Multisampled color and depth targets for the main pass. Color resolves into the
+// renderer's single sampled targets through resolve_target, depth is resolved by a
+// small pass because wgpu can't resolve depth.
+pub struct Msaa {
+    sample_count: u32,
+    supported: Vec<u32>,
+    color_formats: Vec<wgpu::TextureFormat>,
+    // empty at a sample count of 1, the scene then draws straight into the resolve targets
+    color_targets: Vec<Texture>,
+    depth_target: Option<Texture>,
+    depth_resolve_pipeline: wgpu::RenderPipeline,
+    depth_resolve_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Msaa {
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        color_formats: &[wgpu::TextureFormat],
+        supported: Vec<u32>,
+        sample_count: u32,
+    ) -> Self {
+        let shader = post_process::fullscreen_shader(
+            device,
+            "Depth resolve shader",
+            include_str!("depth_resolve.wgsl"),
+        );
+        let depth_resolve_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Depth resolve pipeline"),
+                layout: None,
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            });
+
+        let mut msaa = Self {
+            sample_count: 1,
+            supported,
+            color_formats: color_formats.to_vec(),
+            color_targets: Vec::new(),
+            depth_target: None,
+            depth_resolve_pipeline,
+            depth_resolve_bind_group: None,
+        };
+        msaa.sample_count = msaa.clamp(sample_count);
+        msaa.resize(device, surface_config);
+        msaa
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // the largest supported count that isn't above the requested one
+    fn clamp(&self, sample_count: u32) -> u32 {
+        self.supported
+            .iter()
+            .copied()
+            .filter(|&count| count <= sample_count)
+            .max()
+            .unwrap_or(1)
+    }
+
+    // the next supported count, wrapping back to 1
+    pub fn next_sample_count(&self) -> u32 {
+        self.supported
+            .iter()
+            .copied()
+            .find(|&count| count > self.sample_count)
+            .unwrap_or(1)
+    }
+
+    // True when the count changed, pipelines drawing into the targets have to be
+    // rebuilt with the new multisample_state then.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> bool {
+        let sample_count = self.clamp(sample_count);
+        if sample_count == self.sample_count {
+            return false;
+        }
+        self.sample_count = sample_count;
+        self.resize(device, surface_config);
+        true
+    }
+
+    pub fn multisample_state(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) {
+        if self.sample_count == 1 {
+            self.color_targets.clear();
+            self.depth_target = None;
+            self.depth_resolve_bind_group = None;
+            return;
+        }
+
+        self.color_targets = self
+            .color_formats
+            .iter()
+            .map(|&format| {
+                Texture::create_multisampled_target(
+                    device,
+                    surface_config,
+                    format,
+                    self.sample_count,
+                    "msaa_color_target",
+                )
+            })
+            .collect();
+        let depth_target = Texture::create_depth_texture(
+            device,
+            surface_config,
+            self.sample_count,
+            "msaa_depth_target",
+        );
+        self.depth_resolve_bind_group =
+            Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.depth_resolve_pipeline.get_bind_group_layout(0),
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_target.view),
+                }],
+                label: Some("depth_resolve_bind_group"),
+            }));
+        self.depth_target = Some(depth_target);
+    }
+
+    // what to draw into instead of the resolve target at the same index, if anything
+    pub fn color_target(&self, index: usize) -> Option<&Texture> {
+        self.color_targets.get(index)
+    }
+
+    pub fn depth_target(&self) -> Option<&Texture> {
+        self.depth_target.as_ref()
+    }
+
+    // fills depth from the multisampled depth, does nothing without msaa
+    pub fn resolve_depth(&self, encoder: &mut wgpu::CommandEncoder, depth: &Texture) {
+        let Some(bind_group) = &self.depth_resolve_bind_group else {
+            return;
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth resolve pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.depth_resolve_pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::post_process::{self, draw_fullscreen};
+use crate::texture::Texture;
+use crate::tonemap::HDR_FORMAT;
+
+pub const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransparencyMode {
+    // blended instances are drawn one at a time from the farthest to the nearest, right
+    // for separate objects but not for ones that intersect or overlap themselves
+    Sorted,
+    // blended meshes are drawn in any order and averaged by depth and opacity, never
+    // pops but gets the order of overlapping layers only roughly right
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Sorted => Self::WeightedBlended,
+            Self::WeightedBlended => Self::Sorted,
+        }
+    }
+}
+
+// Weighted blended order independent transparency. The renderer draws blended meshes
+// with fs_oit into the pass begin_accumulation starts, then composite blends the
+// average over the lit scene.
+pub struct OitPass {
+    accumulation_texture: Texture,
+    revealage_texture: Texture,
+    composite_pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl OitPass {
+    pub fn new(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) -> Self {
+        let shader = post_process::fullscreen_shader(
+            device,
+            "Oit composite shader",
+            include_str!("oit_composite.wgsl"),
+        );
+        let composite_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &shader,
+            "fs_main",
+            wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+            "Oit composite pipeline",
+        );
+        let (accumulation_texture, revealage_texture) = create_targets(device, surface_config);
+        let bind_group = create_bind_group(
+            device,
+            &composite_pipeline,
+            &accumulation_texture,
+            &revealage_texture,
+        );
+
+        Self {
+            accumulation_texture,
+            revealage_texture,
+            composite_pipeline,
+            bind_group,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) {
+        (self.accumulation_texture, self.revealage_texture) =
+            create_targets(device, surface_config);
+        self.bind_group = create_bind_group(
+            device,
+            &self.composite_pipeline,
+            &self.accumulation_texture,
+            &self.revealage_texture,
+        );
+    }
+
+    // What the accumulation pipelines draw into. Colors weighted by coverage add up in
+    // the first target, the second is multiplied by how much each fragment lets through.
+    pub fn targets() -> Vec<Option<wgpu::ColorTargetState>> {
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let transmittance = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+            operation: wgpu::BlendOperation::Add,
+        };
+        vec![
+            Some(wgpu::ColorTargetState {
+                format: ACCUMULATION_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: additive,
+                    alpha: additive,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: REVEALAGE_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: transmittance,
+                    alpha: transmittance,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+        ]
+    }
+
+    // Clears the targets and starts the pass the blended meshes are drawn in. depth is
+    // the single sampled scene depth, tested against but not written.
+    pub fn begin_accumulation<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth: &'a Texture,
+    ) -> wgpu::RenderPass<'a> {
+        let clear_colors = [wgpu::Color::TRANSPARENT, wgpu::Color::WHITE];
+        let color_attachments = [&self.accumulation_texture, &self.revealage_texture]
+            .iter()
+            .zip(clear_colors)
+            .map(|(target, clear)| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Oit accumulation pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    // Blends what was accumulated over output, pixels nothing covered are left alone.
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, output: &Texture) {
+        draw_fullscreen(
+            encoder,
+            &self.composite_pipeline,
+            &self.bind_group,
+            &output.view,
+            wgpu::LoadOp::Load,
+            "Oit composite pass",
+        );
+    }
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    surface_config: &wgpu::SurfaceConfiguration,
+) -> (Texture, Texture) {
+    (
+        Texture::create_render_target(
+            device,
+            surface_config,
+            ACCUMULATION_FORMAT,
+            "oit_accumulation_texture",
+        ),
+        Texture::create_render_target(
+            device,
+            surface_config,
+            REVEALAGE_FORMAT,
+            "oit_revealage_texture",
+        ),
+    )
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    pipeline: &wgpu::RenderPipeline,
+    accumulation: &Texture,
+    revealage: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&accumulation.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&revealage.view),
+            },
+        ],
+        label: Some("oit_bind_group"),
+    })
+}
@@ -0,0 +1,21 @@
+// Resolves weighted blended transparency over the lit scene. Appended to
+// fullscreen.wgsl, drawn with alpha blending onto the hdr texture.
+
+@group(0) @binding(0)
+var t_accumulation: texture_2d<f32>;
+@group(0) @binding(1)
+var t_revealage: texture_2d<f32>;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    // the share of the background that still shows through
+    let revealage = textureLoad(t_revealage, pixel, 0).r;
+    if revealage >= 1.0 {
+        discard;
+    }
+    let accumulation = textureLoad(t_accumulation, pixel, 0);
+    // the weighted average color of every transparent fragment here
+    let average = accumulation.rgb / max(accumulation.a, 0.00001);
+    return vec4<f32>(average, 1.0 - revealage);
+}
@@ -0,0 +1,122 @@
+use std::mem;
+use std::sync::mpsc::{self, TryRecvError};
+
+// one u64 per counted statistic
+const RESULT_SIZE: wgpu::BufferAddress = mem::size_of::<u64>() as wgpu::BufferAddress;
+
+// Counts how many fragments the main pass shades with a pipeline statistics query, to
+// compare overdraw with and without the depth prepass. Needs PIPELINE_STATISTICS_QUERY.
+// Counts are read back without stalling, so they lag a few frames behind.
+pub struct OverdrawCounter {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // pixels in the frame copied into readback_buffer this frame
+    copied_pixels: Option<u32>,
+    // pixels in the frame being mapped, and where the mapping reports back
+    pending: Option<(u32, mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>)>,
+    fragments_per_pixel: Option<f32>,
+}
+
+impl OverdrawCounter {
+    // None when the device can't count fragment shader invocations
+    pub fn new(device: &wgpu::Device) -> Option<Self> {
+        if !device
+            .features()
+            .contains(wgpu::Features::PIPELINE_STATISTICS_QUERY)
+        {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Overdraw query set"),
+            ty: wgpu::QueryType::PipelineStatistics(
+                wgpu::PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS,
+            ),
+            count: 1,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overdraw resolve buffer"),
+            size: RESULT_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overdraw readback buffer"),
+            size: RESULT_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            copied_pixels: None,
+            pending: None,
+            fragments_per_pixel: None,
+        })
+    }
+
+    // counts the fragments of the draws between begin and end
+    pub fn begin(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.begin_pipeline_statistics_query(&self.query_set, 0);
+    }
+
+    pub fn end(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.end_pipeline_statistics_query();
+    }
+
+    // Copies out this frame's count, skipped while the last one is still being read.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, pixels: u32) {
+        if self.pending.is_some() {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..1, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            RESULT_SIZE,
+        );
+        self.copied_pixels = Some(pixels);
+    }
+
+    // Starts reading what resolve copied and picks up a finished read, call after the
+    // frame is submitted.
+    pub fn read_back(&mut self, device: &wgpu::Device) {
+        if let Some(pixels) = self.copied_pixels.take() {
+            let (sender, receiver) = mpsc::channel();
+            self.readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let _ = sender.send(result);
+                });
+            self.pending = Some((pixels, receiver));
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        let Some((pixels, receiver)) = &self.pending else {
+            return;
+        };
+        let pixels = *pixels;
+        match receiver.try_recv() {
+            Ok(Ok(())) => {
+                let fragments = {
+                    let mapped = self.readback_buffer.slice(..).get_mapped_range();
+                    bytemuck::pod_read_unaligned::<u64>(&mapped)
+                };
+                self.readback_buffer.unmap();
+                self.fragments_per_pixel = Some(fragments as f32 / pixels.max(1) as f32);
+                self.pending = None;
+            }
+            Ok(Err(_)) | Err(TryRecvError::Disconnected) => self.pending = None,
+            Err(TryRecvError::Empty) => {}
+        }
+    }
+
+    // fragments shaded per pixel in the last frame read back, 1 when every pixel was
+    // shaded exactly once
+    pub fn fragments_per_pixel(&self) -> Option<f32> {
+        self.fragments_per_pixel
+    }
+}
@@ -0,0 +1,99 @@
+// Lighting shared by the forward shader and the deferred lighting pass, prepended to
+// both together with cluster.wgsl and lights.wgsl. Expects the environment maps in
+// group 2.
+
+const PI: f32 = 3.14159265359;
+const LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.4, 1.0, 0.6);
+const LIGHT_COLOR: vec3<f32> = vec3<f32>(3.0);
+// reflectance of dielectrics at normal incidence
+const DIELECTRIC_F0: vec3<f32> = vec3<f32>(0.04);
+
+@group(2) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(2) @binding(2)
+var t_brdf_lut: texture_2d<f32>;
+@group(2) @binding(3)
+var s_environment: sampler;
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// rough surfaces reflect less at grazing angles, used for the ambient term
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// the light reaching the eye from a surface, with the ambient part kept apart for the
+// screen space passes that only darken it
+struct SurfaceLight {
+    direct: vec3<f32>,
+    ambient: vec3<f32>,
+};
+
+// cook torrance for light of the given radiance arriving from direction l, v points
+// from the surface to the eye
+fn direct_light(
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    v: vec3<f32>,
+    l: vec3<f32>,
+    radiance: vec3<f32>,
+) -> vec3<f32> {
+    let f0 = mix(DIELECTRIC_F0, albedo, metallic);
+    let n_dot_v = max(dot(normal, v), 0.0001);
+    let h = normalize(v + l);
+    let n_dot_l = max(dot(normal, l), 0.0);
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let specular = distribution_ggx(max(dot(normal, h), 0.0), roughness)
+        * geometry_smith(n_dot_v, n_dot_l, roughness) * f
+        / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let k_d = (1.0 - f) * (1.0 - metallic);
+    return (k_d * albedo / PI + specular) * radiance * n_dot_l;
+}
+
+// The directional light and the environment. Samples with explicit levels so it also
+// works where control flow isn't uniform.
+fn shade_surface(
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    v: vec3<f32>,
+) -> SurfaceLight {
+    let f0 = mix(DIELECTRIC_F0, albedo, metallic);
+    let n_dot_v = max(dot(normal, v), 0.0001);
+
+    // split sum image based lighting
+    let f_ambient = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_d_ambient = (1.0 - f_ambient) * (1.0 - metallic);
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, normal, 0.0).rgb;
+    let max_lod = f32(textureNumLevels(t_prefiltered) - 1u);
+    let reflected = reflect(-v, normal);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflected, roughness * max_lod).rgb;
+    let brdf = textureSampleLevel(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+
+    var out: SurfaceLight;
+    out.direct = direct_light(albedo, normal, metallic, roughness, v, normalize(LIGHT_DIRECTION), LIGHT_COLOR);
+    out.ambient = k_d_ambient * irradiance * albedo + prefiltered * (f_ambient * brdf.x + brdf.y);
+    return out;
+}
@@ -0,0 +1,680 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::texture::Texture;
+
+// Inputs an effect samples besides the color it transforms.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EffectInputs {
+    pub depth: bool,
+    pub normals: bool,
+    // the ambient light on its own, for effects that only darken that part of color
+    pub ambient: bool,
+    // screen space motion since the last frame, for effects that reproject history
+    pub velocity: bool,
+}
+
+impl EffectInputs {
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            depth: self.depth || other.depth,
+            normals: self.normals || other.normals,
+            ambient: self.ambient || other.ambient,
+            velocity: self.velocity || other.velocity,
+        }
+    }
+}
+
+// What the renderer drew besides color. Normals, ambient and velocity are optional,
+// effects that need a missing one are skipped.
+#[derive(Copy, Clone)]
+pub struct SceneInputs<'a> {
+    pub depth: &'a Texture,
+    pub normals: Option<&'a Texture>,
+    pub ambient: Option<&'a Texture>,
+    pub velocity: Option<&'a Texture>,
+    pub camera_buffer: &'a wgpu::Buffer,
+}
+
+impl SceneInputs<'_> {
+    fn provides(&self, inputs: EffectInputs) -> bool {
+        self.missing(inputs) == EffectInputs::default()
+    }
+
+    // the ones in inputs this scene doesn't have
+    fn missing(&self, inputs: EffectInputs) -> EffectInputs {
+        EffectInputs {
+            depth: false,
+            normals: inputs.normals && self.normals.is_none(),
+            ambient: inputs.ambient && self.ambient.is_none(),
+            velocity: inputs.velocity && self.velocity.is_none(),
+        }
+    }
+}
+
+// A named float an effect exposes for tweaking at runtime.
+#[derive(Clone, Debug)]
+pub struct EffectParam {
+    pub name: &'static str,
+    pub value: f32,
+}
+
+impl EffectParam {
+    pub const fn new(name: &'static str, value: f32) -> Self {
+        Self { name, value }
+    }
+}
+
+// Everything the chain hands an effect when it runs, besides its input and output.
+pub struct EffectContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    pub scene: SceneInputs<'a>,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub trait PostEffect: Any {
+    // unique within a chain, used to enable, reorder and look up the effect
+    fn name(&self) -> &str;
+
+    fn inputs(&self) -> EffectInputs {
+        EffectInputs::default()
+    }
+
+    fn params(&self) -> &[EffectParam] {
+        &[]
+    }
+
+    // false when the effect has no parameter with that name
+    fn set_param(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+
+    // effects that keep their own screen sized textures recreate them here
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {}
+
+    // Reads input and writes every pixel of output, both are in the chain's format and
+    // the size of the screen.
+    fn run(&mut self, context: &mut EffectContext, input: &Texture, output: &Texture);
+}
+
+struct ChainEntry {
+    effect: Box<dyn PostEffect>,
+    enabled: bool,
+}
+
+// Ordered list of full screen effects applied to a target in place. Effects ping-pong
+// between the target and a scratch texture, with a copy back at the end when an odd
+// number of them ran.
+pub struct PostProcessChain {
+    format: wgpu::TextureFormat,
+    entries: Vec<ChainEntry>,
+    scratch: Texture,
+    // what the last warning said was missing, so it's only repeated when that changes
+    reported_missing: EffectInputs,
+}
+
+impl PostProcessChain {
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            format,
+            entries: Vec::new(),
+            scratch: Texture::create_render_target(
+                device,
+                surface_config,
+                format,
+                "post_process_scratch",
+            ),
+            reported_missing: EffectInputs::default(),
+        }
+    }
+
+    // appended enabled, replacing an effect with the same name
+    pub fn push(&mut self, effect: Box<dyn PostEffect>) {
+        self.remove(effect.name());
+        self.entries.push(ChainEntry {
+            effect,
+            enabled: true,
+        });
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn PostEffect>> {
+        let index = self.position(name)?;
+        Some(self.entries.remove(index).effect)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.effect.name())
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.position(name)
+            .is_some_and(|index| self.entries[index].enabled)
+    }
+
+    // false when there is no effect with that name
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.position(name) {
+            Some(index) => {
+                self.entries[index].enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // indices past the end move the effect to the end
+    pub fn move_to(&mut self, name: &str, index: usize) -> bool {
+        match self.position(name) {
+            Some(current) => {
+                let entry = self.entries.remove(current);
+                let index = index.min(self.entries.len());
+                self.entries.insert(index, entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_mut<T: PostEffect>(&mut self, name: &str) -> Option<&mut T> {
+        let index = self.position(name)?;
+        let effect: &mut dyn Any = self.entries[index].effect.as_mut();
+        effect.downcast_mut()
+    }
+
+    pub fn param(&self, effect: &str, param: &str) -> Option<f32> {
+        let index = self.position(effect)?;
+        self.entries[index]
+            .effect
+            .params()
+            .iter()
+            .find(|p| p.name == param)
+            .map(|p| p.value)
+    }
+
+    pub fn set_param(&mut self, effect: &str, param: &str, value: f32) -> bool {
+        match self.position(effect) {
+            Some(index) => self.entries[index].effect.set_param(param, value),
+            None => false,
+        }
+    }
+
+    // what the renderer needs to provide for the enabled effects
+    pub fn required_inputs(&self) -> EffectInputs {
+        self.entries
+            .iter()
+            .filter(|e| e.enabled)
+            .fold(EffectInputs::default(), |inputs, e| {
+                inputs.union(e.effect.inputs())
+            })
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.effect.name() == name)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) {
+        self.scratch = Texture::create_render_target(
+            device,
+            surface_config,
+            self.format,
+            "post_process_scratch",
+        );
+        for entry in &mut self.entries {
+            entry
+                .effect
+                .resize(device, surface_config.width, surface_config.height);
+        }
+    }
+
+    // applies the enabled effects to target in order
+    pub fn run(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &Texture,
+        scene: SceneInputs,
+    ) {
+        let size = target.texture.size();
+        let mut context = EffectContext {
+            device,
+            queue,
+            encoder,
+            scene,
+            width: size.width,
+            height: size.height,
+        };
+
+        let missing = scene.missing(self.required_inputs());
+        if missing != self.reported_missing && missing != EffectInputs::default() {
+            log::warn!("skipping post effects, the scene doesn't provide {missing:?}");
+        }
+        self.reported_missing = missing;
+
+        let mut result_in_scratch = false;
+        for entry in self.entries.iter_mut().filter(|e| e.enabled) {
+            if !scene.provides(entry.effect.inputs()) {
+                continue;
+            }
+            let (input, output) = if result_in_scratch {
+                (&self.scratch, target)
+            } else {
+                (target, &self.scratch)
+            };
+            entry.effect.run(&mut context, input, output);
+            result_in_scratch = !result_in_scratch;
+        }
+
+        if result_in_scratch {
+            encoder.copy_texture_to_texture(
+                self.scratch.texture.as_image_copy(),
+                target.texture.as_image_copy(),
+                size,
+            );
+        }
+    }
+}
+
+// Shader module for a full screen effect, with vs_main from fullscreen.wgsl in front.
+pub fn fullscreen_shader(device: &wgpu::Device, label: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(
+            format!("{}\n{}", include_str!("fullscreen.wgsl"), source).into(),
+        ),
+    })
+}
+
+// Without a layout, bind group 0 is derived from what the entry point uses.
+pub fn fullscreen_pipeline(
+    device: &wgpu::Device,
+    layout: Option<&wgpu::PipelineLayout>,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    target: wgpu::ColorTargetState,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout,
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(target)],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+pub fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+    label: &str,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+enum EffectProgram {
+    Fullscreen(wgpu::RenderPipeline),
+    Compute(wgpu::ComputePipeline),
+}
+
+// Post effect made from a single WGSL shader with its bindings in group 0:
+//   0 input color, texture_2d<f32>
+//   1 linear clamp sampler
+//   2 params uniform, a struct of the params as f32s in order, when there are any
+//   3 depth, texture_depth_2d, when inputs.depth is set
+//   4 view space normals, texture_2d<f32>, when inputs.normals is set
+//   5 output, texture_storage_2d<format, write>, compute effects only
+//   6 ambient light, texture_2d<f32>, when inputs.ambient is set
+//   7 velocity in uv units, texture_2d<f32>, when inputs.velocity is set
+// Full screen effects get vs_main from fullscreen.wgsl and provide fs_main taking a
+// FullscreenOutput, compute effects provide cs_main with an 8x8 workgroup.
+pub struct ShaderEffect {
+    name: String,
+    inputs: EffectInputs,
+    params: Vec<EffectParam>,
+    program: EffectProgram,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: Option<wgpu::Buffer>,
+    sampler: Arc<wgpu::Sampler>,
+}
+
+impl ShaderEffect {
+    pub const WORKGROUP_SIZE: u32 = 8;
+
+    pub fn fullscreen(
+        device: &wgpu::Device,
+        name: &str,
+        source: &str,
+        format: wgpu::TextureFormat,
+        inputs: EffectInputs,
+        params: Vec<EffectParam>,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let shader = fullscreen_shader(device, name, source);
+        let bind_group_layout =
+            Self::create_bind_group_layout(device, name, format, inputs, &params, false);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(name),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = fullscreen_pipeline(
+            device,
+            Some(&layout),
+            &shader,
+            "fs_main",
+            format.into(),
+            name,
+        );
+        Self::new(
+            device,
+            name,
+            inputs,
+            params,
+            EffectProgram::Fullscreen(pipeline),
+            bind_group_layout,
+            sampler,
+        )
+    }
+
+    // format has to support storage writes, which rgba16float does
+    pub fn compute(
+        device: &wgpu::Device,
+        name: &str,
+        source: &str,
+        format: wgpu::TextureFormat,
+        inputs: EffectInputs,
+        params: Vec<EffectParam>,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let bind_group_layout =
+            Self::create_bind_group_layout(device, name, format, inputs, &params, true);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(name),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(name),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        Self::new(
+            device,
+            name,
+            inputs,
+            params,
+            EffectProgram::Compute(pipeline),
+            bind_group_layout,
+            sampler,
+        )
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        name: &str,
+        inputs: EffectInputs,
+        params: Vec<EffectParam>,
+        program: EffectProgram,
+        bind_group_layout: wgpu::BindGroupLayout,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let params_buffer = (!params.is_empty()).then(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(name),
+                size: params_buffer_size(&params),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        Self {
+            name: name.to_string(),
+            inputs,
+            params,
+            program,
+            bind_group_layout,
+            params_buffer,
+            sampler,
+        }
+    }
+
+    fn create_bind_group_layout(
+        device: &wgpu::Device,
+        name: &str,
+        format: wgpu::TextureFormat,
+        inputs: EffectInputs,
+        params: &[EffectParam],
+        compute: bool,
+    ) -> wgpu::BindGroupLayout {
+        let visibility = wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE;
+        let texture = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let filterable = wgpu::TextureSampleType::Float { filterable: true };
+
+        let mut entries = vec![
+            texture(0, filterable),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        if !params.is_empty() {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            });
+        }
+        if inputs.depth {
+            entries.push(texture(3, wgpu::TextureSampleType::Depth));
+        }
+        if inputs.normals {
+            entries.push(texture(4, filterable));
+        }
+        if inputs.ambient {
+            entries.push(texture(6, filterable));
+        }
+        if inputs.velocity {
+            entries.push(texture(7, filterable));
+        }
+        if compute {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            });
+        }
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some(name),
+        })
+    }
+}
+
+// uniform buffers are sized in multiples of 16 bytes
+fn params_buffer_size(params: &[EffectParam]) -> wgpu::BufferAddress {
+    let size = (params.len() * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
+    size.div_ceil(16) * 16
+}
+
+impl PostEffect for ShaderEffect {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn inputs(&self) -> EffectInputs {
+        self.inputs
+    }
+
+    fn params(&self) -> &[EffectParam] {
+        &self.params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match self.params.iter_mut().find(|p| p.name == name) {
+            Some(param) => {
+                param.value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn run(&mut self, context: &mut EffectContext, input: &Texture, output: &Texture) {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&input.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ];
+        if let Some(buffer) = &self.params_buffer {
+            let mut values = self.params.iter().map(|p| p.value).collect::<Vec<_>>();
+            values.resize(buffer.size() as usize / std::mem::size_of::<f32>(), 0.0);
+            context
+                .queue
+                .write_buffer(buffer, 0, bytemuck::cast_slice(&values));
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2,
+                resource: buffer.as_entire_binding(),
+            });
+        }
+        if self.inputs.depth {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&context.scene.depth.view),
+            });
+        }
+        if let (true, Some(normals)) = (self.inputs.normals, context.scene.normals) {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&normals.view),
+            });
+        }
+        if let (true, Some(ambient)) = (self.inputs.ambient, context.scene.ambient) {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&ambient.view),
+            });
+        }
+        if let (true, Some(velocity)) = (self.inputs.velocity, context.scene.velocity) {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::TextureView(&velocity.view),
+            });
+        }
+        if let EffectProgram::Compute(_) = self.program {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&output.view),
+            });
+        }
+
+        // inputs swap every frame with the ping-pong, so the bind group is made per run
+        let bind_group = context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &entries,
+                label: Some(&self.name),
+            });
+
+        match &self.program {
+            EffectProgram::Fullscreen(pipeline) => {
+                draw_fullscreen(
+                    context.encoder,
+                    pipeline,
+                    &bind_group,
+                    &output.view,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    &self.name,
+                );
+            }
+            EffectProgram::Compute(pipeline) => {
+                let mut pass = context
+                    .encoder
+                    .begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some(&self.name),
+                        timestamp_writes: None,
+                    });
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups(
+                    context.width.div_ceil(Self::WORKGROUP_SIZE),
+                    context.height.div_ceil(Self::WORKGROUP_SIZE),
+                    1,
+                );
+            }
+        }
+    }
+}
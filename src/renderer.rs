@@ -1,16 +1,55 @@
-use nalgebra::{Matrix4, Point3, Unit, UnitQuaternion, Vector3};
+use nalgebra::{Matrix4, Point3, Unit, UnitQuaternion, Vector2, Vector3};
+use std::collections::HashMap;
 use std::f32::consts::PI;
+use std::mem;
+use std::path::Path;
 use std::sync::Arc;
 use tokio::runtime::Runtime;
 use wgpu::util::DeviceExt;
 use winit::event_loop::ActiveEventLoop;
 use winit::window::Window;
 
+use crate::anti_aliasing::{AntiAliasing, AntiAliasingPass};
+use crate::bloom::{Bloom, BloomSettings};
 use crate::camera::Camera;
+use crate::debug_draw::DebugDraw;
+use crate::debug_view::{DebugView, DebugViewPass};
+use crate::deferred::{DeferredPass, RenderPath, GBUFFER_FORMATS};
+use crate::depth_prepass::DepthPrepass;
+use crate::ibl::IblMaps;
+use crate::lights::{self, ClusteredLights};
 use crate::model::Model;
-use crate::model::Vertex;
-use crate::model::{self, ModelVertex};
-use crate::texture::Texture;
+use crate::model::{self, AlphaMode, ImportOptions, MaterialUniform, Mesh, DEFAULT_ALPHA_CUTOFF};
+use crate::msaa::{self, Msaa};
+use crate::oit::{OitPass, TransparencyMode};
+use crate::overdraw::OverdrawCounter;
+use crate::post_process::{PostProcessChain, SceneInputs};
+use crate::sampler::{SamplerCache, SamplerDesc};
+use crate::shader_effects;
+use crate::skybox::{Cubemap, Skybox, SkyboxPass};
+use crate::ssao::{Ssao, SsaoSettings};
+use crate::taa::{Taa, TaaSettings};
+use crate::texture::{MipmapGenerator, Texture, TextureRole};
+use crate::tonemap::{TonemapPass, TonemapSettings, HDR_FORMAT};
+use crate::vertex::{ReflectedShader, Vertex, VertexAttributeDesc, VertexLayout, VertexSemantic};
+
+pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+// main pass color attachments: lit color, view space normals, the ambient light and
+// motion since the last frame
+const SCENE_COLOR_FORMATS: [wgpu::TextureFormat; 4] =
+    [HDR_FORMAT, NORMAL_FORMAT, HDR_FORMAT, VELOCITY_FORMAT];
+// tree.png, the texture meshes without a material get, is cut out along its alpha
+const DEFAULT_ALPHA_MODE: AlphaMode = AlphaMode::Mask;
+// cube faces the environment panorama is resampled to
+const ENVIRONMENT_FACE_SIZE: u32 = 256;
+// shows where nothing was drawn and there is no skybox
+const BACKGROUND_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.1,
+    b: 0.1,
+    a: 1.0,
+};
 
 pub struct Renderer<'a> {
     pub surface: wgpu::Surface<'a>,
@@ -18,15 +57,63 @@ pub struct Renderer<'a> {
     pub queue: wgpu::Queue,
     pub surface_config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    pub render_pipeline: wgpu::RenderPipeline,
+    // fill the g-buffer on the deferred path, never multisampled. Only opaque and
+    // masked meshes go in, blended ones are drawn forward after lighting.
+    pub gbuffer_pipelines: HashMap<(VertexLayout, AlphaMode), wgpu::RenderPipeline>,
+    render_path: RenderPath,
+    pub deferred: DeferredPass,
+    // kept to rebuild the scene pipelines when the msaa sample count changes
+    pub shader: ReflectedShader,
+    pub render_pipeline_layout: wgpu::PipelineLayout,
+    pub camera_bind_group_layout: wgpu::BindGroupLayout,
+    // one pipeline per vertex layout used by the loaded meshes and alpha mode
+    pub mesh_pipelines: HashMap<(VertexLayout, AlphaMode), wgpu::RenderPipeline>,
     pub diffuse_bind_group: wgpu::BindGroup,
     pub diffuse_texture: Texture,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
     pub instances: Vec<Instance>,
     pub instance_buffer: wgpu::Buffer,
+    // what was in instance_buffer last frame, its model matrices become the previous ones
+    instance_data: Vec<InstanceRaw>,
+    // unjittered, for motion vectors
+    previous_view_proj: [[f32; 4]; 4],
     pub depth_texture: Texture,
+    pub msaa: Msaa,
+    // the scene is drawn here and tonemapped to the surface
+    pub hdr_texture: Texture,
+    pub normal_texture: Texture,
+    pub ambient_texture: Texture,
+    pub velocity_texture: Texture,
+    // effects applied to the hdr scene before it is tonemapped
+    pub post_process: PostProcessChain,
+    pub tonemap: TonemapPass,
+    pub anti_aliasing: AntiAliasingPass,
     pub obj_model: Model,
+    pub samplers: SamplerCache,
+    // for textures loaded after startup too
+    pub mipmaps: MipmapGenerator,
+    pub skybox_pass: SkyboxPass,
+    // drawn behind the scene when set, otherwise the clear color shows
+    pub skybox: Option<Skybox>,
+    pub ibl: IblMaps,
+    pub environment_bind_group_layout: wgpu::BindGroupLayout,
+    pub environment_bind_group: wgpu::BindGroup,
+    pub lights: ClusteredLights,
+    pub transparency: TransparencyMode,
+    pub oit: OitPass,
+    // draw blended meshes into the oit targets, after the main pass and never
+    // multisampled
+    pub oit_pipelines: HashMap<(VertexLayout, AlphaMode), wgpu::RenderPipeline>,
+    // when set, opaque meshes are drawn depth only first and only their visible
+    // fragments are shaded in the main pass
+    depth_prepass: bool,
+    pub prepass: DepthPrepass,
+    // fragments shaded per pixel in the main pass, None when the adapter can't count them
+    pub overdraw: Option<OverdrawCounter>,
+    pub debug_view: DebugViewPass,
+    // lines and shapes added during the frame, drawn over everything else
+    pub debug_draw: DebugDraw,
 
     // window must outlive surface
     pub window: Arc<winit::window::Window>,
@@ -62,7 +149,17 @@ impl Renderer<'_> {
 
         let device_future = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
+                // bc textures fall back to a cpu decode when the adapter can't sample them,
+                // full precision float textures go without mips and linear filtering, msaa
+                // is limited to 1 and 4 samples without adapter specific format features,
+                // there is no overdraw counter without pipeline statistics and wireframes
+                // are drawn in the shader without line polygon mode
+                required_features: adapter.features()
+                    & (wgpu::Features::TEXTURE_COMPRESSION_BC
+                        | wgpu::Features::FLOAT32_FILTERABLE
+                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                        | wgpu::Features::PIPELINE_STATISTICS_QUERY
+                        | wgpu::Features::POLYGON_MODE_LINE),
                 required_limits: wgpu::Limits::default(),
                 label: None,
                 memory_hints: wgpu::MemoryHints::Performance,
@@ -93,9 +190,36 @@ impl Renderer<'_> {
             desired_maximum_frame_latency: 2,
         };
 
+        let mut samplers = SamplerCache::new();
+        let mut mipmaps = MipmapGenerator::new(&device);
+        // textures record their mip generation here, submitted once everything is loaded
+        let mut upload_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture upload encoder"),
+        });
+
         let diffuse_bytes = include_bytes!("../static/tree.png");
-        let diffuse_texture =
-            Texture::from_bytes(&device, &queue, diffuse_bytes, "../static/tree.png").unwrap();
+        let diffuse_texture = Texture::from_bytes(
+            &device,
+            &queue,
+            &mut upload_encoder,
+            &mut mipmaps,
+            diffuse_bytes,
+            "../static/tree.png",
+            TextureRole::Color,
+            samplers.get(&device, SamplerDesc::ANISOTROPIC_CLAMP),
+        )
+        .unwrap();
+        let flat_normal_texture = Texture::flat_normal(
+            &device,
+            &queue,
+            samplers.get(&device, SamplerDesc::ANISOTROPIC_REPEAT),
+        );
+
+        let white_data_texture = Texture::white_data(
+            &device,
+            &queue,
+            samplers.get(&device, SamplerDesc::ANISOTROPIC_REPEAT),
+        );
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -116,10 +240,94 @@ impl Renderer<'_> {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // roughness, metallic and occlusion maps
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
 
+        let default_material_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Default material buffer"),
+                contents: bytemuck::cast_slice(&[
+                    MaterialUniform::default().with_alpha(DEFAULT_ALPHA_CUTOFF, 1.0)
+                ]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
         let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &texture_bind_group_layout,
             entries: &[
@@ -131,14 +339,52 @@ impl Renderer<'_> {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&flat_normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&flat_normal_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: default_material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&white_data_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&white_data_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&white_data_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&white_data_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&white_data_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&white_data_texture.sampler),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
+        let shader = ReflectedShader::new(
+            &device,
+            &lights::scene_shader_source(include_str!("shader.wgsl")),
+            "Shader",
+        )
+        .expect("error parsing shader");
 
         camera.aspect = surface_config.width as f32 / surface_config.height as f32;
         let camera_uniform = camera.get_uniform();
@@ -153,7 +399,8 @@ impl Renderer<'_> {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // the skybox rebuilds view rays per pixel
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -177,70 +424,197 @@ impl Renderer<'_> {
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance buffer"),
             contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
         let depth_texture =
-            Texture::create_depth_texture(&device, &surface_config, "depth_texture");
+            Texture::create_depth_texture(&device, &surface_config, 1, "depth_texture");
+        let mut msaa_formats = SCENE_COLOR_FORMATS.to_vec();
+        msaa_formats.push(Texture::DEPTH_FORMAT);
+        let msaa = Msaa::new(
+            &device,
+            &surface_config,
+            &SCENE_COLOR_FORMATS,
+            msaa::supported_sample_counts(&adapter, device.features(), &msaa_formats),
+            4,
+        );
+        let hdr_texture =
+            Texture::create_render_target(&device, &surface_config, HDR_FORMAT, "hdr_texture");
+        let normal_texture = Texture::create_render_target(
+            &device,
+            &surface_config,
+            NORMAL_FORMAT,
+            "normal_texture",
+        );
+        let ambient_texture =
+            Texture::create_render_target(&device, &surface_config, HDR_FORMAT, "ambient_texture");
+        let velocity_texture = Texture::create_render_target(
+            &device,
+            &surface_config,
+            VELOCITY_FORMAT,
+            "velocity_texture",
+        );
+        let mut post_process = PostProcessChain::new(&device, &surface_config, HDR_FORMAT);
+        post_process.push(Box::new(Ssao::new(
+            &device,
+            &surface_config,
+            HDR_FORMAT,
+            SsaoSettings::default(),
+        )));
+        post_process.push(Box::new(Taa::new(
+            &device,
+            &surface_config,
+            HDR_FORMAT,
+            TaaSettings::default(),
+            samplers.get(&device, SamplerDesc::LINEAR_CLAMP),
+        )));
+        post_process.push(Box::new(Bloom::new(
+            &device,
+            &surface_config,
+            HDR_FORMAT,
+            BloomSettings::default(),
+            samplers.get(&device, SamplerDesc::LINEAR_CLAMP),
+        )));
+        // after bloom so the glow is darkened and sharpened with the rest, both start off
+        // and are toggled from the keyboard
+        post_process.push(Box::new(shader_effects::vignette(
+            &device,
+            HDR_FORMAT,
+            samplers.get(&device, SamplerDesc::LINEAR_CLAMP),
+        )));
+        post_process.push(Box::new(shader_effects::sharpen(
+            &device,
+            HDR_FORMAT,
+            samplers.get(&device, SamplerDesc::LINEAR_CLAMP),
+        )));
+        post_process.set_enabled(shader_effects::VIGNETTE, false);
+        post_process.set_enabled(shader_effects::SHARPEN, false);
+        let tonemap = TonemapPass::new(
+            &device,
+            &hdr_texture,
+            surface_config.format,
+            TonemapSettings::default(),
+        );
+        let anti_aliasing = AntiAliasingPass::new(
+            &device,
+            &queue,
+            &surface_config,
+            AntiAliasing::Off,
+            &mut samplers,
+        );
+
+        // flat gray ambient light until an environment is set
+        let ibl = IblMaps::uniform(
+            &device,
+            &queue,
+            [0.1, 0.1, 0.1],
+            &mut samplers,
+            &mut mipmaps,
+        );
+        let environment_bind_group_layout = IblMaps::bind_group_layout(&device);
+        let environment_bind_group = ibl.bind_group(&device, &environment_bind_group_layout);
+
+        let lights = ClusteredLights::new(
+            &device,
+            &surface_config,
+            &camera_buffer,
+            &camera_bind_group_layout,
+            &depth_texture,
+        );
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render pipeline layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &environment_bind_group_layout,
+                    &lights.bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                buffers: &[ModelVertex::desc(), InstanceRaw::vertex_buffer_layout()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        });
+        let deferred = DeferredPass::new(
+            &device,
+            &surface_config,
+            &depth_texture,
+            &camera_bind_group_layout,
+            &environment_bind_group_layout,
+            &lights.bind_group_layout,
+        );
 
         let obj_model = model::load_model(
-            "../static/cube.obj",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/static/cube.obj"),
             &device,
             &queue,
+            &mut upload_encoder,
             &texture_bind_group_layout,
+            &mut samplers,
+            &mut mipmaps,
+            &ImportOptions::from_args(std::env::args().skip(1)),
         )
         .unwrap();
+        queue.submit(std::iter::once(upload_encoder.finish()));
+
+        let skybox_pass = SkyboxPass::new(
+            &device,
+            &camera_bind_group_layout,
+            &SCENE_COLOR_FORMATS,
+            msaa.multisample_state(),
+        );
+
+        let mesh_pipelines = create_mesh_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            |alpha_mode| scene_color_targets(&SCENE_COLOR_FORMATS, alpha_mode),
+            &obj_model.meshes,
+            &AlphaMode::ALL,
+            msaa.multisample_state(),
+            "fs_main",
+            false,
+        );
+        let gbuffer_pipelines = create_mesh_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            |alpha_mode| scene_color_targets(&GBUFFER_FORMATS, alpha_mode),
+            &obj_model.meshes,
+            &[AlphaMode::Opaque, AlphaMode::Mask],
+            wgpu::MultisampleState::default(),
+            "fs_gbuffer",
+            false,
+        );
+        let oit = OitPass::new(&device, &surface_config);
+        let oit_pipelines = create_mesh_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            |_| OitPass::targets(),
+            &obj_model.meshes,
+            &[AlphaMode::Blend],
+            wgpu::MultisampleState::default(),
+            "fs_oit",
+            false,
+        );
+        let prepass = DepthPrepass::new(
+            &device,
+            &camera_bind_group_layout,
+            &mesh_vertex_layouts(&obj_model.meshes),
+            &InstanceRaw::layout(),
+            msaa.multisample_state(),
+        );
+        let overdraw = OverdrawCounter::new(&device);
+        let debug_view = DebugViewPass::new(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            &camera_bind_group_layout,
+            &obj_model.meshes,
+            &InstanceRaw::layout(),
+            surface_config.format,
+        );
+        let debug_draw = DebugDraw::new(&device, &camera_bind_group_layout, surface_config.format);
 
         Self {
             window,
@@ -249,20 +623,190 @@ impl Renderer<'_> {
             queue,
             surface_config,
             size,
-            render_pipeline,
+            gbuffer_pipelines,
+            render_path: RenderPath::Forward,
+            deferred,
+            shader,
+            render_pipeline_layout,
+            camera_bind_group_layout,
+            mesh_pipelines,
             diffuse_bind_group,
             diffuse_texture,
             camera_buffer,
             camera_bind_group,
             instances,
             instance_buffer,
+            instance_data: Vec::new(),
+            previous_view_proj: camera_uniform.unjittered_view_proj,
             depth_texture,
+            msaa,
+            hdr_texture,
+            normal_texture,
+            ambient_texture,
+            velocity_texture,
+            post_process,
+            tonemap,
+            anti_aliasing,
             obj_model,
+            samplers,
+            mipmaps,
+            skybox_pass,
+            skybox: None,
+            ibl,
+            environment_bind_group_layout,
+            environment_bind_group,
+            lights,
+            transparency: TransparencyMode::Sorted,
+            oit,
+            oit_pipelines,
+            depth_prepass: false,
+            prepass,
+            overdraw,
+            debug_view,
+            debug_draw,
         }
     }
 
-    pub fn render_pass(&mut self, camera: &Camera, ) {
-        let camera_uniform = camera.get_uniform();
+    // Lights the scene with maps made by IblMaps::from_environment or loaded from a cache.
+    pub fn set_environment(&mut self, ibl: IblMaps) {
+        self.environment_bind_group =
+            ibl.bind_group(&self.device, &self.environment_bind_group_layout);
+        self.ibl = ibl;
+    }
+
+    pub fn set_skybox(&mut self, cubemap: &Cubemap) {
+        self.skybox = Some(self.skybox_pass.create_skybox(&self.device, cubemap));
+    }
+
+    // Sky and image based lighting from an equirectangular .hdr or .exr panorama. The
+    // ibl maps are cached at ibl_cache_path and regenerated when the panorama changes.
+    // The sky is set first, so a failure in the ibl work still leaves it.
+    pub fn load_environment(
+        &mut self,
+        hdr_bytes: &[u8],
+        ibl_cache_path: &Path,
+    ) -> anyhow::Result<()> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Environment encoder"),
+            });
+        let cubemap = Cubemap::from_hdr(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &mut self.mipmaps,
+            hdr_bytes,
+            ENVIRONMENT_FACE_SIZE,
+            "environment",
+            self.samplers
+                .get(&self.device, SamplerDesc::TRILINEAR_CLAMP),
+        )?;
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.set_skybox(&cubemap);
+        let ibl = IblMaps::from_environment_cached(
+            &self.device,
+            &self.queue,
+            &cubemap,
+            hdr_bytes,
+            ibl_cache_path,
+            &mut self.samplers,
+        )?;
+        self.set_environment(ibl);
+        Ok(())
+    }
+
+    // Picks the largest supported msaa sample count up to sample_count and rebuilds
+    // what draws into the main pass. Returns the count in use, which is always 1 on the
+    // deferred path.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        let sample_count = match self.render_path {
+            RenderPath::Forward => sample_count,
+            RenderPath::Deferred => 1,
+        };
+        if self
+            .msaa
+            .set_sample_count(&self.device, &self.surface_config, sample_count)
+        {
+            self.rebuild_scene_pipelines();
+        }
+        self.msaa.sample_count()
+    }
+
+    pub fn render_path(&self) -> RenderPath {
+        self.render_path
+    }
+
+    // The g-buffer isn't multisampled, so switching to the deferred path turns msaa off.
+    // It stays off when switching back until set_sample_count is called again.
+    pub fn set_render_path(&mut self, render_path: RenderPath) {
+        self.render_path = render_path;
+        if render_path == RenderPath::Deferred {
+            self.set_sample_count(1);
+        }
+    }
+
+    pub fn depth_prepass(&self) -> bool {
+        self.depth_prepass
+    }
+
+    pub fn set_depth_prepass(&mut self, depth_prepass: bool) {
+        self.depth_prepass = depth_prepass;
+        self.rebuild_scene_pipelines();
+    }
+
+    fn rebuild_scene_pipelines(&mut self) {
+        let multisample = self.msaa.multisample_state();
+        self.mesh_pipelines = create_mesh_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            |alpha_mode| scene_color_targets(&SCENE_COLOR_FORMATS, alpha_mode),
+            &self.obj_model.meshes,
+            &AlphaMode::ALL,
+            multisample,
+            "fs_main",
+            self.depth_prepass,
+        );
+        self.gbuffer_pipelines = create_mesh_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            |alpha_mode| scene_color_targets(&GBUFFER_FORMATS, alpha_mode),
+            &self.obj_model.meshes,
+            &[AlphaMode::Opaque, AlphaMode::Mask],
+            wgpu::MultisampleState::default(),
+            "fs_gbuffer",
+            self.depth_prepass,
+        );
+        self.prepass.rebuild_pipelines(
+            &self.device,
+            &mesh_vertex_layouts(&self.obj_model.meshes),
+            &InstanceRaw::layout(),
+            multisample,
+        );
+        self.skybox_pass.rebuild_pipeline(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &SCENE_COLOR_FORMATS,
+            multisample,
+        );
+    }
+
+    // camera is mutable so taa can jitter it
+    pub fn render_pass(&mut self, camera: &mut Camera) {
+        let taa_enabled = self.post_process.is_enabled(Taa::NAME);
+        camera.jitter = match self.post_process.get_mut::<Taa>(Taa::NAME) {
+            Some(taa) if taa_enabled => taa.begin_frame(
+                camera,
+                self.surface_config.width,
+                self.surface_config.height,
+            ),
+            _ => Vector2::zeros(),
+        };
+        let mut camera_uniform = camera.get_uniform();
+        camera_uniform.prev_view_proj = self.previous_view_proj;
+        self.previous_view_proj = camera_uniform.unjittered_view_proj;
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
@@ -280,26 +824,180 @@ impl Renderer<'_> {
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                     label: Some("Render encoder"),
                 });
+        self.update_instance_buffer();
+        self.lights.update(
+            &self.queue,
+            &mut command_encoder,
+            camera,
+            self.surface_config.width,
+            self.surface_config.height,
+        );
+
+        // weighted blended transparency draws the blended meshes after the main pass
+        let blended_draws = match self.transparency {
+            TransparencyMode::Sorted => self.sort_blended_draws(camera),
+            TransparencyMode::WeightedBlended => Vec::new(),
+        };
+        if self.depth_prepass {
+            self.encode_depth_prepass(&mut command_encoder);
+        }
+        match self.render_path {
+            RenderPath::Forward => self.encode_forward(&mut command_encoder, &blended_draws),
+            RenderPath::Deferred => self.encode_deferred(&mut command_encoder, &blended_draws),
+        }
+        if let Some(overdraw) = &mut self.overdraw {
+            overdraw.resolve(
+                &mut command_encoder,
+                self.surface_config.width * self.surface_config.height,
+            );
+        }
+
+        self.msaa
+            .resolve_depth(&mut command_encoder, &self.depth_texture);
+        if self.transparency == TransparencyMode::WeightedBlended {
+            self.encode_oit(&mut command_encoder);
+        }
+        self.post_process.run(
+            &self.device,
+            &self.queue,
+            &mut command_encoder,
+            &self.hdr_texture,
+            SceneInputs {
+                depth: &self.depth_texture,
+                normals: Some(&self.normal_texture),
+                ambient: Some(&self.ambient_texture),
+                velocity: Some(&self.velocity_texture),
+                camera_buffer: &self.camera_buffer,
+            },
+        );
+        let tonemap_output = self
+            .anti_aliasing
+            .target()
+            .map_or(&texture_view, |target| &target.view);
+        self.tonemap.encode(
+            &self.queue,
+            &mut command_encoder,
+            &self.hdr_texture,
+            tonemap_output,
+        );
+        self.anti_aliasing
+            .encode(&mut command_encoder, &texture_view);
+        if self.render_path == RenderPath::Deferred {
+            self.deferred.draw_debug_view(
+                &self.queue,
+                &mut command_encoder,
+                &self.camera_bind_group,
+                &texture_view,
+            );
+        }
+        self.encode_debug_view(&mut command_encoder, &texture_view);
+        self.lights
+            .draw_heatmap(&mut command_encoder, &self.camera_bind_group, &texture_view);
+        self.debug_draw.encode(
+            &self.device,
+            &self.queue,
+            &mut command_encoder,
+            &self.camera_bind_group,
+            &texture_view,
+            &self.depth_texture,
+        );
+
+        self.queue.submit(std::iter::once(command_encoder.finish()));
+        if let Some(overdraw) = &mut self.overdraw {
+            overdraw.read_back(&self.device);
+        }
+        surface_texture.present();
+        self.window.request_redraw();
+    }
+
+    // The scene lit as it is drawn, with msaa when it is on.
+    fn encode_forward(&self, encoder: &mut wgpu::CommandEncoder, blended_draws: &[BlendedDraw]) {
+        // with msaa the scene draws into the multisampled targets and resolves into these
+        let resolve_targets = [
+            &self.hdr_texture,
+            &self.normal_texture,
+            &self.ambient_texture,
+            &self.velocity_texture,
+        ];
+        let clear_colors = [
+            BACKGROUND_COLOR,
+            wgpu::Color::TRANSPARENT,
+            wgpu::Color::TRANSPARENT,
+            wgpu::Color::TRANSPARENT,
+        ];
+        let color_attachments = resolve_targets
+            .iter()
+            .zip(clear_colors)
+            .enumerate()
+            .map(|(index, (target, clear))| {
+                let (view, resolve_target) = match self.msaa.color_target(index) {
+                    Some(multisampled) => (&multisampled.view, Some(&target.view)),
+                    None => (&target.view, None),
+                };
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
         {
-            let mut render_pass = command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &texture_view,
+                color_attachments: &color_attachments,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.scene_depth_target().view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: self.scene_depth_load(),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            self.draw_counted(&mut render_pass, &self.mesh_pipelines);
+
+            // after opaque meshes, so it only shades pixels they left at the far plane
+            if let Some(skybox) = &self.skybox {
+                self.skybox_pass
+                    .draw(&mut render_pass, skybox, &self.camera_bind_group);
+            }
+            self.draw_blended(&mut render_pass, blended_draws);
+        }
+    }
+
+    // The scene drawn into the g-buffer, then lit once per pixel into the same targets
+    // the forward path fills. Blended meshes can't go into the g-buffer and are drawn
+    // forward over the result.
+    fn encode_deferred(&self, encoder: &mut wgpu::CommandEncoder, blended_draws: &[BlendedDraw]) {
+        let mut targets = self.deferred.targets().to_vec();
+        targets.push(&self.velocity_texture);
+        let color_attachments = targets
+            .iter()
+            .map(|target| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.1,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
                         store: wgpu::StoreOp::Store,
                     },
-                })],
+                })
+            })
+            .collect::<Vec<_>>();
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Gbuffer pass"),
+                color_attachments: &color_attachments,
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: self.scene_depth_load(),
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -307,21 +1005,304 @@ impl Renderer<'_> {
                 occlusion_query_set: None,
                 timestamp_writes: None,
             });
+            self.draw_counted(&mut render_pass, &self.gbuffer_pipelines);
+        }
+
+        self.deferred.light(
+            encoder,
+            &self.camera_bind_group,
+            &self.environment_bind_group,
+            &self.lights.bind_group,
+            [
+                &self.hdr_texture,
+                &self.normal_texture,
+                &self.ambient_texture,
+            ],
+            BACKGROUND_COLOR,
+        );
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            let mesh = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
-            let instance_count = self.instances.len();
-            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+        if self.skybox.is_none() && blended_draws.is_empty() {
+            return;
         }
+        let scene_targets = [
+            &self.hdr_texture,
+            &self.normal_texture,
+            &self.ambient_texture,
+            &self.velocity_texture,
+        ];
+        let color_attachments = scene_targets
+            .iter()
+            .map(|target| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        // the skybox only shades pixels the g-buffer pass left at the far plane
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Forward pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        if let Some(skybox) = &self.skybox {
+            self.skybox_pass
+                .draw(&mut render_pass, skybox, &self.camera_bind_group);
+        }
+        self.draw_blended(&mut render_pass, blended_draws);
+    }
 
-        self.queue.submit(std::iter::once(command_encoder.finish()));
-        surface_texture.present();
-        self.window.request_redraw();
+    // Opaque meshes into the depth target of the main pass, with no color and only the
+    // vertex positions fetched.
+    fn encode_depth_prepass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.scene_depth_target().view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        let instance_count = self.instances.len() as u32;
+        for mesh in &self.obj_model.meshes {
+            if self.material_for(mesh).0 != AlphaMode::Opaque {
+                continue;
+            }
+            render_pass.set_pipeline(self.prepass.pipeline(&mesh.vertex_layout));
+            model::draw_mesh_instanced(&mut render_pass, mesh, 0..instance_count);
+        }
+    }
+
+    // Every mesh over output in the debug view, nothing while it is off. Depth is
+    // drawn again from scratch, blended meshes included.
+    fn encode_debug_view(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if self.debug_view.view == DebugView::Off {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug view pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        self.set_scene_bind_groups(&mut render_pass);
+        let instance_count = self.instances.len() as u32;
+        for (mesh_index, mesh) in self.obj_model.meshes.iter().enumerate() {
+            let (_, material_bind_group) = self.material_for(mesh);
+            self.debug_view.draw_mesh(
+                &mut render_pass,
+                mesh_index,
+                mesh,
+                material_bind_group,
+                &self.instance_buffer,
+                0..instance_count,
+            );
+        }
+    }
+
+    // the multisampled depth under msaa, the deferred path never has it
+    fn scene_depth_target(&self) -> &Texture {
+        self.msaa.depth_target().unwrap_or(&self.depth_texture)
+    }
+
+    // the main pass keeps what the depth prepass drew
+    fn scene_depth_load(&self) -> wgpu::LoadOp<f32> {
+        if self.depth_prepass {
+            wgpu::LoadOp::Load
+        } else {
+            wgpu::LoadOp::Clear(1.0)
+        }
+    }
+
+    // Draws the opaque and masked meshes, counting the fragments they shade when the
+    // overdraw counter is available.
+    fn draw_counted<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a HashMap<(VertexLayout, AlphaMode), wgpu::RenderPipeline>,
+    ) {
+        if let Some(overdraw) = &self.overdraw {
+            overdraw.begin(render_pass);
+        }
+        self.draw_meshes(render_pass, pipelines, AlphaMode::Opaque);
+        self.draw_meshes(render_pass, pipelines, AlphaMode::Mask);
+        if let Some(overdraw) = &self.overdraw {
+            overdraw.end(render_pass);
+        }
+    }
+
+    // Every blended mesh at once into the oit targets, tested against the resolved depth
+    // of the main pass, then averaged over the lit scene.
+    fn encode_oit(&self, encoder: &mut wgpu::CommandEncoder) {
+        {
+            let mut render_pass = self.oit.begin_accumulation(encoder, &self.depth_texture);
+            self.draw_meshes(&mut render_pass, &self.oit_pipelines, AlphaMode::Blend);
+        }
+        self.oit.composite(encoder, &self.hdr_texture);
+    }
+
+    // the material bind group of mesh and how its alpha is used
+    fn material_for(&self, mesh: &Mesh) -> (AlphaMode, &wgpu::BindGroup) {
+        self.obj_model
+            .materials
+            .get(mesh.material_id as usize)
+            .map_or((DEFAULT_ALPHA_MODE, &self.diffuse_bind_group), |material| {
+                (material.alpha_mode, &material.bind_group)
+            })
+    }
+
+    fn set_scene_bind_groups<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.environment_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.lights.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+    }
+
+    // Draws every instance of the meshes whose material uses alpha_mode.
+    fn draw_meshes<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a HashMap<(VertexLayout, AlphaMode), wgpu::RenderPipeline>,
+        alpha_mode: AlphaMode,
+    ) {
+        self.set_scene_bind_groups(render_pass);
+        let instance_count = self.instances.len() as u32;
+        for mesh in &self.obj_model.meshes {
+            let (mesh_alpha_mode, material_bind_group) = self.material_for(mesh);
+            if mesh_alpha_mode != alpha_mode {
+                continue;
+            }
+            render_pass.set_pipeline(&pipelines[&(mesh.vertex_layout.clone(), alpha_mode)]);
+            render_pass.set_bind_group(0, material_bind_group, &[]);
+            model::draw_mesh_instanced(render_pass, mesh, 0..instance_count);
+        }
+    }
+
+    // Draws blended mesh instances one at a time in the order given.
+    fn draw_blended<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, draws: &[BlendedDraw]) {
+        self.set_scene_bind_groups(render_pass);
+        for draw in draws {
+            let mesh = &self.obj_model.meshes[draw.mesh];
+            let (_, material_bind_group) = self.material_for(mesh);
+            render_pass.set_pipeline(self.pipeline_for(mesh, AlphaMode::Blend));
+            render_pass.set_bind_group(0, material_bind_group, &[]);
+            model::draw_mesh_instanced(render_pass, mesh, draw.instance..draw.instance + 1);
+        }
+    }
+
+    // World space boxes around every instance of every mesh, from the instance data
+    // uploaded last frame.
+    pub fn instance_bounds(&self) -> Vec<(Point3<f32>, Point3<f32>)> {
+        let mut bounds = Vec::new();
+        for mesh in &self.obj_model.meshes {
+            for raw in &self.instance_data {
+                let model = Matrix4::from(raw.model);
+                let corner = |i: usize| {
+                    let pick = |axis: usize| {
+                        if i & (1 << axis) == 0 {
+                            mesh.min[axis]
+                        } else {
+                            mesh.max[axis]
+                        }
+                    };
+                    model.transform_point(&Point3::new(pick(0), pick(1), pick(2)))
+                };
+                let first = corner(0);
+                bounds.push(
+                    (1..8)
+                        .map(corner)
+                        .fold((first, first), |(min, max), p| (min.inf(&p), max.sup(&p))),
+                );
+            }
+        }
+        bounds
+    }
+
+    // Every instance of a blended mesh, the farthest from the camera first so each one
+    // blends over what is behind it. Needs this frame's instance data.
+    fn sort_blended_draws(&self, camera: &Camera) -> Vec<BlendedDraw> {
+        let view = camera.build_view_matrix();
+        let mut draws = Vec::new();
+        for (mesh_index, mesh) in self.obj_model.meshes.iter().enumerate() {
+            if self.material_for(mesh).0 != AlphaMode::Blend {
+                continue;
+            }
+            for (instance, raw) in self.instance_data.iter().enumerate() {
+                let center = Matrix4::from(raw.model).transform_point(&Point3::from(mesh.center));
+                let view_depth = -view.transform_point(&center).z;
+                let draw = BlendedDraw {
+                    mesh: mesh_index,
+                    instance: instance as u32,
+                };
+                draws.push((view_depth, draw));
+            }
+        }
+        draws.sort_by(|a, b| b.0.total_cmp(&a.0));
+        draws.into_iter().map(|(_, draw)| draw).collect()
+    }
+
+    // Uploads the instances with last frame's model matrices next to the current ones,
+    // growing the buffer when instances were added.
+    fn update_instance_buffer(&mut self) {
+        let instance_data = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| instance.to_raw(self.instance_data.get(index)))
+            .collect::<Vec<_>>();
+        let contents: &[u8] = bytemuck::cast_slice(&instance_data);
+        if (contents.len() as wgpu::BufferAddress) > self.instance_buffer.size() {
+            self.instance_buffer =
+                self.device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Instance buffer"),
+                        contents,
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    });
+        } else {
+            self.queue.write_buffer(&self.instance_buffer, 0, contents);
+        }
+        self.instance_data = instance_data;
+    }
+
+    pub fn pipeline_for(&self, mesh: &Mesh, alpha_mode: AlphaMode) -> &wgpu::RenderPipeline {
+        &self.mesh_pipelines[&(mesh.vertex_layout.clone(), alpha_mode)]
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>, camera: &mut Camera) {
@@ -330,24 +1311,224 @@ impl Renderer<'_> {
             self.surface_config.width = new_size.width;
             self.surface_config.height = new_size.height;
             self.surface.configure(&self.device, &self.surface_config);
-            self.depth_texture =
-                Texture::create_depth_texture(&self.device, &self.surface_config, "depth_texture");
+            self.depth_texture = Texture::create_depth_texture(
+                &self.device,
+                &self.surface_config,
+                1,
+                "depth_texture",
+            );
+            self.msaa.resize(&self.device, &self.surface_config);
+            self.deferred
+                .resize(&self.device, &self.surface_config, &self.depth_texture);
+            self.lights.resize(&self.device, &self.depth_texture);
+            self.oit.resize(&self.device, &self.surface_config);
+            self.hdr_texture = Texture::create_render_target(
+                &self.device,
+                &self.surface_config,
+                HDR_FORMAT,
+                "hdr_texture",
+            );
+            self.normal_texture = Texture::create_render_target(
+                &self.device,
+                &self.surface_config,
+                NORMAL_FORMAT,
+                "normal_texture",
+            );
+            self.ambient_texture = Texture::create_render_target(
+                &self.device,
+                &self.surface_config,
+                HDR_FORMAT,
+                "ambient_texture",
+            );
+            self.velocity_texture = Texture::create_render_target(
+                &self.device,
+                &self.surface_config,
+                VELOCITY_FORMAT,
+                "velocity_texture",
+            );
+            self.post_process.resize(&self.device, &self.surface_config);
+            self.tonemap.resize(&self.device, &self.hdr_texture);
+            self.anti_aliasing
+                .resize(&self.device, &self.surface_config);
             camera.aspect = new_size.width as f32 / new_size.height as f32;
         }
     }
 }
 
+// A pipeline for every vertex layout the meshes use in every one of alpha_modes.
+#[allow(clippy::too_many_arguments)]
+fn create_mesh_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &ReflectedShader,
+    color_targets: fn(AlphaMode) -> Vec<Option<wgpu::ColorTargetState>>,
+    meshes: &[Mesh],
+    alpha_modes: &[AlphaMode],
+    multisample: wgpu::MultisampleState,
+    fragment_entry_point: &str,
+    depth_prepass: bool,
+) -> HashMap<(VertexLayout, AlphaMode), wgpu::RenderPipeline> {
+    let mut pipelines = HashMap::new();
+    for mesh in meshes {
+        for &alpha_mode in alpha_modes {
+            let key = (mesh.vertex_layout.clone(), alpha_mode);
+            if pipelines.contains_key(&key) {
+                continue;
+            }
+            let pipeline = create_render_pipeline(
+                device,
+                layout,
+                shader,
+                &color_targets(alpha_mode),
+                &[mesh.vertex_layout.clone(), InstanceRaw::layout()],
+                multisample,
+                fragment_entry_point,
+                alpha_mode,
+                depth_prepass,
+                "Mesh render pipeline",
+            )
+            .unwrap();
+            pipelines.insert(key, pipeline);
+        }
+    }
+    pipelines
+}
+
+// The color targets of the main pass or the g-buffer pass for meshes in alpha_mode.
+fn scene_color_targets(
+    color_formats: &[wgpu::TextureFormat],
+    alpha_mode: AlphaMode,
+) -> Vec<Option<wgpu::ColorTargetState>> {
+    color_formats
+        .iter()
+        .enumerate()
+        .map(|(index, &format)| {
+            // blended meshes only show up in color, the other targets keep what is behind
+            let (blend, write_mask) = match alpha_mode {
+                AlphaMode::Blend if index == 0 => {
+                    (wgpu::BlendState::ALPHA_BLENDING, wgpu::ColorWrites::ALL)
+                }
+                AlphaMode::Blend => (wgpu::BlendState::REPLACE, wgpu::ColorWrites::empty()),
+                AlphaMode::Opaque | AlphaMode::Mask => {
+                    (wgpu::BlendState::REPLACE, wgpu::ColorWrites::ALL)
+                }
+            };
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask,
+            })
+        })
+        .collect()
+}
+
+// Picks the shader's vertex entry point that fits the vertex layouts best and only
+// hands it the attributes it reads.
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &ReflectedShader,
+    targets: &[Option<wgpu::ColorTargetState>],
+    vertex_layouts: &[VertexLayout],
+    multisample: wgpu::MultisampleState,
+    fragment_entry_point: &str,
+    alpha_mode: AlphaMode,
+    depth_prepass: bool,
+    label: &str,
+) -> anyhow::Result<wgpu::RenderPipeline> {
+    let (vertex_entry_point, matched_layouts) = shader.best_vertex_entry(vertex_layouts)?;
+    let buffers = matched_layouts
+        .iter()
+        .map(VertexLayout::buffer_layout)
+        .collect::<Vec<_>>();
+    // without msaa there is no coverage to turn alpha into, the shader discards instead
+    let alpha_to_coverage = alpha_mode == AlphaMode::Mask && multisample.count > 1;
+    // opaque meshes are already in the depth buffer after a prepass, only the nearest
+    // surface passes and there is nothing left to write. Blended meshes are sorted or
+    // averaged instead, and mustn't hide what is drawn after them.
+    let (depth_write_enabled, depth_compare) = match alpha_mode {
+        AlphaMode::Opaque if depth_prepass => (false, wgpu::CompareFunction::Equal),
+        AlphaMode::Opaque | AlphaMode::Mask => (true, wgpu::CompareFunction::Less),
+        AlphaMode::Blend => (false, wgpu::CompareFunction::Less),
+    };
+    let constants = HashMap::from([(
+        "ALPHA_TO_COVERAGE".to_string(),
+        if alpha_to_coverage { 1.0 } else { 0.0 },
+    )]);
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader.module,
+            entry_point: vertex_entry_point,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            buffers: &buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader.module,
+            entry_point: fragment_entry_point,
+            targets,
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            },
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            alpha_to_coverage_enabled: alpha_to_coverage,
+            ..multisample
+        },
+        multiview: None,
+        cache: None,
+    });
+    Ok(pipeline)
+}
+
+// every vertex layout the meshes use, repeats included
+fn mesh_vertex_layouts(meshes: &[Mesh]) -> Vec<VertexLayout> {
+    meshes
+        .iter()
+        .map(|mesh| mesh.vertex_layout.clone())
+        .collect()
+}
+
+// one instance of a blended mesh, index into the model's meshes and the instances
+#[derive(Copy, Clone, Debug)]
+struct BlendedDraw {
+    mesh: usize,
+    instance: u32,
+}
+
 pub struct Instance {
     translation: Vector3<f32>,
     rotation: UnitQuaternion<f32>,
 }
 
 impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
+    // previous is what this instance was uploaded as last frame, if it existed then
+    fn to_raw(&self, previous: Option<&InstanceRaw>) -> InstanceRaw {
         let translation_matrix = Matrix4::new_translation(&self.translation);
         let rotation_matrix = self.rotation.to_homogeneous();
+        let model = (translation_matrix * rotation_matrix).into();
         InstanceRaw {
-            model: (translation_matrix * rotation_matrix).into(),
+            model,
+            prev_model: previous.map_or(model, |previous| previous.model),
         }
     }
 }
@@ -356,37 +1537,51 @@ impl Instance {
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct InstanceRaw {
     model: [[f32; 4]; 4],
+    prev_model: [[f32; 4]; 4],
 }
 
-impl InstanceRaw {
-    fn vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
-        }
-    }
+impl Vertex for InstanceRaw {
+    const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::Instance;
+    const ATTRIBUTES: &'static [VertexAttributeDesc] = &[
+        VertexAttributeDesc::new(
+            VertexSemantic::ModelMatrix(0),
+            wgpu::VertexFormat::Float32x4,
+            0,
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::ModelMatrix(1),
+            wgpu::VertexFormat::Float32x4,
+            mem::size_of::<[f32; 4]>(),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::ModelMatrix(2),
+            wgpu::VertexFormat::Float32x4,
+            mem::size_of::<[f32; 8]>(),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::ModelMatrix(3),
+            wgpu::VertexFormat::Float32x4,
+            mem::size_of::<[f32; 12]>(),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::PrevModelMatrix(0),
+            wgpu::VertexFormat::Float32x4,
+            mem::size_of::<[f32; 16]>(),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::PrevModelMatrix(1),
+            wgpu::VertexFormat::Float32x4,
+            mem::size_of::<[f32; 20]>(),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::PrevModelMatrix(2),
+            wgpu::VertexFormat::Float32x4,
+            mem::size_of::<[f32; 24]>(),
+        ),
+        VertexAttributeDesc::new(
+            VertexSemantic::PrevModelMatrix(3),
+            wgpu::VertexFormat::Float32x4,
+            mem::size_of::<[f32; 28]>(),
+        ),
+    ];
 }
-
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+// Everything that makes two samplers different. Textures share one wgpu::Sampler per
+// distinct description through SamplerCache.
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerDesc {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    // 1 disables anisotropic filtering, wgpu only allows more when every filter is linear
+    pub anisotropy_clamp: u16,
+    pub lod_min_clamp: f32,
+    pub lod_max_clamp: f32,
+    pub compare: Option<wgpu::CompareFunction>,
+}
+
+impl SamplerDesc {
+    pub const TRILINEAR_REPEAT: Self = Self {
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        anisotropy_clamp: 1,
+        lod_min_clamp: 0.0,
+        lod_max_clamp: 32.0,
+        compare: None,
+    };
+
+    pub const TRILINEAR_CLAMP: Self = Self {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        ..Self::TRILINEAR_REPEAT
+    };
+
+    pub const ANISOTROPIC_REPEAT: Self = Self {
+        anisotropy_clamp: 16,
+        ..Self::TRILINEAR_REPEAT
+    };
+
+    pub const ANISOTROPIC_CLAMP: Self = Self {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        ..Self::ANISOTROPIC_REPEAT
+    };
+
+    // for full screen passes and lookup textures that are sampled at their own resolution
+    pub const LINEAR_CLAMP: Self = Self {
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        anisotropy_clamp: 1,
+        ..Self::ANISOTROPIC_CLAMP
+    };
+
+    pub const NEAREST_CLAMP: Self = Self {
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Nearest,
+        ..Self::LINEAR_CLAMP
+    };
+
+    pub fn with_address_mode(self, address_mode: wgpu::AddressMode) -> Self {
+        Self {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            ..self
+        }
+    }
+
+    // anisotropy is dropped when a filter is nearest instead of failing sampler creation
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        let all_linear = self.mag_filter == wgpu::FilterMode::Linear
+            && self.min_filter == wgpu::FilterMode::Linear
+            && self.mipmap_filter == wgpu::FilterMode::Linear;
+        wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: self.address_mode_w,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            lod_min_clamp: self.lod_min_clamp,
+            lod_max_clamp: self.lod_max_clamp,
+            compare: self.compare,
+            anisotropy_clamp: if all_linear {
+                self.anisotropy_clamp.max(1)
+            } else {
+                1
+            },
+            border_color: None,
+        }
+    }
+}
+
+// Splits an mtl texture statement like "-clamp on -s 2 2 tree.png" into the file name
+// and the sampler it asks for. Options that don't affect sampling are skipped.
+pub fn parse_mtl_texture(statement: &str) -> (String, SamplerDesc) {
+    let mut desc = SamplerDesc::ANISOTROPIC_REPEAT;
+    let mut words = statement.split_whitespace().peekable();
+    while let Some(option) = words.next_if(|w| w.starts_with('-')) {
+        match option {
+            "-clamp" => {
+                if words.next() == Some("on") {
+                    desc = desc.with_address_mode(wgpu::AddressMode::ClampToEdge);
+                }
+            }
+            // up to three numbers
+            "-o" | "-s" | "-t" => {
+                for _ in 0..3 {
+                    if words.next_if(|w| w.parse::<f32>().is_ok()).is_none() {
+                        break;
+                    }
+                }
+            }
+            "-mm" => {
+                words.next();
+                words.next();
+            }
+            _ => {
+                words.next();
+            }
+        }
+    }
+    (words.collect::<Vec<_>>().join(" "), desc)
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.address_mode_u == other.address_mode_u
+            && self.address_mode_v == other.address_mode_v
+            && self.address_mode_w == other.address_mode_w
+            && self.mag_filter == other.mag_filter
+            && self.min_filter == other.min_filter
+            && self.mipmap_filter == other.mipmap_filter
+            && self.anisotropy_clamp == other.anisotropy_clamp
+            && self.lod_min_clamp.to_bits() == other.lod_min_clamp.to_bits()
+            && self.lod_max_clamp.to_bits() == other.lod_max_clamp.to_bits()
+            && self.compare == other.compare
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address_mode_u.hash(state);
+        self.address_mode_v.hash(state);
+        self.address_mode_w.hash(state);
+        self.mag_filter.hash(state);
+        self.min_filter.hash(state);
+        self.mipmap_filter.hash(state);
+        self.anisotropy_clamp.hash(state);
+        self.lod_min_clamp.to_bits().hash(state);
+        self.lod_max_clamp.to_bits().hash(state);
+        self.compare.hash(state);
+    }
+}
+
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDesc, Arc<wgpu::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, desc: SamplerDesc) -> Arc<wgpu::Sampler> {
+        self.samplers
+            .entry(desc)
+            .or_insert_with(|| Arc::new(device.create_sampler(&desc.descriptor())))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_file_name_gets_the_default_sampler() {
+        assert_eq!(
+            parse_mtl_texture("tree.png"),
+            ("tree.png".to_string(), SamplerDesc::ANISOTROPIC_REPEAT)
+        );
+    }
+
+    #[test]
+    fn clamp_on_clamps_every_axis() {
+        let (file, desc) = parse_mtl_texture("-clamp on tree.png");
+        assert_eq!(file, "tree.png");
+        assert_eq!(desc, SamplerDesc::ANISOTROPIC_CLAMP);
+
+        let (file, desc) = parse_mtl_texture("-clamp off tree.png");
+        assert_eq!(file, "tree.png");
+        assert_eq!(desc, SamplerDesc::ANISOTROPIC_REPEAT);
+    }
+
+    #[test]
+    fn offset_scale_and_turbulence_take_up_to_three_numbers() {
+        for statement in [
+            "-o 0.5 tree.png",
+            "-s 2 2 tree.png",
+            "-t 0.1 0.2 0.3 tree.png",
+            "-o 1 -s 2 2 1 -t 0 tree.png",
+        ] {
+            assert_eq!(parse_mtl_texture(statement).0, "tree.png", "{statement}");
+        }
+    }
+
+    #[test]
+    fn mm_takes_two_numbers() {
+        let (file, desc) = parse_mtl_texture("-mm 0 1 -clamp on tree.png");
+        assert_eq!(file, "tree.png");
+        assert_eq!(desc, SamplerDesc::ANISOTROPIC_CLAMP);
+    }
+
+    #[test]
+    fn unknown_options_skip_one_value() {
+        assert_eq!(parse_mtl_texture("-bm 0.5 bump.png").0, "bump.png");
+    }
+
+    #[test]
+    fn file_names_keep_their_spaces() {
+        assert_eq!(
+            parse_mtl_texture("-s 2 2 textures/old tree bark.png").0,
+            "textures/old tree bark.png"
+        );
+    }
+}
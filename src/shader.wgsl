@@ -3,28 +3,59 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    // last frame's model matrix, for motion vectors
+    @location(9) prev_model_matrix_0: vec4<f32>,
+    @location(10) prev_model_matrix_1: vec4<f32>,
+    @location(11) prev_model_matrix_2: vec4<f32>,
+    @location(12) prev_model_matrix_3: vec4<f32>,
 };
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+};
+
+struct ColoredVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) color: vec4<f32>,
 };
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // invariant so the depth prepass lands on exactly the same depth
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_tangent: vec4<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) world_position: vec3<f32>,
+    // unjittered clip positions this frame and the last
+    @location(5) current_position: vec4<f32>,
+    @location(6) previous_position: vec4<f32>,
 };
 
 struct CameraUniform {
     view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
-
-@vertex
-fn vs_main(
-    model: VertexInput,
+fn transform_vertex(
+    position: vec3<f32>,
+    tex_coords: vec2<f32>,
+    normal: vec3<f32>,
+    tangent: vec4<f32>,
     instance: InstanceInput,
 ) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
@@ -33,9 +64,46 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    // instances only rotate and translate, so the model matrix also transforms normals
+    let normal_matrix = mat3x3<f32>(
+        model_matrix[0].xyz,
+        model_matrix[1].xyz,
+        model_matrix[2].xyz,
+    );
+    let prev_model_matrix = mat4x4<f32>(
+        instance.prev_model_matrix_0,
+        instance.prev_model_matrix_1,
+        instance.prev_model_matrix_2,
+        instance.prev_model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.tex_coords = tex_coords;
+    out.world_normal = normal_matrix * normal;
+    out.world_tangent = vec4<f32>(normal_matrix * tangent.xyz, tangent.w);
+    out.color = vec4<f32>(1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    out.current_position = camera.unjittered_view_proj * world_position;
+    out.previous_position = camera.prev_view_proj * prev_model_matrix * vec4<f32>(position, 1.0);
+    return out;
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    return transform_vertex(model.position, model.tex_coords, model.normal, model.tangent, instance);
+}
+
+@vertex
+fn vs_colored(
+    model: ColoredVertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var out = transform_vertex(model.position, model.tex_coords, model.normal, model.tangent, instance);
+    out.color = model.color;
     return out;
 }
 
@@ -43,8 +111,233 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+// single channel maps read from r, white when the material has none
+@group(0) @binding(5)
+var t_roughness: texture_2d<f32>;
+@group(0) @binding(6)
+var s_roughness: sampler;
+@group(0) @binding(7)
+var t_metallic: texture_2d<f32>;
+@group(0) @binding(8)
+var s_metallic: sampler;
+@group(0) @binding(9)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(10)
+var s_occlusion: sampler;
+
+struct MaterialUniform {
+    metallic: f32,
+    roughness: f32,
+    alpha_cutoff: f32,
+    opacity: f32,
+    emissive: vec3<f32>,
+};
+@group(0) @binding(4)
+var<uniform> material: MaterialUniform;
+
+// set for masked materials under msaa, the pipeline turns the alpha output into
+// coverage instead of the shader dropping pixels
+override ALPHA_TO_COVERAGE: bool = false;
+
+// the normal and the ambient light on its own feed screen space passes, which can
+// darken just the ambient part of color, velocity is how far the surface moved in uv
+// since the last frame
+struct SceneOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) view_normal: vec4<f32>,
+    @location(2) ambient: vec4<f32>,
+    @location(3) velocity: vec4<f32>,
+};
+
+// what the deferred path stores per pixel instead of lighting it, deferred.wgsl reads
+// it back
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) world_normal: vec4<f32>,
+    // metallic in r, roughness in g, occlusion in b
+    @location(2) material: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+    @location(4) velocity: vec4<f32>,
+};
+
+struct Surface {
+    albedo: vec3<f32>,
+    alpha: f32,
+    normal: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    // only darkens the ambient light
+    occlusion: f32,
+    emissive: vec3<f32>,
+};
+
+fn screen_velocity(current: vec4<f32>, previous: vec4<f32>) -> vec2<f32> {
+    // ndc y points up, uv down
+    return (current.xy / current.w - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
+
+fn sample_surface(in: VertexOutput) -> Surface {
+    let diffuse = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.color;
+
+    // re-orthogonalize after interpolation, bitangent follows the mikktspace convention
+    let n = normalize(in.world_normal);
+    let t = normalize(in.world_tangent.xyz - n * dot(in.world_tangent.xyz, n));
+    let b = cross(n, t) * in.world_tangent.w;
+    let tbn = mat3x3<f32>(t, b, n);
+    // normal maps are two channel, z is rebuilt from x and y
+    let normal_xy = textureSample(t_normal, s_normal, in.tex_coords).rg * 2.0 - 1.0;
+    let tangent_normal = vec3<f32>(normal_xy, sqrt(max(1.0 - dot(normal_xy, normal_xy), 0.0)));
+
+    var surface: Surface;
+    surface.albedo = diffuse.rgb;
+    surface.alpha = diffuse.a * material.opacity;
+    surface.normal = normalize(tbn * tangent_normal);
+    surface.metallic = material.metallic * textureSample(t_metallic, s_metallic, in.tex_coords).r;
+    let roughness = material.roughness * textureSample(t_roughness, s_roughness, in.tex_coords).r;
+    surface.roughness = clamp(roughness, 0.04, 1.0);
+    surface.occlusion = textureSample(t_occlusion, s_occlusion, in.tex_coords).r;
+    surface.emissive = material.emissive;
+    return surface;
+}
+
+// Drops pixels below the cutoff, or sharpens alpha around it so coverage goes from
+// none to full over about a pixel. The cutoff is 0 unless the material is masked.
+fn alpha_test(alpha: f32) -> f32 {
+    if ALPHA_TO_COVERAGE {
+        return saturate((alpha - material.alpha_cutoff) / max(fwidth(alpha), 0.0001) + 0.5);
+    }
+    if alpha < material.alpha_cutoff {
+        discard;
+    }
+    return alpha;
+}
+
+// everything lighting the surface, emissive counts as direct so screen space passes
+// leave it alone
+fn shade_fragment(in: VertexOutput, surface: Surface) -> SurfaceLight {
+    let v = normalize(camera.position.xyz - in.world_position);
+    var light = shade_surface(surface.albedo, surface.normal, surface.metallic, surface.roughness, v);
+    light.ambient *= surface.occlusion;
+    let view_z = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+    light.direct += shade_lights(
+        surface.albedo,
+        surface.normal,
+        surface.metallic,
+        surface.roughness,
+        v,
+        in.world_position,
+        in.clip_position.xy,
+        view_z,
+    ) + surface.emissive;
+    return light;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> SceneOutput {
+    var surface = sample_surface(in);
+    surface.alpha = alpha_test(surface.alpha);
+    let light = shade_fragment(in, surface);
+
+    var out: SceneOutput;
+    out.color = vec4<f32>(light.direct + light.ambient, surface.alpha);
+    out.view_normal = vec4<f32>((camera.view * vec4<f32>(surface.normal, 0.0)).xyz, 0.0);
+    out.ambient = vec4<f32>(light.ambient, 0.0);
+    out.velocity = vec4<f32>(screen_velocity(in.current_position, in.previous_position), 0.0, 0.0);
+    return out;
+}
+
+// Weighted blended order independent transparency. Every transparent fragment adds
+// its weighted premultiplied color to accumulation and multiplies revealage by
+// 1 - alpha, oit_composite.wgsl divides the two back out, so draw order doesn't matter.
+struct OitOutput {
+    @location(0) accumulation: vec4<f32>,
+    @location(1) revealage: vec4<f32>,
+};
+
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let surface = sample_surface(in);
+    let light = shade_fragment(in, surface);
+    let alpha = surface.alpha;
+
+    // McGuire and Bavoil's depth weight, nearer and more opaque fragments count more.
+    // The clamp keeps the sums inside half floats.
+    let weight = clamp(
+        pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - in.clip_position.z * 0.9, 3.0),
+        0.01,
+        3000.0,
+    );
+    var out: OitOutput;
+    out.accumulation = vec4<f32>((light.direct + light.ambient) * alpha, alpha) * weight;
+    out.revealage = vec4<f32>(alpha);
+    return out;
+}
+
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    var surface = sample_surface(in);
+    surface.alpha = alpha_test(surface.alpha);
+
+    var out: GBufferOutput;
+    out.albedo = vec4<f32>(surface.albedo, surface.alpha);
+    out.world_normal = vec4<f32>(surface.normal, 0.0);
+    out.material = vec4<f32>(surface.metallic, surface.roughness, surface.occlusion, 0.0);
+    out.emissive = vec4<f32>(surface.emissive, 0.0);
+    out.velocity = vec4<f32>(screen_velocity(in.current_position, in.previous_position), 0.0, 0.0);
+    return out;
+}
+
+// DebugView in debug_view.rs, each debug pipeline is built with its own
+const DEBUG_VIEW_WIREFRAME: u32 = 1u;
+const DEBUG_VIEW_NORMALS: u32 = 2u;
+const DEBUG_VIEW_UVS: u32 = 3u;
+const DEBUG_VIEW_MIP_LEVEL: u32 = 4u;
+const DEBUG_VIEW_DEPTH: u32 = 5u;
+override DEBUG_VIEW: u32 = DEBUG_VIEW_WIREFRAME;
+
+const WIREFRAME_COLOR: vec3<f32> = vec3<f32>(0.9);
+
+// The level the diffuse texture is sampled from, from level 0 in blue through green,
+// yellow and red to magenta for level 4 and above. In between levels are blended.
+fn mip_level_color(tex_coords: vec2<f32>) -> vec3<f32> {
+    var colors = array<vec3<f32>, 5>(
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(1.0, 1.0, 0.0),
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(1.0, 0.0, 1.0),
+    );
+    let texels = tex_coords * vec2<f32>(textureDimensions(t_diffuse));
+    let dx = dpdx(texels);
+    let dy = dpdy(texels);
+    let max_level = f32(min(textureNumLevels(t_diffuse), 5u) - 1u);
+    let level = clamp(0.5 * log2(max(dot(dx, dx), dot(dy, dy))), 0.0, max_level);
+    let lower = u32(floor(level));
+    let upper = min(lower + 1u, u32(max_level));
+    return mix(colors[lower], colors[upper], fract(level));
+}
 
+// Model inspection views that replace the lit scene, nothing is shaded.
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+fn fs_debug_view(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = WIREFRAME_COLOR;
+    if DEBUG_VIEW == DEBUG_VIEW_NORMALS {
+        // the interpolated vertex normal, without the normal map
+        color = normalize(in.world_normal) * 0.5 + 0.5;
+    } else if DEBUG_VIEW == DEBUG_VIEW_UVS {
+        // repeating uvs wrap back to black
+        color = vec3<f32>(fract(in.tex_coords), 0.0);
+    } else if DEBUG_VIEW == DEBUG_VIEW_MIP_LEVEL {
+        color = mip_level_color(in.tex_coords);
+    } else if DEBUG_VIEW == DEBUG_VIEW_DEPTH {
+        // linear from the eye to the far plane, like the deferred depth view
+        let far = camera.inv_proj * vec4<f32>(0.0, 0.0, 1.0, 1.0);
+        let view_z = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+        color = vec3<f32>(view_z / (-far.z / far.w));
+    }
+    return vec4<f32>(color, 1.0);
 }
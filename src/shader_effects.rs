@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use crate::post_process::{EffectInputs, EffectParam, ShaderEffect};
+
+// Small effects that are a single shader each, built on post_process::ShaderEffect.
+
+pub const VIGNETTE: &str = "vignette";
+pub const SHARPEN: &str = "sharpen";
+
+// vignette.wgsl, with the params in the order of its VignetteParams
+pub fn vignette(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    sampler: Arc<wgpu::Sampler>,
+) -> ShaderEffect {
+    ShaderEffect::fullscreen(
+        device,
+        VIGNETTE,
+        include_str!("vignette.wgsl"),
+        format,
+        EffectInputs::default(),
+        vec![
+            EffectParam::new("strength", 0.4),
+            EffectParam::new("radius", 0.6),
+        ],
+        sampler,
+    )
+}
+
+// sharpen.wgsl, which writes rgba16float so format has to be tonemap::HDR_FORMAT
+pub fn sharpen(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    sampler: Arc<wgpu::Sampler>,
+) -> ShaderEffect {
+    ShaderEffect::compute(
+        device,
+        SHARPEN,
+        include_str!("sharpen.wgsl"),
+        format,
+        EffectInputs::default(),
+        vec![EffectParam::new("strength", 0.5)],
+        sampler,
+    )
+}
@@ -0,0 +1,35 @@
+// Unsharp mask against the four direct neighbours, run as a compute effect through
+// post_process::ShaderEffect. The output format is the chain's, tonemap::HDR_FORMAT.
+
+struct SharpenParams {
+    // how far a pixel is pushed away from the average of its neighbours
+    strength: f32,
+};
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> params: SharpenParams;
+@group(0) @binding(5)
+var t_output: texture_storage_2d<rgba16float, write>;
+
+// clamped to the edges of the image
+fn load(pixel: vec2<i32>) -> vec4<f32> {
+    let max_pixel = vec2<i32>(textureDimensions(t_color)) - 1;
+    return textureLoad(t_color, clamp(pixel, vec2<i32>(0), max_pixel), 0);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_color);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+    let center = load(pixel);
+    let neighbours = load(pixel + vec2<i32>(1, 0)).rgb + load(pixel - vec2<i32>(1, 0)).rgb
+        + load(pixel + vec2<i32>(0, 1)).rgb + load(pixel - vec2<i32>(0, 1)).rgb;
+    let sharpened = center.rgb + (center.rgb - neighbours * 0.25) * params.strength;
+    // hdr colors can't go below zero
+    textureStore(t_output, pixel, vec4<f32>(max(sharpened, vec3<f32>(0.0)), center.a));
+}
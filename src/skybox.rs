@@ -0,0 +1,401 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+
+use crate::texture::{self, FloatPrecision, MipmapGenerator, Texture, TextureRole};
+
+// Cube texture sampled by direction, layers in +x -x +y -y +z -z order.
+pub struct Cubemap {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: Arc<wgpu::Sampler>,
+}
+
+impl Cubemap {
+    pub const FACE_COUNT: u32 = 6;
+
+    // Six square faces of the same size, in layer order. The mips are generated into
+    // encoder.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut MipmapGenerator,
+        faces: &[image::DynamicImage; 6],
+        role: TextureRole,
+        label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        let face_size = faces[0].width();
+        if faces
+            .iter()
+            .any(|f| f.width() != face_size || f.height() != face_size)
+        {
+            bail!("cubemap faces must be square and all the same size");
+        }
+
+        let format = role.format();
+        let texture = Self::create_texture(device, face_size, format, label);
+        let bytes_per_pixel = format
+            .block_copy_size(None)
+            .expect("texture roles only use color formats");
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                &texture::pack_pixels(face, format),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_pixel * face_size),
+                    rows_per_image: Some(face_size),
+                },
+                wgpu::Extent3d {
+                    width: face_size,
+                    height: face_size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        mipmaps.generate(device, encoder, &texture);
+
+        Ok(Self::from_texture(texture, sampler))
+    }
+
+    // Resamples an equirectangular panorama, usually loaded with Texture::from_hdr,
+    // into a half float cubemap. The passes are recorded into encoder.
+    pub fn from_equirectangular(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut MipmapGenerator,
+        equirect: &Texture,
+        face_size: u32,
+        label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let format = wgpu::TextureFormat::Rgba16Float;
+        let texture = Self::create_texture(device, face_size, format, label);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Equirect to cube shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("equirect_to_cube.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Equirect to cube pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(format.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        // wraps horizontally so the seam at the back of the panorama filters across
+        let equirect_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Equirect sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&equirect.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&equirect_sampler),
+                },
+            ],
+            label: Some("equirect_bind_group"),
+        });
+
+        for face in 0..Self::FACE_COUNT {
+            let face_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Cube face view"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: 0,
+                mip_level_count: Some(1),
+                base_array_layer: face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            });
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Equirect to cube pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &face_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, face..face + 1);
+        }
+        mipmaps.generate(device, encoder, &texture);
+
+        Self::from_texture(texture, sampler)
+    }
+
+    // An equirectangular .hdr or .exr panorama, decoded as precisely as the device can
+    // filter and resampled into a cubemap. Everything is recorded into encoder.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_hdr(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut MipmapGenerator,
+        bytes: &[u8],
+        face_size: u32,
+        label: &str,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        let equirect = Texture::from_hdr(
+            device,
+            queue,
+            encoder,
+            mipmaps,
+            bytes,
+            label,
+            FloatPrecision::filterable(device),
+            sampler.clone(),
+        )?;
+        Ok(Self::from_equirectangular(
+            device,
+            encoder,
+            mipmaps,
+            &equirect,
+            face_size,
+            Some(label),
+            sampler,
+        ))
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        face_size: u32,
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> wgpu::Texture {
+        let size = wgpu::Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: Self::FACE_COUNT,
+        };
+        device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: size.max_mips(wgpu::TextureDimension::D2),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        })
+    }
+
+    // wraps a texture with six layers, anything else fails when the cube view is made
+    pub fn from_texture(texture: wgpu::Texture, sampler: Arc<wgpu::Sampler>) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+// the bind group keeps the cubemap's view and sampler alive
+pub struct Skybox {
+    pub bind_group: wgpu::BindGroup,
+}
+
+// Draws the skybox behind everything else. It runs last in the main pass, on the far
+// plane with a LessEqual test, so only pixels no geometry covered get shaded.
+pub struct SkyboxPass {
+    pub pipeline: wgpu::RenderPipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl SkyboxPass {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_formats: &[wgpu::TextureFormat],
+        multisample: wgpu::MultisampleState,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        });
+
+        let pipeline = Self::create_pipeline(
+            device,
+            &bind_group_layout,
+            camera_bind_group_layout,
+            color_formats,
+            multisample,
+        );
+
+        Self {
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    // msaa changes need a new pipeline, skyboxes made earlier stay usable
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_formats: &[wgpu::TextureFormat],
+        multisample: wgpu::MultisampleState,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.bind_group_layout,
+            camera_bind_group_layout,
+            color_formats,
+            multisample,
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_formats: &[wgpu::TextureFormat],
+        multisample: wgpu::MultisampleState,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
+        });
+        let targets = color_formats
+            .iter()
+            .map(|&format| {
+                Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox pipeline layout"),
+            bind_group_layouts: &[bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &targets,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // the depth buffer is cleared to 1, LessEqual lets the far plane through
+            // wherever nothing was drawn
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample,
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn create_skybox(&self, device: &wgpu::Device, cubemap: &Cubemap) -> Skybox {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cubemap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        });
+        Skybox { bind_group }
+    }
+
+    pub fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        skybox: &Skybox,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &skybox.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,60 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(0)
+var t_skybox: texture_cube<f32>;
+@group(0) @binding(1)
+var s_skybox: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+// fullscreen triangle placed on the far plane, so it only shows where nothing was drawn
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// matches the main pass targets, a zero normal marks the sky for screen space passes
+struct SkyboxOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) view_normal: vec4<f32>,
+    @location(2) ambient: vec4<f32>,
+    @location(3) velocity: vec4<f32>,
+};
+
+@fragment
+fn fs_main(in: VertexOutput) -> SkyboxOutput {
+    // the view ray runs between the pixel's points on the near and far planes
+    let near = camera.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let far = camera.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let far_position = vec4<f32>(far.xyz / far.w, 1.0);
+    let direction = far_position.xyz - near.xyz / near.w;
+    var out: SkyboxOutput;
+    out.color = vec4<f32>(textureSample(t_skybox, s_skybox, direction).rgb, 1.0);
+    out.view_normal = vec4<f32>(0.0);
+    out.ambient = vec4<f32>(0.0);
+    // the sky only moves with the camera's rotation, its far plane point is close enough
+    let current = camera.unjittered_view_proj * far_position;
+    let previous = camera.prev_view_proj * far_position;
+    let velocity = (current.xy / current.w - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+    out.velocity = vec4<f32>(velocity, 0.0, 0.0);
+    return out;
+}
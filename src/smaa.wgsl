@@ -0,0 +1,195 @@
+// SMAA 1x at the medium preset, without diagonal and corner detection. Appended to
+// fullscreen.wgsl for vs_main. Three passes: luma edges, blend weights from the area
+// and search lookup textures, then blending each pixel with its neighbours.
+
+const THRESHOLD: f32 = 0.1;
+// an edge this much weaker than its neighbours is likely part of the stronger one
+const LOCAL_CONTRAST_ADAPTATION: f32 = 2.0;
+const MAX_SEARCH_STEPS: f32 = 8.0;
+// match build.rs
+const AREA_MAX_DISTANCE: f32 = 16.0;
+const AREA_SIZE: f32 = 80.0;
+const SEARCH_HALF_WIDTH: f32 = 33.0;
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_linear: sampler;
+@group(0) @binding(2)
+var t_edges: texture_2d<f32>;
+@group(0) @binding(3)
+var t_area: texture_2d<f32>;
+@group(0) @binding(4)
+var t_search: texture_2d<f32>;
+@group(0) @binding(5)
+var t_weights: texture_2d<f32>;
+
+// the target decodes srgb on load, the square root brings luma close to perceptual again
+fn load_luma(pixel: vec2<i32>) -> f32 {
+    let max_pixel = vec2<i32>(textureDimensions(t_color)) - 1;
+    let color = textureLoad(t_color, clamp(pixel, vec2<i32>(0), max_pixel), 0).rgb;
+    return sqrt(dot(color, vec3<f32>(0.2126, 0.7152, 0.0722)));
+}
+
+// r is an edge on the left of the pixel, g one on top
+@fragment
+fn fs_edges(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let l = load_luma(pixel);
+    let l_left = load_luma(pixel + vec2<i32>(-1, 0));
+    let l_top = load_luma(pixel + vec2<i32>(0, -1));
+    let delta = abs(l - vec2<f32>(l_left, l_top));
+    var edges = step(vec2<f32>(THRESHOLD), delta);
+    if dot(edges, vec2<f32>(1.0)) == 0.0 {
+        discard;
+    }
+
+    let l_right = load_luma(pixel + vec2<i32>(1, 0));
+    let l_bottom = load_luma(pixel + vec2<i32>(0, 1));
+    var max_delta = max(delta, abs(l - vec2<f32>(l_right, l_bottom)));
+    let l_left_left = load_luma(pixel + vec2<i32>(-2, 0));
+    let l_top_top = load_luma(pixel + vec2<i32>(0, -2));
+    max_delta = max(max_delta, abs(vec2<f32>(l_left, l_top) - vec2<f32>(l_left_left, l_top_top)));
+    let final_delta = max(max_delta.x, max_delta.y);
+    edges *= step(vec2<f32>(final_delta), LOCAL_CONTRAST_ADAPTATION * delta);
+    return vec4<f32>(edges, 0.0, 0.0);
+}
+
+fn sample_edges(uv: vec2<f32>) -> vec2<f32> {
+    return textureSampleLevel(t_edges, s_linear, uv, 0.0).rg;
+}
+
+// Pixels the search went past the end of the edge, from the bilinear fetch of the edges
+// it stopped on. Offset 0 is for searches left or up, 0.5 right or down.
+fn search_length(e: vec2<f32>, offset: f32) -> f32 {
+    let texel = vec2<i32>(round(e * 32.0) + vec2<f32>(offset * 2.0 * SEARCH_HALF_WIDTH, 0.0));
+    return textureLoad(t_search, texel, 0).r;
+}
+
+// The searches step two pixels at a time, the fetch between pixels reads the edges of
+// both. They return the texture coordinate of the end of the edge.
+fn search_x_left(start: vec2<f32>, end: f32, texel: vec2<f32>) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(0.0, 1.0);
+    while uv.x > end && e.g > 0.8281 && e.r == 0.0 {
+        e = sample_edges(uv);
+        uv.x -= 2.0 * texel.x;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e, 0.0) + 3.25;
+    return texel.x * offset + uv.x;
+}
+
+fn search_x_right(start: vec2<f32>, end: f32, texel: vec2<f32>) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(0.0, 1.0);
+    while uv.x < end && e.g > 0.8281 && e.r == 0.0 {
+        e = sample_edges(uv);
+        uv.x += 2.0 * texel.x;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e, 0.5) + 3.25;
+    return -texel.x * offset + uv.x;
+}
+
+fn search_y_up(start: vec2<f32>, end: f32, texel: vec2<f32>) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(1.0, 0.0);
+    while uv.y > end && e.r > 0.8281 && e.g == 0.0 {
+        e = sample_edges(uv);
+        uv.y -= 2.0 * texel.y;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e.gr, 0.0) + 3.25;
+    return texel.y * offset + uv.y;
+}
+
+fn search_y_down(start: vec2<f32>, end: f32, texel: vec2<f32>) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(1.0, 0.0);
+    while uv.y < end && e.r > 0.8281 && e.g == 0.0 {
+        e = sample_edges(uv);
+        uv.y += 2.0 * texel.y;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e.gr, 0.5) + 3.25;
+    return -texel.y * offset + uv.y;
+}
+
+// coverage for a pixel at sqrt_distance from both ends of an edge with crossing edges
+// e1 and e2 at them
+fn area(sqrt_distance: vec2<f32>, e1: f32, e2: f32) -> vec2<f32> {
+    let distance = min(sqrt_distance, vec2<f32>(AREA_MAX_DISTANCE - 1.0));
+    let texel = AREA_MAX_DISTANCE * round(4.0 * vec2<f32>(e1, e2)) + distance;
+    return textureSampleLevel(t_area, s_linear, (texel + 0.5) / AREA_SIZE, 0.0).rg;
+}
+
+// rg blend the pixel with the one above, ba with the one on the left
+@fragment
+fn fs_weights(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_edges));
+    let texel = 1.0 / size;
+    let uv = in.tex_coords;
+    let pixel = in.clip_position.xy;
+    let offset0 = uv.xyxy + texel.xyxy * vec4<f32>(-0.25, -0.125, 1.25, -0.125);
+    let offset1 = uv.xyxy + texel.xyxy * vec4<f32>(-0.125, -0.25, -0.125, 1.25);
+    let search_end = vec4<f32>(offset0.xz, offset1.yw)
+        + vec4<f32>(-2.0, 2.0, -2.0, 2.0) * texel.xxyy * MAX_SEARCH_STEPS;
+
+    var weights = vec4<f32>(0.0);
+    let e = sample_edges(uv);
+    if e.g > 0.0 {
+        let left = search_x_left(offset0.xy, search_end.x, texel);
+        let right = search_x_right(offset0.zw, search_end.y, texel);
+        let distance = abs(round(size.x * vec2<f32>(left, right) - pixel.x));
+        let e1 = sample_edges(vec2<f32>(left, offset1.y)).r;
+        let e2 = textureSampleLevel(
+            t_edges,
+            s_linear,
+            vec2<f32>(right, offset1.y),
+            0.0,
+            vec2<i32>(1, 0),
+        ).r;
+        weights = vec4<f32>(area(sqrt(distance), e1, e2), weights.zw);
+    }
+    if e.r > 0.0 {
+        let up = search_y_up(offset1.xy, search_end.z, texel);
+        let down = search_y_down(offset1.zw, search_end.w, texel);
+        let distance = abs(round(size.y * vec2<f32>(up, down) - pixel.y));
+        let e1 = sample_edges(vec2<f32>(offset0.x, up)).g;
+        let e2 = textureSampleLevel(
+            t_edges,
+            s_linear,
+            vec2<f32>(offset0.x, down),
+            0.0,
+            vec2<i32>(0, 1),
+        ).g;
+        weights = vec4<f32>(weights.xy, area(sqrt(distance), e1, e2));
+    }
+    return weights;
+}
+
+@fragment
+fn fs_blend(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_color));
+    let uv = in.tex_coords;
+    let current = textureSampleLevel(t_weights, s_linear, uv, 0.0);
+    // right, bottom, then this pixel's left and top
+    let a = vec4<f32>(
+        textureSampleLevel(t_weights, s_linear, uv + vec2<f32>(texel.x, 0.0), 0.0).a,
+        textureSampleLevel(t_weights, s_linear, uv + vec2<f32>(0.0, texel.y), 0.0).g,
+        current.z,
+        current.x,
+    );
+    if dot(a, vec4<f32>(1.0)) < 1e-5 {
+        return textureSampleLevel(t_color, s_linear, uv, 0.0);
+    }
+
+    // blend along whichever direction has the stronger weights
+    var offset = vec4<f32>(0.0, a.y, 0.0, a.w);
+    var weight = a.yw;
+    if max(a.x, a.z) > max(a.y, a.w) {
+        offset = vec4<f32>(a.x, 0.0, a.z, 0.0);
+        weight = a.xz;
+    }
+    weight /= dot(weight, vec2<f32>(1.0));
+    let coords = uv.xyxy + offset * vec4<f32>(texel, -texel);
+    return weight.x * textureSampleLevel(t_color, s_linear, coords.xy, 0.0)
+        + weight.y * textureSampleLevel(t_color, s_linear, coords.zw, 0.0);
+}
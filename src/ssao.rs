@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use crate::post_process::{
+    self, draw_fullscreen, EffectContext, EffectInputs, EffectParam, PostEffect,
+};
+use crate::texture::Texture;
+
+const AO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[derive(Copy, Clone, Debug)]
+pub struct SsaoSettings {
+    pub radius: f32,
+    // 0 turns occlusion off, above 1 exaggerates it
+    pub intensity: f32,
+    // clamped to 1..64 in the shader
+    pub sample_count: u32,
+    pub bias: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            intensity: 1.0,
+            sample_count: 16,
+            bias: 0.025,
+        }
+    }
+}
+
+impl SsaoSettings {
+    // in the order of SsaoParams in ssao.wgsl
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("radius", self.radius),
+            EffectParam::new("intensity", self.intensity),
+            EffectParam::new("sample_count", self.sample_count as f32),
+            EffectParam::new("bias", self.bias),
+        ]
+    }
+}
+
+// Screen space ambient occlusion. Occlusion is estimated from depth and view space
+// normals, blurred without bleeding across edges, and taken out of the ambient light.
+pub struct Ssao {
+    params: Vec<EffectParam>,
+    params_buffer: wgpu::Buffer,
+    ao_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    // holds the blurred result after a run, blur_texture is the in between step
+    ao_texture: Texture,
+    blur_texture: Texture,
+}
+
+impl Ssao {
+    pub const NAME: &'static str = "ssao";
+
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        settings: SsaoSettings,
+    ) -> Self {
+        let shader =
+            post_process::fullscreen_shader(device, "Ssao shader", include_str!("ssao.wgsl"));
+        let ao_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &shader,
+            "fs_ao",
+            AO_FORMAT.into(),
+            "Ssao pipeline",
+        );
+        let blur_horizontal_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &shader,
+            "fs_blur_horizontal",
+            AO_FORMAT.into(),
+            "Ssao horizontal blur pipeline",
+        );
+        let blur_vertical_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &shader,
+            "fs_blur_vertical",
+            AO_FORMAT.into(),
+            "Ssao vertical blur pipeline",
+        );
+        let composite_pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &shader,
+            "fs_composite",
+            format.into(),
+            "Ssao composite pipeline",
+        );
+
+        let params = settings.params();
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Ssao params buffer"),
+            size: (params.len() * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            params,
+            params_buffer,
+            ao_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            composite_pipeline,
+            ao_texture: create_ao_texture(
+                device,
+                surface_config.width,
+                surface_config.height,
+                "ssao_texture",
+            ),
+            blur_texture: create_ao_texture(
+                device,
+                surface_config.width,
+                surface_config.height,
+                "ssao_blur_texture",
+            ),
+        }
+    }
+}
+
+fn create_ao_texture(device: &wgpu::Device, width: u32, height: u32, label: &str) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: AO_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // the passes read it with textureLoad, the sampler is for anything that displays it
+    let sampler = Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    }));
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn texture_entry(binding: u32, texture: &Texture) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(&texture.view),
+    }
+}
+
+impl PostEffect for Ssao {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn inputs(&self) -> EffectInputs {
+        EffectInputs {
+            depth: true,
+            normals: true,
+            ambient: true,
+            velocity: false,
+        }
+    }
+
+    fn params(&self) -> &[EffectParam] {
+        &self.params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match self.params.iter_mut().find(|p| p.name == name) {
+            Some(param) => {
+                param.value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.ao_texture = create_ao_texture(device, width, height, "ssao_texture");
+        self.blur_texture = create_ao_texture(device, width, height, "ssao_blur_texture");
+    }
+
+    fn run(&mut self, context: &mut EffectContext, input: &Texture, output: &Texture) {
+        let scene = context.scene;
+        let (Some(normals), Some(ambient)) = (scene.normals, scene.ambient) else {
+            return;
+        };
+        context.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&self.params.iter().map(|p| p.value).collect::<Vec<_>>()),
+        );
+
+        let bind_group = |pipeline: &wgpu::RenderPipeline, entries: &[wgpu::BindGroupEntry]| {
+            context
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries,
+                    label: Some("ssao_bind_group"),
+                })
+        };
+        let camera_entry = wgpu::BindGroupEntry {
+            binding: 3,
+            resource: scene.camera_buffer.as_entire_binding(),
+        };
+
+        let ao_bind_group = bind_group(
+            &self.ao_pipeline,
+            &[
+                texture_entry(0, scene.depth),
+                texture_entry(1, normals),
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                camera_entry.clone(),
+            ],
+        );
+        let blur_horizontal_bind_group = bind_group(
+            &self.blur_horizontal_pipeline,
+            &[
+                texture_entry(0, scene.depth),
+                camera_entry.clone(),
+                texture_entry(4, &self.ao_texture),
+            ],
+        );
+        let blur_vertical_bind_group = bind_group(
+            &self.blur_vertical_pipeline,
+            &[
+                texture_entry(0, scene.depth),
+                camera_entry,
+                texture_entry(4, &self.blur_texture),
+            ],
+        );
+        let composite_bind_group = bind_group(
+            &self.composite_pipeline,
+            &[
+                texture_entry(4, &self.ao_texture),
+                texture_entry(5, input),
+                texture_entry(6, ambient),
+            ],
+        );
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::WHITE);
+        draw_fullscreen(
+            context.encoder,
+            &self.ao_pipeline,
+            &ao_bind_group,
+            &self.ao_texture.view,
+            clear,
+            "Ssao pass",
+        );
+        draw_fullscreen(
+            context.encoder,
+            &self.blur_horizontal_pipeline,
+            &blur_horizontal_bind_group,
+            &self.blur_texture.view,
+            clear,
+            "Ssao horizontal blur pass",
+        );
+        draw_fullscreen(
+            context.encoder,
+            &self.blur_vertical_pipeline,
+            &blur_vertical_bind_group,
+            &self.ao_texture.view,
+            clear,
+            "Ssao vertical blur pass",
+        );
+        draw_fullscreen(
+            context.encoder,
+            &self.composite_pipeline,
+            &composite_bind_group,
+            &output.view,
+            clear,
+            "Ssao composite pass",
+        );
+    }
+}
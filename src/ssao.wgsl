@@ -0,0 +1,150 @@
+// Normal oriented hemisphere ssao with a depth aware blur. Appended to fullscreen.wgsl
+// for vs_main. Every texture is screen sized and read with textureLoad.
+
+const PI: f32 = 3.14159265359;
+const MAX_SAMPLES: u32 = 64u;
+const BLUR_RADIUS: i32 = 4;
+// how quickly the blur stops at depth edges, relative to the pixel's distance
+const BLUR_DEPTH_FALLOFF: f32 = 20.0;
+
+struct SsaoParams {
+    // world units around each pixel that can occlude it
+    radius: f32,
+    intensity: f32,
+    sample_count: f32,
+    // keeps flat surfaces from occluding themselves
+    bias: f32,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+@group(0) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> ssao: SsaoParams;
+@group(0) @binding(3)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(4)
+var t_ao: texture_2d<f32>;
+@group(0) @binding(5)
+var t_color: texture_2d<f32>;
+@group(0) @binding(6)
+var t_ambient: texture_2d<f32>;
+
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let position = camera.inv_proj * vec4<f32>(ndc, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+fn pixel_view_z(pixel: vec2<i32>) -> f32 {
+    let uv = (vec2<f32>(pixel) + 0.5) / vec2<f32>(textureDimensions(t_depth));
+    return view_position(uv, textureLoad(t_depth, pixel, 0)).z;
+}
+
+// per pixel rotation so neighbouring pixels sample different directions
+fn interleaved_gradient_noise(position: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(position, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+@fragment
+fn fs_ao(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    let normal = textureLoad(t_normal, pixel, 0).xyz;
+    // the sky has no normal and nothing to occlude
+    if depth >= 1.0 || dot(normal, normal) < 0.01 {
+        return vec4<f32>(1.0);
+    }
+
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let position = view_position(in.tex_coords, depth);
+    let n = normalize(normal);
+    var up = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(n.z) < 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    let rotation = interleaved_gradient_noise(in.clip_position.xy);
+
+    let count = clamp(u32(ssao.sample_count), 1u, MAX_SAMPLES);
+    var occlusion = 0.0;
+    for (var i = 0u; i < count; i++) {
+        // cosine weighted direction, samples get further out as i grows
+        let xi = hammersley(i, count);
+        let phi = 2.0 * PI * fract(xi.x + rotation);
+        let r = sqrt(xi.y);
+        let local = vec3<f32>(r * cos(phi), r * sin(phi), sqrt(1.0 - xi.y));
+        let scale = mix(0.1, 1.0, pow((f32(i) + 1.0) / f32(count), 2.0));
+        let direction = tangent * local.x + bitangent * local.y + n * local.z;
+        let sample_position = position + direction * ssao.radius * scale;
+
+        let clip = camera.proj * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0)) {
+            continue;
+        }
+        let scene_z = pixel_view_z(vec2<i32>(uv * size));
+        // the camera looks down -z, so closer geometry has a larger z
+        let range = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - scene_z));
+        occlusion += select(0.0, 1.0, scene_z >= sample_position.z + ssao.bias) * range;
+    }
+
+    let ao = clamp(1.0 - occlusion / f32(count) * ssao.intensity, 0.0, 1.0);
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
+
+// gaussian blur along one axis that skips samples across depth discontinuities
+fn bilateral_blur(pixel: vec2<i32>, direction: vec2<i32>) -> f32 {
+    let max_pixel = vec2<i32>(textureDimensions(t_ao)) - 1;
+    let center_z = pixel_view_z(pixel);
+    var total = 0.0;
+    var total_weight = 0.0;
+    for (var offset = -BLUR_RADIUS; offset <= BLUR_RADIUS; offset++) {
+        let sample_pixel = clamp(pixel + direction * offset, vec2<i32>(0), max_pixel);
+        let z = pixel_view_z(sample_pixel);
+        let spatial = exp(-f32(offset * offset) / 8.0);
+        let range = exp(-abs(z - center_z) * BLUR_DEPTH_FALLOFF / max(abs(center_z), 0.001));
+        let weight = spatial * range;
+        total += textureLoad(t_ao, sample_pixel, 0).r * weight;
+        total_weight += weight;
+    }
+    return total / total_weight;
+}
+
+@fragment
+fn fs_blur_horizontal(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let ao = bilateral_blur(vec2<i32>(in.clip_position.xy), vec2<i32>(1, 0));
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
+
+@fragment
+fn fs_blur_vertical(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let ao = bilateral_blur(vec2<i32>(in.clip_position.xy), vec2<i32>(0, 1));
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
+
+// takes the occluded share of the ambient light back out of the lit color
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let color = textureLoad(t_color, pixel, 0);
+    let ambient = textureLoad(t_ambient, pixel, 0).rgb;
+    let ao = textureLoad(t_ao, pixel, 0).r;
+    return vec4<f32>(color.rgb - ambient * (1.0 - ao), color.a);
+}
@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::camera::Camera;
+use crate::post_process::{
+    self, draw_fullscreen, EffectContext, EffectInputs, EffectParam, PostEffect,
+};
+use crate::texture::Texture;
+
+// length of the jitter sequence before it repeats
+const JITTER_PHASES: u32 = 8;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TaaSettings {
+    // share of the new frame in the result, lower is smoother but ghosts longer
+    pub current_weight: f32,
+    // a camera moving further than this or turning by more radians than cut_angle in
+    // one frame counts as a cut, and the history is dropped
+    pub cut_distance: f32,
+    pub cut_angle: f32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self {
+            current_weight: 0.1,
+            cut_distance: 2.0,
+            cut_angle: 0.5,
+        }
+    }
+}
+
+// Temporal anti-aliasing. The renderer jitters the camera with begin_frame every frame,
+// the effect blends each jittered frame into a history reprojected with the velocity
+// buffer.
+pub struct Taa {
+    settings: TaaSettings,
+    params: Vec<EffectParam>,
+    params_buffer: wgpu::Buffer,
+    sampler: Arc<wgpu::Sampler>,
+    pipeline: wgpu::RenderPipeline,
+    // the last result, in the chain's format
+    history: Texture,
+    history_valid: bool,
+    frame: u32,
+    // position and forward of the camera last frame, for spotting cuts
+    previous_camera: Option<(Point3<f32>, Vector3<f32>)>,
+}
+
+impl Taa {
+    pub const NAME: &'static str = "taa";
+
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        settings: TaaSettings,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let shader =
+            post_process::fullscreen_shader(device, "Taa shader", include_str!("taa.wgsl"));
+        let pipeline = post_process::fullscreen_pipeline(
+            device,
+            None,
+            &shader,
+            "fs_main",
+            format.into(),
+            "Taa pipeline",
+        );
+        // uniform buffers are sized in multiples of 16 bytes
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Taa params buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            settings,
+            params: vec![EffectParam::new("current_weight", settings.current_weight)],
+            params_buffer,
+            sampler,
+            pipeline,
+            history: create_history_texture(
+                device,
+                surface_config.width,
+                surface_config.height,
+                format,
+            ),
+            history_valid: false,
+            frame: 0,
+            previous_camera: None,
+        }
+    }
+
+    // starts over from the next frame, for when the view changes in a way the velocity
+    // buffer can't follow
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    // Checks the camera for a cut and returns the jitter to give it this frame, in ndc.
+    pub fn begin_frame(&mut self, camera: &Camera, width: u32, height: u32) -> Vector2<f32> {
+        let forward = camera.get_camera_forward();
+        if let Some((position, previous_forward)) = self.previous_camera {
+            if (camera.position - position).norm() > self.settings.cut_distance
+                || forward.angle(&previous_forward) > self.settings.cut_angle
+            {
+                self.reset_history();
+            }
+        }
+        self.previous_camera = Some((camera.position, forward));
+
+        // halton(2, 3) spreads the samples evenly over the pixel, index 0 would be (0, 0)
+        self.frame = (self.frame + 1) % JITTER_PHASES;
+        let index = self.frame + 1;
+        let offset = Vector2::new(halton(index, 2), halton(index, 3)) - Vector2::repeat(0.5);
+        // a pixel is 2 / size wide in ndc
+        Vector2::new(
+            offset.x * 2.0 / width.max(1) as f32,
+            offset.y * 2.0 / height.max(1) as f32,
+        )
+    }
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// copied into after every run, and sampled with filtering for the reprojection
+fn create_history_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("taa_history_texture"),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    }));
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn texture_entry(binding: u32, texture: &Texture) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(&texture.view),
+    }
+}
+
+impl PostEffect for Taa {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn inputs(&self) -> EffectInputs {
+        EffectInputs {
+            depth: true,
+            velocity: true,
+            ..Default::default()
+        }
+    }
+
+    fn params(&self) -> &[EffectParam] {
+        &self.params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match self.params.iter_mut().find(|p| p.name == name) {
+            Some(param) => {
+                param.value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.history = create_history_texture(device, width, height, self.history.texture.format());
+        self.reset_history();
+    }
+
+    fn run(&mut self, context: &mut EffectContext, input: &Texture, output: &Texture) {
+        let size = input.texture.size();
+        if !self.history_valid {
+            // nothing to blend with yet, the frame is passed through and starts the history
+            context.encoder.copy_texture_to_texture(
+                input.texture.as_image_copy(),
+                output.texture.as_image_copy(),
+                size,
+            );
+            context.encoder.copy_texture_to_texture(
+                input.texture.as_image_copy(),
+                self.history.texture.as_image_copy(),
+                size,
+            );
+            self.history_valid = true;
+            return;
+        }
+        let Some(velocity) = context.scene.velocity else {
+            // can't reproject without motion, the frame is passed through and the history
+            // restarts once velocity is back
+            context.encoder.copy_texture_to_texture(
+                input.texture.as_image_copy(),
+                output.texture.as_image_copy(),
+                size,
+            );
+            self.history_valid = false;
+            return;
+        };
+
+        let mut values = self.params.iter().map(|p| p.value).collect::<Vec<_>>();
+        values.resize(4, 0.0);
+        context
+            .queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&values));
+
+        let bind_group = context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    texture_entry(0, input),
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.params_buffer.as_entire_binding(),
+                    },
+                    texture_entry(3, context.scene.depth),
+                    texture_entry(7, velocity),
+                    texture_entry(8, &self.history),
+                ],
+                label: Some("taa_bind_group"),
+            });
+        draw_fullscreen(
+            context.encoder,
+            &self.pipeline,
+            &bind_group,
+            &output.view,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            "Taa pass",
+        );
+        context.encoder.copy_texture_to_texture(
+            output.texture.as_image_copy(),
+            self.history.texture.as_image_copy(),
+            size,
+        );
+    }
+}
@@ -0,0 +1,93 @@
+// Temporal anti-aliasing resolve. Appended to fullscreen.wgsl for vs_main. The scene
+// is drawn with a different sub-pixel jitter every frame, reprojecting the accumulated
+// history with the velocity buffer and blending a little of each new frame in averages
+// the jitter into anti-aliasing.
+
+struct TaaParams {
+    // share of the new frame in the result, lower is smoother but ghosts longer
+    current_weight: f32,
+};
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_linear: sampler;
+@group(0) @binding(2)
+var<uniform> taa: TaaParams;
+@group(0) @binding(3)
+var t_depth: texture_depth_2d;
+@group(0) @binding(7)
+var t_velocity: texture_2d<f32>;
+@group(0) @binding(8)
+var t_history: texture_2d<f32>;
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Blending hdr colors lets a few very bright samples dominate and flicker, weighting by
+// 1 / (1 + luma) brings them into 0..1 first and is undone at the end.
+fn compress(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + luma(color));
+}
+
+fn decompress(color: vec3<f32>) -> vec3<f32> {
+    return color / max(1.0 - luma(color), 0.0001);
+}
+
+// clamping in YCoCg keeps the hue of the history, clamping rgb per channel shifts it
+fn rgb_to_ycocg(color: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        0.25 * color.r + 0.5 * color.g + 0.25 * color.b,
+        0.5 * color.r - 0.5 * color.b,
+        -0.25 * color.r + 0.5 * color.g - 0.25 * color.b,
+    );
+}
+
+fn ycocg_to_rgb(color: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        color.x + color.y - color.z,
+        color.x + color.z,
+        color.x - color.y - color.z,
+    );
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let max_pixel = vec2<i32>(textureDimensions(t_color)) - 1;
+    let current = textureLoad(t_color, pixel, 0);
+
+    // the bounds of the 3x3 neighbourhood, and the velocity of the closest surface in it
+    // so the edges of moving objects reproject with the object instead of behind it
+    var color_min = vec3<f32>(1e9);
+    var color_max = vec3<f32>(-1e9);
+    var closest_depth = 1.0;
+    var closest_pixel = pixel;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), max_pixel);
+            let color = rgb_to_ycocg(compress(textureLoad(t_color, neighbour, 0).rgb));
+            color_min = min(color_min, color);
+            color_max = max(color_max, color);
+            let depth = textureLoad(t_depth, neighbour, 0);
+            if depth < closest_depth {
+                closest_depth = depth;
+                closest_pixel = neighbour;
+            }
+        }
+    }
+
+    let history_uv = in.tex_coords - textureLoad(t_velocity, closest_pixel, 0).xy;
+    // nothing to reproject from off screen
+    if any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0)) {
+        return current;
+    }
+
+    // history outside what the neighbourhood could produce is stale, disoccluded or lit
+    // differently now, clamping it into the bounds limits the ghosting
+    let history = textureSampleLevel(t_history, s_linear, history_uv, 0.0).rgb;
+    let clamped = clamp(rgb_to_ycocg(compress(history)), color_min, color_max);
+    let result = mix(ycocg_to_rgb(clamped), compress(current.rgb), taa.current_weight);
+    return vec4<f32>(max(decompress(result), vec3<f32>(0.0)), current.a);
+}
@@ -1,12 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use image::GenericImageView;
 
+use crate::compressed::CompressedImage;
+
+// What a texture holds, which picks its format and how its channels are packed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureRole {
+    // albedo and emissive, decoded from srgb to linear when sampled
+    Color,
+    // roughness, metallic, occlusion and masks, sampled exactly as stored
+    LinearData,
+    // tangent space x and y only, the shader rebuilds z
+    NormalMap,
+    // linear float radiance that can go past 1
+    Hdr,
+}
+
+impl TextureRole {
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureRole::Color => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureRole::LinearData => wgpu::TextureFormat::Rgba8Unorm,
+            TextureRole::NormalMap => wgpu::TextureFormat::Rg8Unorm,
+            TextureRole::Hdr => wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+}
+
+// Storage for float textures. Half is always filterable, full precision only is when
+// the device has FLOAT32_FILTERABLE.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FloatPrecision {
+    Half,
+    Full,
+}
+
+impl FloatPrecision {
+    // the most precise one the device can filter
+    pub fn filterable(device: &wgpu::Device) -> Self {
+        if device
+            .features()
+            .contains(wgpu::Features::FLOAT32_FILTERABLE)
+        {
+            FloatPrecision::Full
+        } else {
+            FloatPrecision::Half
+        }
+    }
+
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            FloatPrecision::Half => wgpu::TextureFormat::Rgba16Float,
+            FloatPrecision::Full => wgpu::TextureFormat::Rgba32Float,
+        }
+    }
+}
+
+// the image's texels laid out as `format` expects them
+pub fn pack_pixels(img: &image::DynamicImage, format: wgpu::TextureFormat) -> Vec<u8> {
+    match format {
+        wgpu::TextureFormat::Rg8Unorm => {
+            img.to_rgba8().pixels().flat_map(|p| [p[0], p[1]]).collect()
+        }
+        wgpu::TextureFormat::Rgba16Float => img
+            .to_rgba32f()
+            .into_raw()
+            .into_iter()
+            .flat_map(|c| half::f16::from_f32(c).to_le_bytes())
+            .collect(),
+        wgpu::TextureFormat::Rgba32Float => bytemuck::cast_slice(&img.to_rgba32f()).to_vec(),
+        _ => img.to_rgba8().into_raw(),
+    }
+}
+
+// Textures without mips in the file get theirs generated into encoder.
 pub fn load_texture(
     file_name: &str,
+    role: TextureRole,
+    sampler: Arc<wgpu::Sampler>,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    mipmaps: &mut MipmapGenerator,
 ) -> anyhow::Result<Texture> {
     let bytes = load_binary(file_name)?;
-    let texture = Texture::from_bytes(device, queue, &bytes, file_name)?;
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let texture = match extension.as_deref() {
+        Some("ktx2") => {
+            let image = CompressedImage::from_ktx2(&bytes)?;
+            Texture::from_compressed(device, queue, &image, Some(file_name), role, sampler)
+        }
+        Some("dds") => {
+            let image = CompressedImage::from_dds(&bytes, role == TextureRole::Color)?;
+            Texture::from_compressed(device, queue, &image, Some(file_name), role, sampler)
+        }
+        _ => Texture::from_bytes(
+            device, queue, encoder, mipmaps, &bytes, file_name, role, sampler,
+        )?,
+    };
     Ok(texture)
 }
 
@@ -15,31 +111,320 @@ pub fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     let bin = std::fs::read(path)?;
     Ok(bin)
 }
+// Renders each mip level from the one above it with a linear blit. Made once and
+// shared by everything that uploads textures, the pipeline for a format is made the
+// first time a texture of that format comes through.
+pub struct MipmapGenerator {
+    shader: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl MipmapGenerator {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blit shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("blit.wgsl").into()),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            shader,
+            sampler,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    // Records the blits for every array layer into encoder. Level 0 must be filled in
+    // by the time the encoder is submitted, queue writes made before that count, and
+    // the texture needs RENDER_ATTACHMENT usage.
+    pub fn generate(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        let mip_level_count = texture.mip_level_count();
+        if mip_level_count <= 1 {
+            return;
+        }
+        let format = texture.format();
+        if !self.pipelines.contains_key(&format) {
+            let pipeline = self.create_pipeline(device, format);
+            self.pipelines.insert(format, pipeline);
+        }
+        let pipeline = &self.pipelines[&format];
+        let bind_group_layout = pipeline.get_bind_group_layout(0);
+
+        for layer in 0..texture.depth_or_array_layers() {
+            let views = (0..mip_level_count)
+                .map(|mip| {
+                    texture.create_view(&wgpu::TextureViewDescriptor {
+                        label: Some("Mip view"),
+                        format: Some(format),
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        base_mip_level: mip,
+                        mip_level_count: Some(1),
+                        base_array_layer: layer,
+                        array_layer_count: Some(1),
+                        ..Default::default()
+                    })
+                })
+                .collect::<Vec<_>>();
+            for target_mip in 1..views.len() {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&views[target_mip - 1]),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                    label: None,
+                });
+
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mipmap pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &views[target_mip],
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mipmap pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[Some(format.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    // shared with every other texture using the same SamplerDesc
+    pub sampler: Arc<wgpu::Sampler>,
 }
 
 impl Texture {
+    #[allow(clippy::too_many_arguments)]
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut MipmapGenerator,
         bytes: &[u8],
         label: &str,
+        role: TextureRole,
+        sampler: Arc<wgpu::Sampler>,
     ) -> anyhow::Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        let texture = Self::from_image(device, queue, &img, Some(label))?;
+        let texture = Self::from_image(
+            device,
+            queue,
+            encoder,
+            mipmaps,
+            &img,
+            Some(label),
+            role,
+            sampler,
+        )?;
         Ok(texture)
     }
 
+    // 1x1 normal map pointing straight out of the surface, for materials without one
+    pub fn flat_normal(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        Self::solid_color(
+            device,
+            queue,
+            [128, 128, 255, 255],
+            "flat_normal",
+            TextureRole::NormalMap,
+            sampler,
+        )
+    }
+
+    // stands in for roughness, metallic and occlusion maps, multiplies by one
+    pub fn white_data(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        Self::solid_color(
+            device,
+            queue,
+            [255; 4],
+            "white_data",
+            TextureRole::LinearData,
+            sampler,
+        )
+    }
+
+    // 1x1 texture of one color, the texel is stored as given and decoded by role
+    pub fn solid_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        role: TextureRole,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let format = role.format();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let pixel = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+        let bytes_per_pixel = format
+            .block_copy_size(None)
+            .expect("texture roles only use color formats");
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &pack_pixels(&pixel.into(), format),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_pixel),
+                rows_per_image: Some(1),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    // Radiance .hdr or OpenEXR files, kept linear and unclamped for environment maps
+    // and light probes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_hdr(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut MipmapGenerator,
+        bytes: &[u8],
+        label: &str,
+        precision: FloatPrecision,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image_with_format(
+            device,
+            queue,
+            encoder,
+            mipmaps,
+            &img,
+            Some(label),
+            precision.format(),
+            sampler,
+        )
+    }
+
+    // the mips are generated into encoder, submit it before sampling the texture
+    #[allow(clippy::too_many_arguments)]
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut MipmapGenerator,
         img: &image::DynamicImage,
         label: Option<&str>,
+        role: TextureRole,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        Self::from_image_with_format(
+            device,
+            queue,
+            encoder,
+            mipmaps,
+            img,
+            label,
+            role.format(),
+            sampler,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mipmaps: &mut MipmapGenerator,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+        sampler: Arc<wgpu::Sampler>,
     ) -> anyhow::Result<Self> {
-        let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
         let size = wgpu::Extent3d {
@@ -47,14 +432,31 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        let pixels = pack_pixels(img, format);
+        let bytes_per_pixel = format
+            .block_copy_size(None)
+            .expect("textures are only created with color formats");
+        // mips are blitted with a linear sampler, so formats that can't be filtered get none
+        let filterable = format
+            .guaranteed_format_features(device.features())
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE);
+        let mip_level_count = if filterable {
+            size.max_mips(wgpu::TextureDimension::D2)
+        } else {
+            1
+        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format,
+            // render attachment so the mip chain can be rendered from the base level
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
         });
 
@@ -65,25 +467,17 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            &pixels,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
+                bytes_per_row: Some(bytes_per_pixel * dimensions.0),
                 rows_per_image: Some(dimensions.1),
             },
             size,
         );
+        mipmaps.generate(device, encoder, &texture);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
 
         Ok(Self {
             texture,
@@ -92,11 +486,104 @@ impl Texture {
         })
     }
 
+    // Uploads the mips baked into the file as they are. Without TEXTURE_COMPRESSION_BC
+    // every level is decoded on the cpu first and packed the way the role asks for.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: Option<&str>,
+        role: TextureRole,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let bc_supported = device
+            .features()
+            .contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+        // the file's own srgb flag wins over the role, it knows how the data was encoded
+        let format = match (bc_supported, role, image.srgb) {
+            (true, _, srgb) => image.format.wgpu_format(srgb),
+            (false, TextureRole::NormalMap, _) => wgpu::TextureFormat::Rg8Unorm,
+            (false, _, true) => wgpu::TextureFormat::Rgba8UnormSrgb,
+            (false, _, false) => wgpu::TextureFormat::Rgba8Unorm,
+        };
+        let size = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+        let mip_level_count = image.levels.len() as u32;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (mip_level, data) in image.levels.iter().enumerate() {
+            let mip_level = mip_level as u32;
+            let (width, height) = image.level_size(mip_level);
+            let mip_size = wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            };
+            let copy_texture = wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+            };
+            if bc_supported {
+                // copies of block compressed levels cover whole blocks, even past the edge
+                queue.write_texture(
+                    copy_texture,
+                    data,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(image.level_bytes_per_row(mip_level)),
+                        rows_per_image: Some(height.div_ceil(4)),
+                    },
+                    mip_size.physical_size(format),
+                );
+            } else {
+                let decoded =
+                    image::RgbaImage::from_raw(width, height, image.decode_level_rgba8(mip_level))
+                        .expect("decoded level matches its size");
+                let pixels = pack_pixels(&decoded.into(), format);
+                let bytes_per_pixel = format.block_copy_size(None).unwrap_or(4);
+                queue.write_texture(
+                    copy_texture,
+                    &pixels,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(bytes_per_pixel * width),
+                        rows_per_image: Some(height),
+                    },
+                    mip_size,
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+    // sample_count above 1 makes a multisampled target for msaa
     pub fn create_depth_texture(
         device: &wgpu::Device,
         surface_config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -109,7 +596,7 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -120,7 +607,7 @@ impl Texture {
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let sampler = Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -131,12 +618,167 @@ impl Texture {
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
             ..Default::default()
+        }));
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    // Screen sized color target that later passes sample from.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: surface_config.width.max(1),
+            height: surface_config.height.max(1),
+            depth_or_array_layers: 1,
+        };
+
+        // copies let post effects ping-pong through it, compute effects write it directly
+        // when the format allows storage
+        let mut usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST;
+        if format
+            .guaranteed_format_features(device.features())
+            .allowed_usages
+            .contains(wgpu::TextureUsages::STORAGE_BINDING)
+        {
+            usage |= wgpu::TextureUsages::STORAGE_BINDING;
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
         });
 
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        }));
+
         Self {
             texture,
             view,
             sampler,
         }
     }
+
+    // Color target for msaa, drawn to and resolved into a single sampled target. It
+    // can't be sampled or copied, so it gets a plain sampler only to fill the field.
+    pub fn create_multisampled_target(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: surface_config.width.max(1),
+            height: surface_config.height.max(1),
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = Arc::new(device.create_sampler(&wgpu::SamplerDescriptor::default()));
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba8(texels: &[[u8; 4]]) -> image::DynamicImage {
+        let raw = texels.concat();
+        image::RgbaImage::from_raw(texels.len() as u32, 1, raw)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn srgb_and_linear_rgba8_keep_the_stored_bytes() {
+        // the gpu decodes srgb when sampling, packing mustn't convert anything
+        let img = rgba8(&[[10, 128, 250, 255], [0, 1, 2, 3]]);
+        let srgb = pack_pixels(&img, TextureRole::Color.format());
+        let linear = pack_pixels(&img, TextureRole::LinearData.format());
+        assert_eq!(srgb, [10, 128, 250, 255, 0, 1, 2, 3]);
+        assert_eq!(linear, srgb);
+    }
+
+    #[test]
+    fn normal_maps_keep_only_x_and_y() {
+        let img = rgba8(&[[128, 128, 255, 255], [0, 255, 10, 20]]);
+        let packed = pack_pixels(&img, TextureRole::NormalMap.format());
+        assert_eq!(packed, [128, 128, 0, 255]);
+    }
+
+    #[test]
+    fn rgb_images_get_opaque_alpha() {
+        let img =
+            image::DynamicImage::from(image::RgbImage::from_raw(1, 1, vec![1, 2, 3]).unwrap());
+        assert_eq!(
+            pack_pixels(&img, TextureRole::LinearData.format()),
+            [1, 2, 3, 255]
+        );
+    }
+
+    #[test]
+    fn hdr_packs_half_floats_past_one() {
+        let img = image::DynamicImage::from(
+            image::Rgb32FImage::from_raw(1, 1, vec![4.0, 0.5, 0.0]).unwrap(),
+        );
+        let packed = pack_pixels(&img, TextureRole::Hdr.format());
+        let halves = packed
+            .chunks_exact(2)
+            .map(|c| half::f16::from_le_bytes([c[0], c[1]]).to_f32())
+            .collect::<Vec<_>>();
+        assert_eq!(halves, [4.0, 0.5, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn unorm_texels_become_zero_to_one_halves() {
+        let img = rgba8(&[[255, 0, 51, 255]]);
+        let packed = pack_pixels(&img, TextureRole::Hdr.format());
+        assert_eq!(packed.len(), 8);
+        assert_eq!(packed[..2], half::f16::ONE.to_le_bytes());
+        assert_eq!(packed[2..4], half::f16::ZERO.to_le_bytes());
+        assert_eq!(packed[4..6], half::f16::from_f32(0.2).to_le_bytes());
+    }
 }
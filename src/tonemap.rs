@@ -0,0 +1,385 @@
+use std::time::Instant;
+
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+
+// The scene is lit and drawn in this format, the tonemapper brings it down to the surface.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const HISTOGRAM_BIN_COUNT: u64 = 256;
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    Aces,
+    Reinhard,
+    AgX,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Aces => Self::Reinhard,
+            Self::Reinhard => Self::AgX,
+            Self::AgX => Self::Aces,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct AutoExposure {
+    // log2 luminance range the histogram covers, anything outside is clamped into it
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    // how quickly the exposure follows the scene, higher is faster
+    pub adaptation_speed: f32,
+    // stops added on top of the metered exposure
+    pub compensation: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            adaptation_speed: 1.5,
+            compensation: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Exposure {
+    // linear multiplier applied before tonemapping
+    Manual(f32),
+    // metered from a luminance histogram of the frame
+    Auto(AutoExposure),
+}
+
+impl Exposure {
+    // between a manual exposure of 1 and auto exposure with the default settings
+    pub fn toggle(self) -> Self {
+        match self {
+            Self::Manual(_) => Self::Auto(AutoExposure::default()),
+            Self::Auto(_) => Self::Manual(1.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TonemapSettings {
+    pub tonemapper: Tonemapper,
+    pub exposure: Exposure,
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::Aces,
+            exposure: Exposure::Manual(1.0),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    tonemapper: u32,
+    auto_exposure: u32,
+    manual_exposure: f32,
+    compensation: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    pixel_count: f32,
+}
+
+// Resolves the hdr target to the surface. Settings can be changed at any time and
+// apply from the next frame.
+pub struct TonemapPass {
+    pub settings: TonemapSettings,
+    pipeline: wgpu::RenderPipeline,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    exposure_params_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    // luminance the eye has adapted to, carried over between frames
+    luminance_buffer: wgpu::Buffer,
+    // these read the hdr target and are rebuilt with it on resize
+    bind_group: wgpu::BindGroup,
+    histogram_bind_group: wgpu::BindGroup,
+    average_bind_group: wgpu::BindGroup,
+    last_update: Instant,
+}
+
+impl TonemapPass {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_texture: &Texture,
+        output_format: wgpu::TextureFormat,
+        settings: TonemapSettings,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("tonemap.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(output_format.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let exposure_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Exposure shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("exposure.wgsl").into()),
+        });
+        let compute_pipeline = |entry_point, label| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &exposure_shader,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let histogram_pipeline = compute_pipeline("histogram_main", "Luminance histogram pipeline");
+        let average_pipeline = compute_pipeline("average_main", "Luminance average pipeline");
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemap uniform buffer"),
+            size: std::mem::size_of::<TonemapUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let exposure_params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure params buffer"),
+            size: std::mem::size_of::<ExposureParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance histogram buffer"),
+            size: HISTOGRAM_BIN_COUNT * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        // starts at middle gray so the first frames aren't over or under exposed
+        let luminance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Adapted luminance buffer"),
+            contents: bytemuck::cast_slice(&[0.18f32]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let average_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &average_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: luminance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("luminance_average_bind_group"),
+        });
+        let (bind_group, histogram_bind_group) = Self::create_hdr_bind_groups(
+            device,
+            &pipeline,
+            &histogram_pipeline,
+            hdr_texture,
+            &uniform_buffer,
+            &exposure_params_buffer,
+            &histogram_buffer,
+            &luminance_buffer,
+        );
+
+        Self {
+            settings,
+            pipeline,
+            histogram_pipeline,
+            average_pipeline,
+            uniform_buffer,
+            exposure_params_buffer,
+            histogram_buffer,
+            luminance_buffer,
+            bind_group,
+            histogram_bind_group,
+            average_bind_group,
+            last_update: Instant::now(),
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, hdr_texture: &Texture) {
+        (self.bind_group, self.histogram_bind_group) = Self::create_hdr_bind_groups(
+            device,
+            &self.pipeline,
+            &self.histogram_pipeline,
+            hdr_texture,
+            &self.uniform_buffer,
+            &self.exposure_params_buffer,
+            &self.histogram_buffer,
+            &self.luminance_buffer,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_hdr_bind_groups(
+        device: &wgpu::Device,
+        pipeline: &wgpu::RenderPipeline,
+        histogram_pipeline: &wgpu::ComputePipeline,
+        hdr_texture: &Texture,
+        uniform_buffer: &wgpu::Buffer,
+        exposure_params_buffer: &wgpu::Buffer,
+        histogram_buffer: &wgpu::Buffer,
+        luminance_buffer: &wgpu::Buffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: luminance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        });
+        let histogram_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &histogram_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("luminance_histogram_bind_group"),
+        });
+        (bind_group, histogram_bind_group)
+    }
+
+    // Meters the hdr target when auto exposure is on, then tonemaps it into output.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr_texture: &Texture,
+        output: &wgpu::TextureView,
+    ) {
+        let now = Instant::now();
+        let delta_time = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        let (auto_exposure, manual_exposure, compensation) = match self.settings.exposure {
+            Exposure::Manual(exposure) => (None, exposure, 0.0),
+            Exposure::Auto(auto) => (Some(auto), 1.0, auto.compensation),
+        };
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TonemapUniform {
+                tonemapper: match self.settings.tonemapper {
+                    Tonemapper::Aces => 0,
+                    Tonemapper::Reinhard => 1,
+                    Tonemapper::AgX => 2,
+                },
+                auto_exposure: auto_exposure.is_some() as u32,
+                manual_exposure,
+                compensation,
+            }]),
+        );
+
+        if let Some(auto) = auto_exposure {
+            let size = hdr_texture.texture.size();
+            queue.write_buffer(
+                &self.exposure_params_buffer,
+                0,
+                bytemuck::cast_slice(&[ExposureParams {
+                    min_log_luminance: auto.min_log_luminance,
+                    log_luminance_range: (auto.max_log_luminance - auto.min_log_luminance)
+                        .max(0.001),
+                    // frame rate independent exponential easing
+                    adaptation: 1.0 - (-delta_time * auto.adaptation_speed).exp(),
+                    pixel_count: (size.width * size.height) as f32,
+                }]),
+            );
+
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Auto exposure pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.histogram_pipeline);
+            pass.set_bind_group(0, &self.histogram_bind_group, &[]);
+            pass.dispatch_workgroups(
+                size.width.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+                size.height.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+                1,
+            );
+            pass.set_pipeline(&self.average_pipeline);
+            pass.set_bind_group(0, &self.average_bind_group, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
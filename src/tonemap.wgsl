@@ -0,0 +1,88 @@
+const TONEMAP_ACES: u32 = 0u;
+const TONEMAP_REINHARD: u32 = 1u;
+const TONEMAP_AGX: u32 = 2u;
+// middle gray that auto exposure maps the average luminance to
+const EXPOSURE_KEY: f32 = 0.18;
+
+struct TonemapUniform {
+    tonemapper: u32,
+    auto_exposure: u32,
+    manual_exposure: f32,
+    // exposure compensation in stops, only used with auto exposure
+    compensation: f32,
+};
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: TonemapUniform;
+@group(0) @binding(2)
+var<storage, read> adapted_luminance: f32;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+// Narkowicz's fit of the aces filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(x: vec3<f32>) -> vec3<f32> {
+    return x / (1.0 + x);
+}
+
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+// polynomial approximation of the agx base look, returns linear values for the srgb target
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var x = inset * color;
+    x = clamp(log2(max(x, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    x = (x - min_ev) / (max_ev - min_ev);
+    x = outset * agx_contrast(x);
+    return pow(clamp(x, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(2.2));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(t_hdr, vec2<u32>(in.clip_position.xy), 0).rgb;
+
+    var exposure = tonemap.manual_exposure;
+    if tonemap.auto_exposure != 0u {
+        exposure = EXPOSURE_KEY / max(adapted_luminance, 0.0001) * exp2(tonemap.compensation);
+    }
+    let color = hdr * exposure;
+
+    var mapped: vec3<f32>;
+    switch tonemap.tonemapper {
+        case TONEMAP_REINHARD: { mapped = reinhard(color); }
+        case TONEMAP_AGX: { mapped = agx(color); }
+        default: { mapped = aces(color); }
+    }
+    // the surface is srgb, encoding happens on write
+    return vec4<f32>(mapped, 1.0);
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+
+// What an attribute means. Each semantic always lives at the same shader location
+// so vertex structs and shaders agree without listing locations by hand.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VertexSemantic {
+    Position,
+    TexCoord,
+    Normal,
+    Tangent,
+    Color,
+    // columns of the per instance model matrix
+    ModelMatrix(u32),
+    // columns of the instance's model matrix in the last frame
+    PrevModelMatrix(u32),
+}
+
+impl VertexSemantic {
+    pub const fn shader_location(self) -> u32 {
+        match self {
+            VertexSemantic::Position => 0,
+            VertexSemantic::TexCoord => 1,
+            VertexSemantic::Normal => 2,
+            VertexSemantic::Tangent => 3,
+            VertexSemantic::Color => 4,
+            VertexSemantic::ModelMatrix(column) => 5 + column,
+            VertexSemantic::PrevModelMatrix(column) => 9 + column,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexAttributeDesc {
+    pub semantic: VertexSemantic,
+    pub format: wgpu::VertexFormat,
+    pub offset: wgpu::BufferAddress,
+}
+
+impl VertexAttributeDesc {
+    pub const fn new(semantic: VertexSemantic, format: wgpu::VertexFormat, offset: usize) -> Self {
+        Self {
+            semantic,
+            format,
+            offset: offset as wgpu::BufferAddress,
+        }
+    }
+}
+
+// Implementors only declare their attributes, the wgpu layout is derived from them.
+pub trait Vertex: bytemuck::Pod {
+    const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::Vertex;
+    const ATTRIBUTES: &'static [VertexAttributeDesc];
+
+    fn layout() -> VertexLayout {
+        VertexLayout::new(
+            std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            Self::STEP_MODE,
+            Self::ATTRIBUTES.to_vec(),
+        )
+    }
+}
+
+// Owned description of one vertex buffer. Also used as the key for pipelines built for it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    pub array_stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<VertexAttributeDesc>,
+    wgpu_attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl VertexLayout {
+    pub fn new(
+        array_stride: wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode,
+        attributes: Vec<VertexAttributeDesc>,
+    ) -> Self {
+        let wgpu_attributes = attributes
+            .iter()
+            .map(|a| wgpu::VertexAttribute {
+                format: a.format,
+                offset: a.offset,
+                shader_location: a.semantic.shader_location(),
+            })
+            .collect();
+        Self {
+            array_stride,
+            step_mode,
+            attributes,
+            wgpu_attributes,
+        }
+    }
+
+    pub fn buffer_layout(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.array_stride,
+            step_mode: self.step_mode,
+            attributes: &self.wgpu_attributes,
+        }
+    }
+
+    // Drops the attributes the entry point doesn't read, and errors if it reads
+    // something none of the layouts provide or provide in a different shape.
+    pub fn match_inputs(
+        layouts: &[VertexLayout],
+        inputs: &EntryInputs,
+    ) -> anyhow::Result<Vec<VertexLayout>> {
+        for input in &inputs.inputs {
+            let attribute = layouts
+                .iter()
+                .flat_map(|l| l.attributes.iter())
+                .find(|a| a.semantic.shader_location() == input.location)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} reads location {} but no vertex buffer provides it",
+                        inputs.entry_point,
+                        input.location
+                    )
+                })?;
+            let (kind, components) = format_shape(attribute.format);
+            if kind != input.kind || components != input.components {
+                bail!(
+                    "{} reads location {} as {:?}x{} but {:?} provides {:?}",
+                    inputs.entry_point,
+                    input.location,
+                    input.kind,
+                    input.components,
+                    attribute.semantic,
+                    attribute.format
+                );
+            }
+        }
+        Ok(layouts
+            .iter()
+            .map(|l| {
+                VertexLayout::new(
+                    l.array_stride,
+                    l.step_mode,
+                    l.attributes
+                        .iter()
+                        .filter(|a| inputs.reads(a.semantic.shader_location()))
+                        .copied()
+                        .collect(),
+                )
+            })
+            .collect())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderInput {
+    pub location: u32,
+    pub kind: naga::ScalarKind,
+    pub components: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct EntryInputs {
+    pub entry_point: String,
+    pub inputs: Vec<ShaderInput>,
+}
+
+impl EntryInputs {
+    pub fn reads(&self, location: u32) -> bool {
+        self.inputs.iter().any(|i| i.location == location)
+    }
+}
+
+// A wgsl module along with the vertex inputs of each of its vertex entry points.
+pub struct ReflectedShader {
+    pub module: wgpu::ShaderModule,
+    pub vertex_entries: HashMap<String, EntryInputs>,
+}
+
+impl ReflectedShader {
+    pub fn new(device: &wgpu::Device, source: &str, label: &str) -> anyhow::Result<Self> {
+        let vertex_entries = reflect_vertex_entries(source, label)?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        Ok(Self {
+            module,
+            vertex_entries,
+        })
+    }
+
+    // The vertex entry point that reads the most attributes the layouts can provide,
+    // so a colored mesh picks the entry that uses color over one that ignores it.
+    pub fn best_vertex_entry(
+        &self,
+        layouts: &[VertexLayout],
+    ) -> anyhow::Result<(&str, Vec<VertexLayout>)> {
+        best_vertex_entry(&self.vertex_entries, layouts)
+    }
+}
+
+// the inputs of every vertex entry point in a wgsl source, by entry point name
+fn reflect_vertex_entries(
+    source: &str,
+    label: &str,
+) -> anyhow::Result<HashMap<String, EntryInputs>> {
+    let naga_module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| anyhow!(e.emit_to_string_with_path(source, label)))?;
+    Ok(naga_module
+        .entry_points
+        .iter()
+        .filter(|e| e.stage == naga::ShaderStage::Vertex)
+        .map(|e| {
+            let mut inputs = Vec::new();
+            for argument in &e.function.arguments {
+                collect_inputs(
+                    &naga_module,
+                    argument.binding.as_ref(),
+                    argument.ty,
+                    &mut inputs,
+                );
+            }
+            let entry = EntryInputs {
+                entry_point: e.name.clone(),
+                inputs,
+            };
+            (e.name.clone(), entry)
+        })
+        .collect())
+}
+
+fn best_vertex_entry<'a>(
+    vertex_entries: &'a HashMap<String, EntryInputs>,
+    layouts: &[VertexLayout],
+) -> anyhow::Result<(&'a str, Vec<VertexLayout>)> {
+    vertex_entries
+        .values()
+        .filter_map(|entry| {
+            VertexLayout::match_inputs(layouts, entry)
+                .ok()
+                .map(|matched| (entry, matched))
+        })
+        .max_by_key(|(entry, _)| {
+            (
+                entry.inputs.len(),
+                std::cmp::Reverse(entry.entry_point.clone()),
+            )
+        })
+        .map(|(entry, matched)| (entry.entry_point.as_str(), matched))
+        .ok_or_else(|| anyhow!("no vertex entry point matches {:?}", layouts))
+}
+
+fn collect_inputs(
+    module: &naga::Module,
+    binding: Option<&naga::Binding>,
+    ty: naga::Handle<naga::Type>,
+    out: &mut Vec<ShaderInput>,
+) {
+    match binding {
+        Some(naga::Binding::Location { location, .. }) => {
+            let (kind, components) = match module.types[ty].inner {
+                naga::TypeInner::Scalar(scalar) => (scalar.kind, 1),
+                naga::TypeInner::Vector { size, scalar } => (scalar.kind, size as u32),
+                _ => return,
+            };
+            out.push(ShaderInput {
+                location: *location,
+                kind,
+                components,
+            });
+        }
+        Some(naga::Binding::BuiltIn(_)) => {}
+        None => {
+            if let naga::TypeInner::Struct { members, .. } = &module.types[ty].inner {
+                for member in members {
+                    collect_inputs(module, member.binding.as_ref(), member.ty, out);
+                }
+            }
+        }
+    }
+}
+
+// the scalar kind and component count a vertex format shows up as in a shader
+fn format_shape(format: wgpu::VertexFormat) -> (naga::ScalarKind, u32) {
+    use naga::ScalarKind::{Float, Sint, Uint};
+    use wgpu::VertexFormat as F;
+    match format {
+        F::Uint8x2 | F::Uint16x2 | F::Uint32x2 => (Uint, 2),
+        F::Uint8x4 | F::Uint16x4 | F::Uint32x4 => (Uint, 4),
+        F::Uint32 => (Uint, 1),
+        F::Uint32x3 => (Uint, 3),
+        F::Sint8x2 | F::Sint16x2 | F::Sint32x2 => (Sint, 2),
+        F::Sint8x4 | F::Sint16x4 | F::Sint32x4 => (Sint, 4),
+        F::Sint32 => (Sint, 1),
+        F::Sint32x3 => (Sint, 3),
+        F::Float32 | F::Float64 => (Float, 1),
+        F::Unorm8x2 | F::Snorm8x2 | F::Unorm16x2 | F::Snorm16x2 | F::Float16x2 => (Float, 2),
+        F::Float32x2 | F::Float64x2 => (Float, 2),
+        F::Float32x3 | F::Float64x3 => (Float, 3),
+        F::Unorm8x4 | F::Snorm8x4 | F::Unorm16x4 | F::Snorm16x4 | F::Float16x4 => (Float, 4),
+        F::Float32x4 | F::Float64x4 | F::Unorm10_10_10_2 => (Float, 4),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vs_lit reads position, normal and uvs, vs_colored position and color
+    const SHADER: &str = "
+        struct LitInput {
+            @location(0) position: vec3<f32>,
+            @location(1) tex_coords: vec2<f32>,
+            @location(2) normal: vec3<f32>,
+        };
+
+        @vertex
+        fn vs_lit(in: LitInput, @builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+            return vec4<f32>(in.position + in.normal, in.tex_coords.x);
+        }
+
+        @vertex
+        fn vs_colored(
+            @location(0) position: vec3<f32>,
+            @location(4) color: vec4<f32>,
+        ) -> @builtin(position) vec4<f32> {
+            return vec4<f32>(position, color.a);
+        }
+    ";
+
+    fn layout(attributes: &[(VertexSemantic, wgpu::VertexFormat)]) -> VertexLayout {
+        let mut offset = 0;
+        let attributes = attributes
+            .iter()
+            .map(|&(semantic, format)| {
+                let attribute = VertexAttributeDesc::new(semantic, format, offset);
+                offset += format.size() as usize;
+                attribute
+            })
+            .collect();
+        VertexLayout::new(
+            offset as wgpu::BufferAddress,
+            wgpu::VertexStepMode::Vertex,
+            attributes,
+        )
+    }
+
+    fn lit_layout() -> VertexLayout {
+        layout(&[
+            (VertexSemantic::Position, wgpu::VertexFormat::Float32x3),
+            (VertexSemantic::TexCoord, wgpu::VertexFormat::Float32x2),
+            (VertexSemantic::Normal, wgpu::VertexFormat::Float32x3),
+            (VertexSemantic::Tangent, wgpu::VertexFormat::Float32x4),
+        ])
+    }
+
+    fn entries() -> HashMap<String, EntryInputs> {
+        reflect_vertex_entries(SHADER, "test shader").unwrap()
+    }
+
+    #[test]
+    fn reflects_locations_through_structs_and_skips_builtins() {
+        let entries = entries();
+        let mut lit = entries["vs_lit"]
+            .inputs
+            .iter()
+            .map(|i| (i.location, i.components))
+            .collect::<Vec<_>>();
+        lit.sort();
+        assert_eq!(lit, [(0, 3), (1, 2), (2, 3)]);
+        assert!(entries["vs_colored"].reads(4));
+    }
+
+    #[test]
+    fn matching_layout_drops_unread_attributes() {
+        let entries = entries();
+        let matched = VertexLayout::match_inputs(&[lit_layout()], &entries["vs_lit"]).unwrap();
+        let semantics = matched[0]
+            .attributes
+            .iter()
+            .map(|a| a.semantic)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            semantics,
+            [
+                VertexSemantic::Position,
+                VertexSemantic::TexCoord,
+                VertexSemantic::Normal
+            ]
+        );
+        // the offsets and stride still describe the whole buffer
+        assert_eq!(matched[0].array_stride, lit_layout().array_stride);
+        assert_eq!(matched[0].attributes[2].offset, 20);
+    }
+
+    #[test]
+    fn missing_semantic_is_an_error() {
+        let entries = entries();
+        let error = VertexLayout::match_inputs(&[lit_layout()], &entries["vs_colored"])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("location 4"), "{error}");
+    }
+
+    #[test]
+    fn format_shape_mismatch_is_an_error() {
+        let entries = entries();
+        let integer_uvs = layout(&[
+            (VertexSemantic::Position, wgpu::VertexFormat::Float32x3),
+            (VertexSemantic::TexCoord, wgpu::VertexFormat::Uint32x2),
+            (VertexSemantic::Normal, wgpu::VertexFormat::Float32x3),
+        ]);
+        assert!(VertexLayout::match_inputs(&[integer_uvs], &entries["vs_lit"]).is_err());
+        let short_normals = layout(&[
+            (VertexSemantic::Position, wgpu::VertexFormat::Float32x3),
+            (VertexSemantic::TexCoord, wgpu::VertexFormat::Float32x2),
+            (VertexSemantic::Normal, wgpu::VertexFormat::Float32x2),
+        ]);
+        assert!(VertexLayout::match_inputs(&[short_normals], &entries["vs_lit"]).is_err());
+    }
+
+    #[test]
+    fn format_shape_treats_normalized_formats_as_floats() {
+        assert_eq!(
+            format_shape(wgpu::VertexFormat::Unorm8x4),
+            (naga::ScalarKind::Float, 4)
+        );
+        assert_eq!(
+            format_shape(wgpu::VertexFormat::Sint16x2),
+            (naga::ScalarKind::Sint, 2)
+        );
+        assert_eq!(
+            format_shape(wgpu::VertexFormat::Uint32),
+            (naga::ScalarKind::Uint, 1)
+        );
+    }
+
+    #[test]
+    fn best_entry_reads_the_most_attributes() {
+        let entries = entries();
+        let (entry_point, _) = best_vertex_entry(&entries, &[lit_layout()]).unwrap();
+        assert_eq!(entry_point, "vs_lit");
+
+        let colored = layout(&[
+            (VertexSemantic::Position, wgpu::VertexFormat::Float32x3),
+            (VertexSemantic::Color, wgpu::VertexFormat::Float32x4),
+        ]);
+        let (entry_point, matched) = best_vertex_entry(&entries, &[colored]).unwrap();
+        assert_eq!(entry_point, "vs_colored");
+        assert_eq!(matched[0].attributes.len(), 2);
+
+        let positions_only = layout(&[(VertexSemantic::Position, wgpu::VertexFormat::Float32x3)]);
+        assert!(best_vertex_entry(&entries, &[positions_only]).is_err());
+    }
+}
@@ -0,0 +1,24 @@
+// Darkens the corners of the image. Appended to fullscreen.wgsl and run through
+// post_process::ShaderEffect.
+
+struct VignetteParams {
+    // how dark the corners get, 0 leaves the image alone
+    strength: f32,
+    // distance from the center the darkening starts at, 1 is the middle of the edges
+    radius: f32,
+};
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_color: sampler;
+@group(0) @binding(2)
+var<uniform> params: VignetteParams;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_color, s_color, in.tex_coords);
+    let distance = length(in.tex_coords * 2.0 - 1.0);
+    let darkening = smoothstep(params.radius, sqrt(2.0), distance) * params.strength;
+    return vec4<f32>(color.rgb * (1.0 - darkening), color.a);
+}
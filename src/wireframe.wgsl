@@ -0,0 +1,76 @@
+// Barycentric wireframe, for adapters without line polygon mode. Meshes are drawn
+// without their index buffer and every vertex fetches its position through the
+// indices itself, so the corners of each triangle know which one they are and the
+// fragments between them how far they are from an edge.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// where the position is in the mesh's vertices, counted in floats
+struct WireframeParams {
+    stride: u32,
+    position_offset: u32,
+};
+
+@group(0) @binding(0)
+var<storage, read> indices: array<u32>;
+@group(0) @binding(1)
+var<storage, read> vertices: array<f32>;
+@group(0) @binding(2)
+var<uniform> params: WireframeParams;
+
+const WIREFRAME_COLOR: vec3<f32> = vec3<f32>(0.9);
+// in pixels
+const LINE_WIDTH: f32 = 1.0;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) barycentric: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let base = indices[vertex_index] * params.stride + params.position_offset;
+    let position = vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    out.barycentric = vec3<f32>(0.0);
+    out.barycentric[vertex_index % 3u] = 1.0;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // distance to each edge in pixels
+    let distance = in.barycentric / fwidth(in.barycentric);
+    if min(min(distance.x, distance.y), distance.z) > LINE_WIDTH {
+        discard;
+    }
+    return vec4<f32>(WIREFRAME_COLOR, 1.0);
+}